@@ -1,10 +1,18 @@
 //! bech32k: KeyURI-specific bech32 encoding/decoding support
 
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
 /// Minimum length of a bech32k string
 pub const MIN_LENGTH: usize = 8;
 
-/// Maximum length of a bech32k string
-pub const MAX_LENGTH: usize = 90;
+/// Maximum length of a bech32k string. This is longer than bech32's limit
+/// of 90 so SHA-512 fingerprints fit (beyond 90 characters the checksum no
+/// longer guarantees detecting every 4-character error, but still detects
+/// errors with overwhelming probability)
+pub const MAX_LENGTH: usize = 160;
 
 /// bech32k encoding character set (same as bech32)
 const CHARSET: [char; 32] = [
@@ -32,7 +40,7 @@ const GENERATOR_COEFFICIENTS: [u32; 5] = [
 ];
 
 /// bech32k-specific separator between URI prefix and encoded data
-const SEPARATOR: char = ';';
+pub const SEPARATOR: char = ';';
 
 /// Encode a Bech32k string from a string prefix and binary data
 pub fn encode(prefix: &str, data: &[u8]) -> String {
@@ -55,6 +63,10 @@ pub fn decode(encoded: &str) -> Result<(String, Vec<u8>), Error> {
         return Err(Error::SeparatorMissing);
     }
 
+    if encoded.matches(SEPARATOR).count() > 1 {
+        return Err(Error::MultipleSeparators);
+    }
+
     match len {
         MIN_LENGTH..=MAX_LENGTH => (),
         _ => return Err(Error::LengthInvalid),
@@ -267,46 +279,65 @@ impl Base32Converter {
 }
 
 /// Error types for Bech32 encoding / decoding
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Error {
     /// KeyURI is missing the ';' character
-    #[fail(display = "missing separator character: \";\"")]
     SeparatorMissing,
 
+    /// KeyURI contains more than one ';' character
+    MultipleSeparators,
+
     /// Checksum for the Bech32 string does not match expected value
-    #[fail(display = "checksum mismatch")]
     ChecksumInvalid,
 
     /// String is too short or long
-    #[fail(display = "invalid KeyURI length (min 8, max 90)")]
     LengthInvalid,
 
     /// Character is not valid
-    #[fail(display = "character invalid ({})'", byte)]
     CharInvalid {
         /// Invalid byte
         byte: u8,
     },
 
     /// Data is not valid
-    #[fail(display = "data invalid ({})", byte)]
     DataInvalid {
         /// Invalid byte
         byte: u8,
     },
 
     /// Padding missing/invalid
-    #[fail(display = "padding invalid")]
     PaddingInvalid,
 
     /// Mixed-case string
-    #[fail(display = "string contains mixed-case")]
     CaseInvalid,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SeparatorMissing => write!(f, "missing separator character: \";\""),
+            Error::MultipleSeparators => write!(f, "more than one separator character: \";\""),
+            Error::ChecksumInvalid => write!(f, "checksum mismatch"),
+            Error::LengthInvalid => write!(
+                f,
+                "invalid KeyURI length (min {}, max {})",
+                MIN_LENGTH, MAX_LENGTH
+            ),
+            Error::CharInvalid { byte } => write!(f, "character invalid ({})'", byte),
+            Error::DataInvalid { byte } => write!(f, "data invalid ({})", byte),
+            Error::PaddingInvalid => write!(f, "padding invalid"),
+            Error::CaseInvalid => write!(f, "string contains mixed-case"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
 #[cfg(test)]
 mod tests {
-    use super::{decode, encode};
+    use super::{decode, encode, Error};
+    use alloc::format;
 
     const EXAMPLE_PREFIX: &str = "example.prefix";
     const EXAMPLE_DATA: &[u8] = &[0, 255, 1, 2, 3, 42, 101];
@@ -321,4 +352,10 @@ mod tests {
         assert_eq!(prefix, EXAMPLE_PREFIX);
         assert_eq!(data, EXAMPLE_DATA);
     }
+
+    #[test]
+    fn test_multiple_separators() {
+        let encoded = format!("{};extra", EXAMPLE_ENCODED);
+        assert_eq!(decode(&encoded), Err(Error::MultipleSeparators));
+    }
 }
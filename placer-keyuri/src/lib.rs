@@ -0,0 +1,179 @@
+//! Keys-as-URIs (with Bech32 binary data/checksums)
+//!
+//! This crate is `no_std` (but requires `alloc`) so KeyURIs can be handled
+//! by minimal pack verifiers. The `std` feature (on by default) adds
+//! `std::error::Error` support.
+//!
+//! TODO: use upstream <https://github.com/miscreant/keyuri>
+
+#![no_std]
+#![deny(missing_docs, unsafe_code, unused_import_braces, unused_qualifications)]
+
+extern crate alloc;
+
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256, Sha512};
+
+pub mod bech32k;
+
+/// Encryption `KeyURI` prefix (AES-256-SIV secret key)
+pub const ENCRYPTION_KEY_PREFIX: &str = "secret.key:aes256siv+hks256";
+
+/// Signing `KeyURI` prefix (secret key)
+pub const SIGNING_KEY_PREFIX: &str = "secret.key:ed25519";
+
+/// Verify `KeyURI` prefix (public key)
+pub const VERIFY_KEY_PREFIX: &str = "public.key:ed25519";
+
+/// Signing `KeyURI` prefix for Ed25519ph (pre-hashed) secret keys
+pub const SIGNING_KEY_PREHASH_PREFIX: &str = "secret.key:ed25519ph";
+
+/// Verify `KeyURI` prefix for Ed25519ph (pre-hashed) public keys
+pub const VERIFY_KEY_PREHASH_PREFIX: &str = "public.key:ed25519ph";
+
+/// Key fingerprint `KeyURI` prefix (SHA-256)
+pub const FINGERPRINT_PREFIX: &str = "public.fingerprint:sha-256";
+
+/// Key fingerprint `KeyURI` prefix (SHA-512)
+pub const FINGERPRINT_SHA512_PREFIX: &str = "public.fingerprint:sha-512";
+
+/// Key fingerprint `KeyURI` prefix (BLAKE3)
+pub const FINGERPRINT_BLAKE3_PREFIX: &str = "public.fingerprint:blake3";
+
+/// Digest algorithms for `KeyURI` fingerprints. The algorithm is part of a
+/// fingerprint's prefix, so the same key has a different fingerprint under
+/// each algorithm.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FingerprintAlgorithm {
+    /// SHA-256 (the default)
+    Sha256,
+
+    /// SHA-512
+    Sha512,
+
+    /// BLAKE3 (256-bit output)
+    Blake3,
+}
+
+impl FingerprintAlgorithm {
+    /// All supported fingerprint algorithms
+    pub const ALL: &'static [FingerprintAlgorithm] = &[
+        FingerprintAlgorithm::Sha256,
+        FingerprintAlgorithm::Sha512,
+        FingerprintAlgorithm::Blake3,
+    ];
+
+    /// Get the fingerprint algorithm for a `KeyURI` prefix
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|algorithm| algorithm.prefix().eq_ignore_ascii_case(prefix))
+            .cloned()
+    }
+
+    /// Get the algorithm a fingerprint was computed with, from its prefix
+    pub fn of(fingerprint: &str) -> Option<Self> {
+        let fingerprint = fingerprint.trim();
+        let prefix_len = fingerprint.find(bech32k::SEPARATOR)?;
+        Self::from_prefix(&fingerprint[..prefix_len])
+    }
+
+    /// Get the `KeyURI` prefix for fingerprints computed with this algorithm
+    pub fn prefix(self) -> &'static str {
+        match self {
+            FingerprintAlgorithm::Sha256 => FINGERPRINT_PREFIX,
+            FingerprintAlgorithm::Sha512 => FINGERPRINT_SHA512_PREFIX,
+            FingerprintAlgorithm::Blake3 => FINGERPRINT_BLAKE3_PREFIX,
+        }
+    }
+
+    /// Compute a digest of the given data with this algorithm
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            FingerprintAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            FingerprintAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+            FingerprintAlgorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+impl Default for FingerprintAlgorithm {
+    fn default() -> Self {
+        FingerprintAlgorithm::Sha256
+    }
+}
+
+/// Encode a `KeyURI` fingerprint of the given string (which should be a `KeyURI`)
+/// using the default (SHA-256) fingerprint algorithm
+pub fn fingerprint(keyuri: &str) -> String {
+    fingerprint_with(FingerprintAlgorithm::default(), keyuri)
+}
+
+/// Encode a `KeyURI` fingerprint of the given string (which should be a `KeyURI`)
+/// using the given fingerprint algorithm
+pub fn fingerprint_with(algorithm: FingerprintAlgorithm, keyuri: &str) -> String {
+    bech32k::encode(algorithm.prefix(), &algorithm.digest(keyuri.as_bytes()))
+}
+
+/// Does the given fingerprint (computed with any supported algorithm) match
+/// the given `KeyURI`? Fingerprints with unknown algorithms never match.
+pub fn fingerprint_matches(keyuri: &str, fingerprint: &str) -> bool {
+    match FingerprintAlgorithm::of(fingerprint) {
+        Some(algorithm) => {
+            normalize_fingerprint(&fingerprint_with(algorithm, keyuri))
+                == normalize_fingerprint(fingerprint)
+        }
+        None => false,
+    }
+}
+
+/// Normalize a `KeyURI` fingerprint for comparison. bech32k is
+/// case-insensitive, so fingerprints which differ only in case (or
+/// surrounding whitespace) refer to the same key.
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bech32k, fingerprint, fingerprint_matches, fingerprint_with, FingerprintAlgorithm,
+        VERIFY_KEY_PREFIX,
+    };
+
+    #[test]
+    fn test_fingerprint_roundtrip() {
+        let keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]);
+        let other_keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[2u8; 32]);
+
+        for &(algorithm, digest_len) in &[
+            (FingerprintAlgorithm::Sha256, 32),
+            (FingerprintAlgorithm::Sha512, 64),
+            (FingerprintAlgorithm::Blake3, 32),
+        ] {
+            let encoded = fingerprint_with(algorithm, &keyuri);
+            let (prefix, digest) = bech32k::decode(&encoded).unwrap();
+
+            assert_eq!(prefix, algorithm.prefix());
+            assert_eq!(digest.len(), digest_len);
+            assert_eq!(FingerprintAlgorithm::of(&encoded), Some(algorithm));
+
+            assert!(fingerprint_matches(&keyuri, &encoded));
+            assert!(fingerprint_matches(&keyuri, &encoded.to_ascii_uppercase()));
+            assert!(!fingerprint_matches(&other_keyuri, &encoded));
+        }
+
+        assert_eq!(
+            fingerprint(&keyuri),
+            fingerprint_with(FingerprintAlgorithm::Sha256, &keyuri)
+        );
+        assert!(!fingerprint_matches(
+            &keyuri,
+            "public.fingerprint:md5;qqqqqq"
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+//! Benchmarks for pack encryption, decryption, and keyring lookups
+//!
+//! All keys, UUIDs, and dates are fixed so results are comparable across runs.
+//! Run with `cargo bench` in the `placer-pack` directory.
+
+use chrono::{TimeZone, Utc};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use placer_pack::{
+    crypto::{Encryptor, Signer, ENCRYPTION_KEY_SIZE, SIGNING_KEY_SIZE},
+    keyuri::{bech32k, ENCRYPTION_KEY_PREFIX, SIGNING_KEY_PREFIX},
+    pack::Uuid,
+    Keyrings, Pack, PackFile,
+};
+use std::fs;
+use std::path::PathBuf;
+
+/// Fixed Ed25519 signing key seed
+const SIGNING_KEY: [u8; SIGNING_KEY_SIZE] = [0x5a; SIGNING_KEY_SIZE];
+
+/// Fixed encryption key
+const ENCRYPTION_KEY: [u8; ENCRYPTION_KEY_SIZE] = [0xa5; ENCRYPTION_KEY_SIZE];
+
+/// Fixed pack UUID (used as the encryption key's HKDF salt)
+const PACK_UUID: [u8; 16] = [0x42; 16];
+
+/// Sizes of single-file packs to benchmark
+const FILE_SIZES: &[usize] = &[1024, 64 * 1024, 512 * 1024];
+
+/// Numbers of 4 KiB files in multi-file packs to benchmark
+const FILE_COUNTS: &[usize] = &[1, 16, 128];
+
+/// Size of each file in multi-file packs
+const MULTI_FILE_SIZE: usize = 4096;
+
+/// Create a pack containing `count` files of `size` bytes each
+fn example_pack(count: usize, size: usize) -> Pack {
+    Pack {
+        uuid: Uuid::from_bytes(PACK_UUID),
+        date: Utc.timestamp(1_500_000_000, 0),
+        fingerprints: None,
+        base_uuid: None,
+        deletions: vec![],
+        description: None,
+        channel: None,
+        files: (0..count)
+            .map(|i| PackFile {
+                filename: format!("/etc/bench/{}.conf", i),
+                content_type: String::new(),
+                modified_at: None,
+                body: vec![(i % 256) as u8; size],
+            })
+            .collect(),
+        chunked_files: vec![],
+        limits: Default::default(),
+    }
+}
+
+/// Encryption key in KeyURI format
+fn encryption_keyuri() -> String {
+    bech32k::encode(ENCRYPTION_KEY_PREFIX, &ENCRYPTION_KEY)
+}
+
+/// Encrypt and sign a pack with the fixed keys
+fn seal(pack: Pack, signer: &Signer) -> Vec<u8> {
+    let mut encryptor = Encryptor::from_keyuri(&encryption_keyuri(), &PACK_UUID).unwrap();
+    pack.encrypt_and_sign(&mut encryptor, signer).unwrap()
+}
+
+/// Load keyrings containing the fixed keys (written to a temporary file,
+/// since that's how keyrings are loaded)
+fn keyrings() -> Keyrings {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "placer-pack-bench-keyring-{}.toml",
+        std::process::id()
+    ));
+
+    let config = format!(
+        "[signing]\ndefault = \"{}\"\n\n[encryption]\ndefault = \"{}\"\n",
+        bech32k::encode(SIGNING_KEY_PREFIX, &SIGNING_KEY),
+        encryption_keyuri()
+    );
+
+    fs::write(&path, config).unwrap();
+    let keyrings = Keyrings::load(&path);
+    fs::remove_file(&path).unwrap();
+    keyrings.unwrap()
+}
+
+/// Packs to benchmark, by label and total plaintext size
+fn cases() -> Vec<(String, usize, usize)> {
+    let mut cases = vec![];
+
+    for &size in FILE_SIZES {
+        cases.push((format!("1x{}B", size), 1, size));
+    }
+
+    for &count in FILE_COUNTS {
+        cases.push((
+            format!("{}x{}B", count, MULTI_FILE_SIZE),
+            count,
+            MULTI_FILE_SIZE,
+        ));
+    }
+
+    cases
+}
+
+fn bench_encrypt(c: &mut Criterion) {
+    let signer = Signer::from_bytes(&SIGNING_KEY).unwrap();
+    let mut group = c.benchmark_group("encrypt_and_sign");
+
+    for (label, count, size) in cases() {
+        group.throughput(Throughput::Bytes((count * size) as u64));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.iter_batched(
+                || example_pack(count, size),
+                |pack| seal(pack, &signer),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_decrypt(c: &mut Criterion) {
+    let signer = Signer::from_bytes(&SIGNING_KEY).unwrap();
+    let keyrings = keyrings();
+    let mut group = c.benchmark_group("verify_and_decrypt");
+
+    for (label, count, size) in cases() {
+        let bytes = seal(example_pack(count, size), &signer);
+
+        group.throughput(Throughput::Bytes((count * size) as u64));
+        group.bench_function(BenchmarkId::from_parameter(label), |b| {
+            b.iter(|| Pack::verify_and_decrypt_with(black_box(&bytes), &keyrings).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_key_lookup(c: &mut Criterion) {
+    let signer = Signer::from_bytes(&SIGNING_KEY).unwrap();
+    let keyrings = keyrings();
+    let bytes = seal(example_pack(1, 1024), &signer);
+    let fingerprints = Pack::inspect(&bytes).unwrap().recipients.remove(0);
+    let uuid = Uuid::from_bytes(PACK_UUID);
+
+    // Includes HKDF derivation of the pack's encryption key
+    c.bench_function("keyrings/key_lookup", |b| {
+        b.iter(|| {
+            keyrings
+                .key_lookup(black_box(&fingerprints), black_box(&uuid))
+                .unwrap()
+        })
+    });
+
+    c.bench_function("encryptor/from_keyuri", |b| {
+        let keyuri = encryption_keyuri();
+        b.iter(|| Encryptor::from_keyuri(black_box(&keyuri), black_box(&PACK_UUID)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_encrypt, bench_decrypt, bench_key_lookup);
+criterion_main!(benches);
@@ -6,21 +6,111 @@
 #[macro_use]
 extern crate slog;
 
-use slog::{Drain, Logger};
-use std::fs::File;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use chrono::{DateTime, TimeZone, Utc};
+use clear_on_drop::clear::Clear;
+use filetime::FileTime;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use slog::{Drain, Level, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::str;
+use std::sync::mpsc;
+use std::time::Duration;
 use structopt::StructOpt;
 use uuid::Uuid;
 
+use placer_pack::crypto::{EncryptionKey, PublicKey, SignatureScheme, Signer, SIGNING_KEY_SIZE};
 use placer_pack::keyrings::DEFAULT_KEY_LABEL;
-use placer_pack::{Keyrings, Pack};
+use placer_pack::keyuri::{bech32k, normalize_fingerprint, ENCRYPTION_KEY_PREFIX};
+use placer_pack::{Keyrings, Limits, Pack};
+
+/// Permissions of files extracted from packs
+const EXTRACTED_FILE_MODE: u32 = 0o600;
+
+/// How long to wait for changes to settle before rebuilding in watch mode
+const WATCH_DEBOUNCE_MS: u64 = 500;
 
 /// Command line arguments (using structopt as the parser)
 #[derive(StructOpt, Debug)]
 #[structopt(name = "placer-pack", about = "builder for placer packs")]
-enum Opts {
+struct Opts {
+    /// Print debugging information (key fingerprints, sizes, etc)
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool,
+
+    /// Subcommand to run
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+/// placer-pack subcommands
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(
+        name = "audit",
+        about = "check a pack's keys, UUID, and signature agree"
+    )]
+    Audit {
+        /// Path to keyring configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Path to the pack
+        #[structopt(name = "PACK", parse(from_os_str))]
+        pack: PathBuf,
+    },
+
+    #[structopt(name = "cat", about = "print the contents of a file in a pack")]
+    Cat {
+        /// Path to keyring configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Path to the pack
+        #[structopt(name = "PACK", parse(from_os_str))]
+        pack: PathBuf,
+
+        /// Name of the file to print
+        #[structopt(name = "FILENAME")]
+        filename: String,
+    },
+
+    #[structopt(
+        name = "cosign",
+        about = "add a signature to a pack signed by another key"
+    )]
+    Cosign {
+        /// Path to configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-signing-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Path to the pack (updated in place)
+        #[structopt(name = "PACK", parse(from_os_str))]
+        pack: PathBuf,
+    },
+
     #[structopt(name = "create", about = "create a placer pack (pretend it's tar!)")]
     Create {
         /// Base directory for all files
@@ -36,15 +126,127 @@ enum Opts {
         )]
         config: PathBuf,
 
+        /// Read the signing and encryption KeyURIs from stdin (separated by
+        /// whitespace) instead of the keyring file
+        #[structopt(long = "stdin-key", raw(conflicts_with = r#""env_key""#))]
+        stdin_key: bool,
+
+        /// Read the signing and encryption KeyURIs from the PLACER_SIGNING_KEY
+        /// and PLACER_ENCRYPTION_KEY environment variables instead of the
+        /// keyring file
+        #[structopt(long = "env-key")]
+        env_key: bool,
+
         /// Path to output file
         #[structopt(short = "f", long = "file", parse(from_os_str))]
         output: PathBuf,
 
+        /// Create a delta pack containing only changes relative to this base pack
+        #[structopt(short = "b", long = "base", parse(from_os_str))]
+        base_pack: Option<PathBuf>,
+
+        /// Store changed files in a delta pack as chunks of their versions in
+        /// the base pack plus new data, where that's smaller (requires
+        /// placers which support v0.2 packs)
+        #[structopt(long = "chunked", raw(requires = r#""base_pack""#))]
+        chunked: bool,
+
+        /// Only include files modified at or after this time (RFC 3339)
+        #[structopt(long = "since", parse(try_from_str = "parse_rfc3339"))]
+        since: Option<DateTime<Utc>>,
+
+        /// Only include files modified at or before this time (RFC 3339)
+        #[structopt(long = "until", parse(try_from_str = "parse_rfc3339"))]
+        until: Option<DateTime<Utc>>,
+
+        /// Encryption key labels of pack recipients (may be given multiple times)
+        #[structopt(short = "r", long = "recipient", number_of_values = 1)]
+        recipients: Vec<String>,
+
+        /// Maximum number of files in the pack
+        #[structopt(long = "max-files")]
+        max_files: Option<usize>,
+
+        /// Maximum size of an individual file in the pack (in bytes)
+        #[structopt(long = "max-file-size")]
+        max_file_size: Option<usize>,
+
+        /// UUID of the pack (random if unspecified)
+        #[structopt(long = "uuid")]
+        uuid: Option<Uuid>,
+
+        /// Free-text description of the pack, for auditing
+        #[structopt(long = "description")]
+        description: Option<String>,
+
+        /// Channel (i.e. environment, e.g. "production") the pack is for.
+        /// placer refuses packs for channels it isn't configured to accept.
+        #[structopt(long = "channel")]
+        channel: Option<String>,
+
+        /// Date of the pack, and modification time of all files in it, for
+        /// reproducible packs (RFC 3339, defaults to $SOURCE_DATE_EPOCH)
+        #[structopt(long = "date", parse(try_from_str = "parse_rfc3339"))]
+        date: Option<DateTime<Utc>>,
+
+        /// Keep running, rebuilding the pack (with a new UUID and date)
+        /// whenever an input file changes
+        #[structopt(
+            short = "w",
+            long = "watch",
+            raw(conflicts_with_all = r#"&["uuid", "date"]"#)
+        )]
+        watch: bool,
+
         /// Files to include in pack
         #[structopt(name = "FILE", parse(from_os_str))]
         input: Vec<PathBuf>,
     },
 
+    #[structopt(name = "diff", about = "compare the contents of two packs")]
+    Diff {
+        /// Path to keyring configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Print differences as JSON
+        #[structopt(long = "json")]
+        json: bool,
+
+        /// Path to the older pack
+        #[structopt(name = "OLD", parse(from_os_str))]
+        old: PathBuf,
+
+        /// Path to the newer pack
+        #[structopt(name = "NEW", parse(from_os_str))]
+        new: PathBuf,
+    },
+
+    #[structopt(name = "extract", about = "extract all files in a pack to a directory")]
+    Extract {
+        /// Path to keyring configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Directory to extract files into
+        #[structopt(short = "C", long = "into", default_value = ".", parse(from_os_str))]
+        into: PathBuf,
+
+        /// Path to the pack
+        #[structopt(name = "PACK", parse(from_os_str))]
+        pack: PathBuf,
+    },
+
     #[structopt(
         name = "export",
         about = "export a keyring suitable for a placer verifier"
@@ -72,6 +274,63 @@ enum Opts {
         output: PathBuf,
     },
 
+    #[structopt(
+        name = "rotate-keys",
+        about = "re-encrypt/re-sign a directory of packs under a new keyring"
+    )]
+    RotateKeys {
+        /// Path to the keyring the packs are currently signed/encrypted with
+        #[structopt(long = "old", parse(from_os_str))]
+        old: PathBuf,
+
+        /// Path to the new keyring (generated if it doesn't exist)
+        #[structopt(long = "new", parse(from_os_str))]
+        new: PathBuf,
+
+        /// Directory containing the packs to migrate
+        #[structopt(long = "packs", parse(from_os_str))]
+        packs: PathBuf,
+
+        /// Force overwrite the verify keyring if it exists
+        #[structopt(short = "f", long = "force")]
+        force: bool,
+
+        /// Verify keyring to export for the new keys
+        #[structopt(
+            name = "OUTPUT",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(
+        name = "check-keyring",
+        about = "check a keyring can verify and decrypt the given packs"
+    )]
+    CheckKeyring {
+        /// Path to keyring configuration file
+        #[structopt(
+            short = "c",
+            long = "config",
+            default_value = "placer-verify-keyring.toml",
+            parse(from_os_str)
+        )]
+        config: PathBuf,
+
+        /// Paths to the packs
+        #[structopt(name = "PACK", parse(from_os_str))]
+        packs: Vec<PathBuf>,
+    },
+
+    #[structopt(name = "fingerprint", about = "print the fingerprint of a KeyURI")]
+    Fingerprint {
+        /// KeyURI of a signing, verify, or encryption key (read from stdin if
+        /// omitted, which keeps secret keys out of the process list)
+        #[structopt(name = "KEYURI")]
+        keyuri: Option<String>,
+    },
+
     #[structopt(name = "keygen", about = "generate random keyring for producing packs")]
     Keygen {
         /// Force overwrite the keyring if it exists
@@ -89,79 +348,818 @@ enum Opts {
 }
 
 fn main() {
-    let log = init_logger();
+    let opts = Opts::from_args();
+    let log = init_logger(opts.verbose);
 
-    match Opts::from_args() {
-        Opts::Create {
+    match opts.command {
+        Command::Audit { config, pack } => audit(&log, &config, &pack),
+        Command::Cat {
+            config,
+            pack,
+            filename,
+        } => cat(&log, &config, &pack, &filename),
+        Command::Cosign { config, pack } => cosign(&log, &config, &pack),
+        Command::Create {
             base,
             config,
+            stdin_key,
+            env_key,
             input,
             output,
-        } => create(&log, &base, &config, &input, &output),
-        Opts::Export {
+            base_pack,
+            chunked,
+            since,
+            until,
+            recipients,
+            max_files,
+            max_file_size,
+            uuid,
+            description,
+            channel,
+            date,
+            watch,
+        } => {
+            let defaults = Limits::default();
+            let limits = Limits {
+                max_files: max_files.unwrap_or(defaults.max_files),
+                max_file_size: max_file_size.unwrap_or(defaults.max_file_size),
+            };
+
+            let keyrings = load_create_keyrings(&log, &config, stdin_key, env_key);
+
+            create(
+                &log,
+                &base,
+                keyrings,
+                &input,
+                &output,
+                base_pack.as_ref().map(PathBuf::as_path),
+                chunked,
+                since.as_ref(),
+                until.as_ref(),
+                &recipients,
+                limits,
+                uuid,
+                description,
+                channel,
+                date.or_else(|| if watch { None } else { source_date_epoch(&log) }),
+                watch,
+            )
+        }
+        Command::Diff {
+            config,
+            json,
+            old,
+            new,
+        } => diff(&log, &config, json, &old, &new),
+        Command::Extract { config, into, pack } => extract(&log, &config, &into, &pack),
+        Command::Export {
             config,
             force,
             output,
         } => export(&log, &config, force, &output),
-        Opts::Keygen { force, output } => keygen(&log, force, &output),
+        Command::RotateKeys {
+            old,
+            new,
+            packs,
+            force,
+            output,
+        } => rotate_keys(&log, &old, &new, &packs, force, &output),
+        Command::CheckKeyring { config, packs } => check_keyring(&log, &config, &packs),
+        Command::Fingerprint { keyuri } => fingerprint(&log, keyuri),
+        Command::Keygen { force, output } => keygen(&log, force, &output),
     }
 }
 
 /// Initialize the logger
-fn init_logger() -> Logger {
+fn init_logger(verbose: bool) -> Logger {
+    let level = if verbose { Level::Debug } else { Level::Info };
     let decorator = slog_term::TermDecorator::new().build();
     let drain = slog_term::CompactFormat::new(decorator).build();
-    let drain = std::sync::Mutex::new(drain).fuse();
+    let drain = std::sync::Mutex::new(drain).filter_level(level).fuse();
 
     Logger::root(drain, o!())
 }
 
-/// Create a new pack
-fn create(log: &Logger, base_dir: &Path, config: &Path, input: &[PathBuf], output: &Path) {
+/// Audit a pack for self-consistency
+fn audit(log: &Logger, config: &Path, pack_path: &Path) {
+    let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let pack = load_pack(log, &keyrings, pack_path).unwrap_or_else(|e| {
+        crit!(log, "{}", e);
+        process::exit(1);
+    });
+
+    let result = fs::read(pack_path)
+        .map_err(|e| e.into())
+        .and_then(|bytes| pack.validate_self_consistency(&bytes, &keyrings));
+
+    if let Err(e) = result {
+        crit!(
+            log,
+            "audit failed for {}: {}",
+            pack_path.to_string_lossy(),
+            e
+        );
+        process::exit(1);
+    }
+
+    if let Some(ref description) = pack.description {
+        info!(log, "description: {:?}", description);
+    }
+
+    if let Some(ref channel) = pack.channel {
+        info!(log, "channel: {:?}", channel);
+    }
+
+    info!(log, "{} is self-consistent", pack_path.to_string_lossy());
+}
+
+/// Print the contents of a file in a pack to stdout
+fn cat(log: &Logger, config: &Path, pack_path: &Path, filename: &str) {
     let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
         crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
         process::exit(1);
     });
 
-    let uuid = Uuid::new_v4();
-    let mut encryptor = keyrings
-        .encryption
-        .get(DEFAULT_KEY_LABEL, uuid.as_bytes())
+    let pack = load_pack(log, &keyrings, pack_path).unwrap_or_else(|e| {
+        crit!(log, "{}", e);
+        process::exit(1);
+    });
+
+    let file = pack.get_file(filename).unwrap_or_else(|| {
+        let filenames: Vec<_> = pack.files().map(|file| file.filename.as_str()).collect();
+        crit!(
+            log,
+            "no such file in {}: {} (available: {})",
+            pack_path.to_string_lossy(),
+            filename,
+            filenames.join(", ")
+        );
+        process::exit(1);
+    });
+
+    // Warn before dumping binary data to the terminal
+    if !file.content_type.starts_with("text/") && str::from_utf8(&file.body).is_err() {
+        warn!(
+            log,
+            "{} appears to be binary (content type: {})", filename, file.content_type
+        );
+    }
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+
+    handle
+        .write_all(&file.body)
+        .and_then(|_| handle.flush())
         .unwrap_or_else(|e| {
-            crit!(log, "error initializing encryptor: {}", e);
+            crit!(log, "error writing to stdout: {}", e);
             process::exit(1);
         });
+}
+
+/// Add a cosignature to a pack using the default signing key
+fn cosign(log: &Logger, config: &Path, pack_path: &Path) {
+    let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
+        process::exit(1);
+    });
 
-    // TODO: support for using a non-default signing key
     let signer = keyrings.signing.get(DEFAULT_KEY_LABEL).unwrap_or_else(|e| {
         crit!(log, "error initializing signer: {}", e);
         process::exit(1);
     });
 
-    let pack = Pack::create(uuid, base_dir, input).unwrap_or_else(|e| {
-        crit!(log, "error creating pack: {}", e);
+    let bytes = fs::read(pack_path).unwrap_or_else(|e| {
+        crit!(log, "error reading {}: {}", pack_path.to_string_lossy(), e);
         process::exit(1);
     });
 
+    let cosigned = Pack::cosign(&bytes, signer).unwrap_or_else(|e| {
+        crit!(
+            log,
+            "error cosigning {}: {}",
+            pack_path.to_string_lossy(),
+            e
+        );
+        process::exit(1);
+    });
+
+    write_atomically(pack_path, &cosigned).unwrap_or_else(|e| {
+        crit!(
+            log,
+            "error writing pack to {}: {}",
+            pack_path.to_string_lossy(),
+            e
+        );
+        process::exit(1);
+    });
+
+    info!(log, "cosigned pack: {}", pack_path.to_string_lossy());
+}
+
+/// Create a new pack
+#[allow(clippy::too_many_arguments)]
+fn create(
+    log: &Logger,
+    base_dir: &Path,
+    keyrings: Keyrings,
+    input: &[PathBuf],
+    output: &Path,
+    base_pack: Option<&Path>,
+    chunked: bool,
+    since: Option<&DateTime<Utc>>,
+    until: Option<&DateTime<Utc>>,
+    recipients: &[String],
+    limits: Limits,
+    uuid: Option<Uuid>,
+    description: Option<String>,
+    channel: Option<String>,
+    date: Option<DateTime<Utc>>,
+    watch: bool,
+) {
+    let labels = if recipients.is_empty() {
+        vec![DEFAULT_KEY_LABEL.to_owned()]
+    } else {
+        recipients.to_vec()
+    };
+
+    let build = || {
+        build_pack(
+            log,
+            &keyrings,
+            &labels,
+            base_dir,
+            input,
+            output,
+            base_pack,
+            chunked,
+            since,
+            until,
+            limits,
+            uuid.unwrap_or_else(Uuid::new_v4),
+            description.clone(),
+            channel.clone(),
+            date,
+        )
+    };
+
+    if let Err(e) = build() {
+        crit!(log, "{}", e);
+
+        if !watch {
+            process::exit(1);
+        }
+    }
+
+    if watch {
+        watch_inputs(log, base_dir, input, build);
+    }
+}
+
+/// Load the keyrings used to create packs: from stdin or the environment if
+/// requested (so secret keys needn't be written to disk), otherwise from the
+/// keyring file
+fn load_create_keyrings(log: &Logger, config: &Path, stdin_key: bool, env_key: bool) -> Keyrings {
+    let result = if stdin_key {
+        Keyrings::from_reader(io::stdin().lock())
+    } else if env_key {
+        Keyrings::from_env()
+    } else {
+        Keyrings::load(config)
+    };
+
+    result.unwrap_or_else(|e| {
+        if stdin_key {
+            crit!(log, "error reading keys from stdin: {}", e);
+        } else if env_key {
+            crit!(log, "error reading keys from the environment: {}", e);
+        } else {
+            crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
+        }
+
+        process::exit(1);
+    })
+}
+
+/// Create a pack from the given input files, then encrypt, sign, and write
+/// it to the output file
+#[allow(clippy::too_many_arguments)]
+fn build_pack(
+    log: &Logger,
+    keyrings: &Keyrings,
+    labels: &[String],
+    base_dir: &Path,
+    input: &[PathBuf],
+    output: &Path,
+    base_pack: Option<&Path>,
+    chunked: bool,
+    since: Option<&DateTime<Utc>>,
+    until: Option<&DateTime<Utc>>,
+    limits: Limits,
+    uuid: Uuid,
+    description: Option<String>,
+    channel: Option<String>,
+    date: Option<DateTime<Utc>>,
+) -> Result<(), String> {
+    let mut encryptors = labels
+        .iter()
+        .map(|label| {
+            keyrings
+                .encryption
+                .get(label, uuid.as_bytes())
+                .map_err(|e| format!("error initializing \"{}\" encryptor: {}", label, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // TODO: support for using a non-default signing key
+    let signer = keyrings
+        .signing
+        .get(DEFAULT_KEY_LABEL)
+        .map_err(|e| format!("error initializing signer: {}", e))?;
+
+    debug!(log, "pack UUID (key derivation salt): {}", uuid);
+
+    for (label, encryptor) in labels.iter().zip(&encryptors) {
+        debug!(
+            log,
+            "encrypting for \"{}\": {}",
+            label,
+            encryptor.fingerprint()
+        );
+    }
+
+    if let Ok(public_key) = signer.public_key() {
+        debug!(log, "signing with: {}", public_key.to_fingerprint());
+    }
+
+    let mut pack = Pack::create_modified_within(uuid, base_dir, input, since, until)
+        .map_err(|e| format!("error creating pack: {}", e))?;
+
+    if let Some(path) = base_pack {
+        let base = load_pack(log, keyrings, path)?;
+
+        pack = if chunked {
+            pack.into_chunked_delta(&base)
+        } else {
+            pack.into_delta(&base)
+        };
+    }
+
+    pack.limits = limits;
+    pack.description = description;
+    pack.channel = channel.filter(|c| !c.is_empty());
+
+    if let Some(date) = date {
+        if encryptors.len() > 1 {
+            warn!(
+                log,
+                "packs for multiple recipients aren't reproducible (random content key)"
+            );
+        }
+
+        pack.set_reproducible_date(date);
+    }
+
     let output_filename = output.to_string_lossy().to_string();
-    let mut output_file = File::create(output).unwrap_or_else(|e| {
-        crit!(log, "couldn't open {} for writing: {}", output_filename, e);
+
+    debug!(
+        log,
+        "payload: {} file(s) ({} bytes), {} chunked file(s), {} deletion(s)",
+        pack.file_count(),
+        pack.files().map(|file| file.body.len()).sum::<usize>(),
+        pack.chunked_files.len(),
+        pack.deletions.len()
+    );
+
+    let serialized_pack = if encryptors.len() == 1 {
+        pack.encrypt_and_sign(&mut encryptors[0], signer)
+    } else {
+        pack.encrypt_and_sign_for_recipients(&mut encryptors, signer)
+    }
+    .map_err(|e| format!("error encrypting/signing pack: {}", e))?;
+
+    log_pack_details(log, &serialized_pack);
+
+    write_atomically(output, &serialized_pack)
+        .map_err(|e| format!("error writing pack to {}: {}", output_filename, e))?;
+
+    info!(log, "created pack: {}", &output_filename);
+    Ok(())
+}
+
+/// Watch the input files for changes, calling `build` after each batch of
+/// changes. Their parent directories are watched (rather than the files
+/// themselves) so files which editors replace by renaming are still seen.
+fn watch_inputs<F>(log: &Logger, base_dir: &Path, input: &[PathBuf], build: F) -> !
+where
+    F: Fn() -> Result<(), String>,
+{
+    let canonical_base = base_dir.canonicalize().unwrap_or_else(|e| {
+        crit!(
+            log,
+            "error canonicalizing {}: {}",
+            base_dir.to_string_lossy(),
+            e
+        );
         process::exit(1);
     });
 
-    let serialized_pack = pack
-        .encrypt_and_sign(&mut encryptor, signer)
-        .unwrap_or_else(|e| {
-            crit!(log, "error encrypting/signing pack: {}", e);
+    let mut watched_paths = BTreeSet::new();
+    let mut watched_dirs = BTreeSet::new();
+
+    for filename in input {
+        let path = canonical_base.join(filename);
+        let dir = path
+            .parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .unwrap_or_else(|| {
+                crit!(log, "can't watch {}", path.to_string_lossy());
+                process::exit(1);
+            });
+
+        watched_paths.insert(dir.join(path.file_name().unwrap()));
+        watched_dirs.insert(dir);
+    }
+
+    let (tx, rx) = mpsc::channel();
+
+    let mut watcher =
+        notify::watcher(tx, Duration::from_millis(WATCH_DEBOUNCE_MS)).unwrap_or_else(|e| {
+            crit!(log, "error initializing file watcher: {}", e);
             process::exit(1);
         });
 
-    output_file.write_all(&serialized_pack).unwrap_or_else(|e| {
-        crit!(log, "error writing pack: {}", e);
+    for dir in &watched_dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .unwrap_or_else(|e| {
+                crit!(log, "error watching {}: {}", dir.to_string_lossy(), e);
+                process::exit(1);
+            });
+    }
+
+    info!(
+        log,
+        "watching {} file(s) for changes (Ctrl-C to stop)",
+        watched_paths.len()
+    );
+
+    rebuild_on_changes(log, &rx, &watched_paths, build);
+
+    crit!(log, "file watcher stopped unexpectedly");
+    process::exit(1);
+}
+
+/// Call `build` after each batch of changes to the watched paths until the
+/// watcher stops. Build errors (e.g. a base pack which fails to load) are
+/// logged, and the next change triggers another attempt.
+fn rebuild_on_changes<F>(
+    log: &Logger,
+    rx: &mpsc::Receiver<DebouncedEvent>,
+    watched_paths: &BTreeSet<PathBuf>,
+    build: F,
+) where
+    F: Fn() -> Result<(), String>,
+{
+    while let Ok(event) = rx.recv() {
+        let changed = match event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Chmod(path)
+            | DebouncedEvent::Remove(path)
+            | DebouncedEvent::Rename(_, path) => path,
+            DebouncedEvent::Error(e, path) => {
+                warn!(log, "error watching {:?}: {}", path, e);
+                continue;
+            }
+            _ => continue,
+        };
+
+        if !watched_paths.contains(&changed) {
+            continue;
+        }
+
+        // Rebuild once for everything that changed at the same time
+        while rx.try_recv().is_ok() {}
+
+        info!(log, "{} changed, rebuilding", changed.to_string_lossy());
+
+        if let Err(e) = build() {
+            error!(log, "{}", e);
+        }
+    }
+}
+
+/// Write data to a temporary file alongside the given path, then rename it
+/// into place, so a failed write never leaves a truncated file at `path`
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = tmp_path_for(path);
+
+    let result = File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+/// Path of the temporary (dot)file used while writing the given path
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.tmp", file_name))
+}
+
+/// Differences between two packs
+#[derive(Debug, Default, Serialize)]
+struct PackDiff {
+    /// Did the pack UUID change?
+    uuid_changed: bool,
+
+    /// Did the signing key fingerprint change?
+    signing_key_changed: bool,
+
+    /// Did the encryption key fingerprint change?
+    encryption_key_changed: bool,
+
+    /// Files present only in the newer pack
+    added: Vec<String>,
+
+    /// Files present only in the older pack
+    removed: Vec<String>,
+
+    /// Files whose contents differ between the packs
+    changed: Vec<String>,
+}
+
+/// Parse an RFC 3339 timestamp from the command line
+fn parse_rfc3339(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(s).map(|date| date.with_timezone(&Utc))
+}
+
+/// Get the date from the `SOURCE_DATE_EPOCH` environment variable (if set),
+/// as used by reproducible build tooling (seconds since the Unix epoch)
+fn source_date_epoch(log: &Logger) -> Option<DateTime<Utc>> {
+    let value = env::var("SOURCE_DATE_EPOCH").ok()?;
+
+    let date = value
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .unwrap_or_else(|| {
+            crit!(log, "invalid SOURCE_DATE_EPOCH: {:?}", value);
+            process::exit(1);
+        });
+
+    Some(date)
+}
+
+/// Compare the contents of two packs
+fn diff(log: &Logger, config: &Path, json: bool, old: &Path, new: &Path) {
+    let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
         process::exit(1);
     });
 
-    info!(log, "created pack: {}", &output_filename);
+    let old_pack = load_pack(log, &keyrings, old).unwrap_or_else(|e| {
+        crit!(log, "{}", e);
+        process::exit(1);
+    });
+    let new_pack = load_pack(log, &keyrings, new).unwrap_or_else(|e| {
+        crit!(log, "{}", e);
+        process::exit(1);
+    });
+
+    let old_fingerprints = old_pack.fingerprints.as_ref().unwrap();
+    let new_fingerprints = new_pack.fingerprints.as_ref().unwrap();
+
+    let mut result = PackDiff {
+        uuid_changed: old_pack.uuid() != new_pack.uuid(),
+        signing_key_changed: old_fingerprints.signing_key != new_fingerprints.signing_key,
+        encryption_key_changed: old_fingerprints.encryption_key != new_fingerprints.encryption_key,
+        ..PackDiff::default()
+    };
+
+    let old_files: BTreeMap<_, _> = old_pack
+        .files()
+        .map(|file| (&file.filename, Sha256::digest(&file.body)))
+        .collect();
+
+    let new_files: BTreeMap<_, _> = new_pack
+        .files()
+        .map(|file| (&file.filename, Sha256::digest(&file.body)))
+        .collect();
+
+    for (filename, digest) in &new_files {
+        match old_files.get(filename) {
+            Some(old_digest) if old_digest != digest => result.changed.push(filename.to_string()),
+            Some(_) => (),
+            None => result.added.push(filename.to_string()),
+        }
+    }
+
+    for filename in old_files.keys() {
+        if !new_files.contains_key(filename) {
+            result.removed.push(filename.to_string());
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        return;
+    }
+
+    println!("uuid: {} -> {}", old_pack.uuid(), new_pack.uuid());
+
+    if result.signing_key_changed {
+        println!(
+            "signing key: {} -> {}",
+            old_fingerprints.signing_key, new_fingerprints.signing_key
+        );
+    }
+
+    if result.encryption_key_changed {
+        println!(
+            "encryption key: {} -> {}",
+            old_fingerprints.encryption_key, new_fingerprints.encryption_key
+        );
+    }
+
+    for filename in &result.added {
+        println!("added: {}", filename);
+    }
+
+    for filename in &result.removed {
+        println!("removed: {}", filename);
+    }
+
+    for filename in &result.changed {
+        println!("changed: {}", filename);
+    }
+}
+
+/// Load a pack, verifying and decrypting it with the given keyrings
+fn load_pack(log: &Logger, keyrings: &Keyrings, path: &Path) -> Result<Pack, String> {
+    if let Ok(bytes) = fs::read(path) {
+        log_pack_details(log, &bytes);
+    }
+
+    let pack = Pack::load(path, |fingerprints, uuid| {
+        debug!(
+            log,
+            "looking up keys: {} / {} (salt: {})",
+            fingerprints.signing_key,
+            fingerprints.encryption_key,
+            uuid
+        );
+
+        keyrings
+            .key_lookup(fingerprints, uuid)
+            .map_err(|e| error!(log, "missing keys for {}: {}", path.to_string_lossy(), e))
+            .ok()
+    })
+    .map_err(|e| format!("error loading {}: {}", path.to_string_lossy(), e))?;
+
+    debug!(
+        log,
+        "verified and decrypted {}: {} file(s), {} deletion(s), description: {:?}",
+        path.to_string_lossy(),
+        pack.file_count(),
+        pack.deletions.len(),
+        pack.description
+    );
+
+    Ok(pack)
+}
+
+/// Log the (unverified) header of a serialized pack at debug level
+fn log_pack_details(log: &Logger, bytes: &[u8]) {
+    let header = match Pack::inspect(bytes) {
+        Ok(header) => header,
+        Err(e) => {
+            debug!(log, "couldn't parse pack header: {}", e);
+            return;
+        }
+    };
+
+    debug!(
+        log,
+        "pack {} ({}): {} bytes, {}-byte ciphertext, {}-byte signature",
+        header.uuid,
+        header.date.to_rfc3339(),
+        bytes.len(),
+        header.ciphertext_len,
+        header.signature_len
+    );
+
+    for fingerprints in &header.recipients {
+        debug!(
+            log,
+            "recipient: {} / {}", fingerprints.signing_key, fingerprints.encryption_key
+        );
+    }
+}
+
+/// Extract all files in a pack into the given directory
+fn extract(log: &Logger, config: &Path, into: &Path, pack_path: &Path) {
+    let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let pack = load_pack(log, &keyrings, pack_path).unwrap_or_else(|e| {
+        crit!(log, "{}", e);
+        process::exit(1);
+    });
+
+    for file in &pack {
+        let relative_path = sanitize_filename(&file.filename).unwrap_or_else(|| {
+            crit!(
+                log,
+                "refusing to extract unsafe filename: {:?}",
+                file.filename
+            );
+            process::exit(1);
+        });
+
+        let output_path = into.join(&relative_path);
+
+        fs::create_dir_all(output_path.parent().unwrap())
+            .and_then(|_| write_extracted_file(&output_path, &file.body))
+            .unwrap_or_else(|e| {
+                crit!(
+                    log,
+                    "error writing {}: {}",
+                    output_path.to_string_lossy(),
+                    e
+                );
+                process::exit(1);
+            });
+
+        if let Some(modified_at) = file.modified_at.as_ref().and_then(|t| t.to_system_time()) {
+            let mtime = FileTime::from_system_time(modified_at);
+
+            if let Err(e) = filetime::set_file_mtime(&output_path, mtime) {
+                warn!(
+                    log,
+                    "couldn't set mtime of {}: {}",
+                    output_path.to_string_lossy(),
+                    e
+                );
+            }
+        }
+
+        info!(log, "extracted {}", output_path.to_string_lossy());
+    }
+}
+
+/// Write a file extracted from a pack, which is only readable by its owner
+/// (packs often contain secrets), even if it replaces an existing file
+fn write_extracted_file(path: &Path, body: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(EXTRACTED_FILE_MODE)
+        .open(path)?;
+
+    file.set_permissions(Permissions::from_mode(EXTRACTED_FILE_MODE))?;
+    file.write_all(body)
+}
+
+/// Convert a filename from a pack into a relative path, refusing any which
+/// would escape the output directory
+fn sanitize_filename(filename: &str) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+
+    for component in Path::new(filename).components() {
+        match component {
+            Component::Normal(part) => result.push(part),
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if result.as_os_str().is_empty() {
+        None
+    } else {
+        Some(result)
+    }
 }
 
 /// Export secret keyring to a verifier keyring
@@ -188,6 +1186,242 @@ fn export(log: &Logger, config: &Path, force: bool, output: &Path) {
     info!(log, "saved verify keyring to: {}", output.to_string_lossy())
 }
 
+/// Migrate every pack in a directory from the old keyring to the new one,
+/// generating the new keyring if needed and exporting its verify keyring.
+///
+/// All packs are re-encrypted in memory before any are written, so a pack
+/// which can't be migrated leaves the whole directory untouched.
+fn rotate_keys(log: &Logger, old: &Path, new: &Path, packs_dir: &Path, force: bool, output: &Path) {
+    if output.exists() && !force {
+        crit!(
+            log,
+            "{}: already exists (use -f to overwrite)",
+            output.to_string_lossy()
+        );
+        process::exit(1);
+    }
+
+    let old_keyrings = Keyrings::load(old).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", old.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    if new.exists() {
+        info!(log, "using existing keyring: {}", new.to_string_lossy());
+    } else {
+        Keyrings::generate_random(new).unwrap_or_else(|e| {
+            crit!(log, "error generating keys: {}", e);
+            process::exit(1);
+        });
+
+        info!(log, "new secret keys saved to: {}", new.to_string_lossy());
+    }
+
+    let new_keyrings = Keyrings::load(new).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", new.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let pack_paths = list_packs(packs_dir).unwrap_or_else(|e| {
+        crit!(log, "error reading {}: {}", packs_dir.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let mut migrated = vec![];
+
+    for path in pack_paths {
+        let bytes = fs::read(&path).unwrap_or_else(|e| {
+            crit!(log, "error reading {}: {}", path.to_string_lossy(), e);
+            process::exit(1);
+        });
+
+        match Pack::reencrypt(&bytes, &old_keyrings, &new_keyrings) {
+            Ok(reencrypted) => migrated.push((path, reencrypted)),
+            Err(_) if Pack::verify_and_decrypt_with(&bytes, &new_keyrings).is_ok() => {
+                info!(log, "already migrated: {}", path.to_string_lossy());
+            }
+            Err(e) => {
+                crit!(
+                    log,
+                    "error migrating {}: {} (no packs were modified)",
+                    path.to_string_lossy(),
+                    e
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    for (path, reencrypted) in &migrated {
+        write_atomically(path, reencrypted).unwrap_or_else(|e| {
+            crit!(
+                log,
+                "error writing pack to {}: {}",
+                path.to_string_lossy(),
+                e
+            );
+            process::exit(1);
+        });
+
+        info!(log, "migrated pack: {}", path.to_string_lossy());
+    }
+
+    new_keyrings.export_verify_keys(output).unwrap_or_else(|e| {
+        crit!(log, "error exporting verify keyring: {}", e);
+        process::exit(1);
+    });
+
+    info!(
+        log,
+        "migrated {} pack(s); saved verify keyring to: {}",
+        migrated.len(),
+        output.to_string_lossy()
+    );
+}
+
+/// List the packs in a directory (i.e. all files which aren't dotfiles)
+fn list_packs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_file() && !entry.file_name().to_string_lossy().starts_with('.') {
+            paths.push(entry.path());
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// Check the keyring contains keys for at least one recipient of every pack
+fn check_keyring(log: &Logger, config: &Path, pack_paths: &[PathBuf]) {
+    let keyrings = Keyrings::load(config).unwrap_or_else(|e| {
+        crit!(log, "error parsing {}: {}", config.to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let (signing, encryption) = keyrings.covered_fingerprints();
+    let mut uncovered = 0;
+
+    for path in pack_paths {
+        let header = fs::read(path)
+            .map_err(|e| e.into())
+            .and_then(|bytes| Pack::inspect(&bytes))
+            .unwrap_or_else(|e| {
+                crit!(log, "error reading {}: {}", path.to_string_lossy(), e);
+                process::exit(1);
+            });
+
+        let covered = header.recipients.iter().any(|fingerprints| {
+            signing.contains(&normalize_fingerprint(&fingerprints.signing_key))
+                && encryption.contains(&normalize_fingerprint(&fingerprints.encryption_key))
+        });
+
+        if covered {
+            info!(log, "{}: ok", path.to_string_lossy());
+            continue;
+        }
+
+        uncovered += 1;
+
+        for fingerprints in &header.recipients {
+            error!(
+                log,
+                "{}: missing keys (signing: {}, encryption: {})",
+                path.to_string_lossy(),
+                fingerprints.signing_key,
+                fingerprints.encryption_key
+            );
+        }
+    }
+
+    if uncovered > 0 {
+        crit!(
+            log,
+            "{} can't verify/decrypt {} of {} pack(s)",
+            config.to_string_lossy(),
+            uncovered,
+            pack_paths.len()
+        );
+        process::exit(1);
+    }
+}
+
+/// Print the fingerprint of a KeyURI, as referenced by packs. Secret signing
+/// keys are fingerprinted by their public key.
+fn fingerprint(log: &Logger, keyuri: Option<String>) {
+    let keyuri = match keyuri {
+        Some(keyuri) => SecretBytes(keyuri.into_bytes()),
+        None => {
+            let mut input = SecretBytes(vec![]);
+
+            if let Err(e) = io::stdin().read_to_end(&mut input.0) {
+                drop(input);
+                crit!(log, "error reading KeyURI from stdin: {}", e);
+                process::exit(1);
+            }
+
+            input
+        }
+    };
+
+    // Clear the KeyURI before exiting (which skips destructors)
+    let result = key_fingerprint(&keyuri.0);
+    drop(keyuri);
+
+    match result {
+        Ok(fingerprint) => println!("{}", fingerprint),
+        Err(e) => {
+            crit!(log, "{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Compute the fingerprint of a KeyURI (see `fingerprint`)
+fn key_fingerprint(keyuri: &[u8]) -> Result<String, String> {
+    let keyuri = str::from_utf8(keyuri)
+        .map_err(|_| "invalid KeyURI: not valid UTF-8".to_owned())?
+        .trim();
+
+    let (prefix, decoded_key) =
+        bech32k::decode(keyuri).map_err(|e| format!("invalid KeyURI: {}", e))?;
+    let decoded_key = SecretBytes(decoded_key);
+
+    let result = if let Some(scheme) = SignatureScheme::from_signing_key_prefix(&prefix) {
+        if decoded_key.0.len() != SIGNING_KEY_SIZE {
+            return Err(format!(
+                "bad length for {}: {} (expected {})",
+                prefix,
+                decoded_key.0.len(),
+                SIGNING_KEY_SIZE
+            ));
+        }
+
+        Signer::from_bytes_with_scheme(&decoded_key.0, scheme)
+            .and_then(|signer| signer.public_key())
+            .map(|public_key| public_key.to_fingerprint())
+    } else if prefix == ENCRYPTION_KEY_PREFIX {
+        EncryptionKey::from_keyuri(keyuri).map(|key| key.fingerprint().to_owned())
+    } else {
+        PublicKey::from_keyuri(keyuri).map(|public_key| public_key.to_fingerprint())
+    };
+
+    result.map_err(|e| format!("invalid key: {}", e))
+}
+
+/// Secret data (e.g. a KeyURI or decoded key) which is cleared when dropped,
+/// so it's cleared on every path (including errors)
+struct SecretBytes(Vec<u8>);
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().clear();
+    }
+}
+
 /// Generate a new random keyring
 fn keygen(log: &Logger, force: bool, output: &Path) {
     if output.exists() && !force {
@@ -210,3 +1444,123 @@ fn keygen(log: &Logger, force: bool, output: &Path) {
         output.to_string_lossy()
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        key_fingerprint, load_pack, rebuild_on_changes, sanitize_filename, tmp_path_for,
+        write_extracted_file,
+    };
+    use notify::DebouncedEvent;
+    use placer_pack::crypto::{EncryptionKey, SIGNING_KEY_SIZE};
+    use placer_pack::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX, SIGNING_KEY_PREFIX};
+    use placer_pack::Keyrings;
+    use slog::{Discard, Logger};
+    use std::cell::{Cell, RefCell};
+    use std::collections::BTreeSet;
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(
+            sanitize_filename("/etc/myapp/config.toml"),
+            Some(PathBuf::from("etc/myapp/config.toml"))
+        );
+        assert_eq!(sanitize_filename("/etc/../../root/.ssh/id_rsa"), None);
+        assert_eq!(sanitize_filename("/"), None);
+    }
+
+    #[test]
+    fn test_write_extracted_file() {
+        let path = env::temp_dir().join(format!("placer-pack-extract-{}", std::process::id()));
+
+        // Existing files are made private too
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+
+        write_extracted_file(&path, b"secret").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"secret");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        let keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[1u8; 32]);
+        assert_eq!(
+            key_fingerprint(keyuri.as_bytes()).unwrap(),
+            EncryptionKey::from_keyuri(&keyuri).unwrap().fingerprint()
+        );
+
+        let keyuri = format!(
+            " {}\n",
+            bech32k::encode(SIGNING_KEY_PREFIX, &[2u8; SIGNING_KEY_SIZE])
+        );
+        assert!(key_fingerprint(keyuri.as_bytes()).is_ok());
+
+        // Errors are returned (rather than exiting) so secrets are cleared
+        let keyuri = bech32k::encode(SIGNING_KEY_PREFIX, &[2u8; SIGNING_KEY_SIZE - 1]);
+        let error = key_fingerprint(keyuri.as_bytes()).unwrap_err();
+        assert!(error.starts_with("bad length"), "{}", error);
+
+        assert!(key_fingerprint(b"not-a-keyuri").is_err());
+        assert!(key_fingerprint(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_rebuild_after_error() {
+        let log = Logger::root(Discard, o!());
+        let dir = env::temp_dir().join(format!("placer-pack-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let keyrings_path = dir.join("keyrings.toml");
+        Keyrings::generate_random(&keyrings_path).unwrap();
+        let keyrings = Keyrings::load(&keyrings_path).unwrap();
+
+        let input = dir.join("input.conf");
+        let mut watched_paths = BTreeSet::new();
+        watched_paths.insert(input.clone());
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(DebouncedEvent::Write(dir.join("unwatched")))
+            .unwrap();
+        tx.send(DebouncedEvent::Write(input.clone())).unwrap();
+
+        // Each build fails to load its (missing) base pack. The first one
+        // then reports another change, after which the watcher stops.
+        let next_change = RefCell::new(Some(tx));
+        let builds = Cell::new(0);
+
+        rebuild_on_changes(&log, &rx, &watched_paths, || {
+            builds.set(builds.get() + 1);
+
+            if let Some(tx) = next_change.borrow_mut().take() {
+                tx.send(DebouncedEvent::Write(input.clone())).unwrap();
+            }
+
+            load_pack(&log, &keyrings, &dir.join("missing.pack")).map(|_| ())
+        });
+
+        assert_eq!(builds.get(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tmp_path_for() {
+        assert_eq!(
+            tmp_path_for(Path::new("/srv/packs/base.pack")),
+            PathBuf::from("/srv/packs/.base.pack.tmp")
+        );
+        assert_eq!(
+            tmp_path_for(Path::new("base.pack")),
+            PathBuf::from(".base.pack.tmp")
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Content-defined chunking, used to store files in delta packs as ranges of
+//! their versions in the base pack plus new data.
+//!
+//! Chunk boundaries are found with a "gear" rolling hash, so they depend only
+//! on the data just before them: an edit only changes the chunks around it,
+//! and chunks elsewhere in the file still match the base pack's version.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::error::Error;
+pub use crate::protos::pack::Chunk;
+
+/// Minimum size of a chunk (except the last chunk of a file)
+pub const MIN_CHUNK_SIZE: usize = 2048;
+
+/// Maximum size of a chunk
+pub const MAX_CHUNK_SIZE: usize = 65_536;
+
+/// A boundary is placed where these bits of the rolling hash are all zero,
+/// so chunks average around 8 KiB (beyond the minimum)
+const BOUNDARY_MASK: u64 = 0x1fff << 51;
+
+/// Split data into content-defined chunks
+pub fn boundaries(data: &[u8]) -> Vec<Range<usize>> {
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < data.len() {
+        let end = start + next_boundary(&data[start..]);
+        chunks.push(start..end);
+        start = end;
+    }
+
+    chunks
+}
+
+/// Encode `body` as chunks: ranges of `base` wherever chunks of the two
+/// match, and new data elsewhere. Adjacent ranges (and new data) are merged.
+pub fn encode(base: &[u8], body: &[u8]) -> Vec<Chunk> {
+    let mut base_chunks = HashMap::new();
+
+    for range in boundaries(base) {
+        base_chunks
+            .entry(digest(&base[range.clone()]))
+            .or_insert(range);
+    }
+
+    let mut chunks: Vec<Chunk> = vec![];
+
+    for range in boundaries(body) {
+        let data = &body[range];
+
+        match base_chunks.get(&digest(data)) {
+            Some(base_range) => push_range(&mut chunks, base_range),
+            None => push_data(&mut chunks, data),
+        }
+    }
+
+    chunks
+}
+
+/// Reassemble a file's body from chunks of its base version, refusing
+/// ranges outside the base version or bodies larger than `max_size`
+pub fn reassemble(base: &[u8], chunks: &[Chunk], max_size: usize) -> Result<Vec<u8>, Error> {
+    let mut body = vec![];
+
+    for chunk in chunks {
+        let data = if chunk.base_length == 0 {
+            &chunk.data[..]
+        } else {
+            if !chunk.data.is_empty() {
+                fail!(Delta, "chunk contains both a base range and new data");
+            }
+
+            let start = chunk.base_offset as usize;
+            let end = start.checked_add(chunk.base_length as usize);
+
+            end.and_then(|end| base.get(start..end)).ok_or_else(|| {
+                err!(
+                    Delta,
+                    "chunk range out of bounds: {}+{} (base is {}-bytes)",
+                    chunk.base_offset,
+                    chunk.base_length,
+                    base.len()
+                )
+            })?
+        };
+
+        if body.len() + data.len() > max_size {
+            fail!(
+                FileTooLarge,
+                "reassembled file too large (max {}-bytes)",
+                max_size
+            );
+        }
+
+        body.extend_from_slice(data);
+    }
+
+    Ok(body)
+}
+
+/// Find the end of the chunk starting at the beginning of `data`
+fn next_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let mut hash = 0u64;
+
+    for (i, &byte) in data[..end].iter().enumerate() {
+        hash = (hash << 1).wrapping_add(gear(byte));
+
+        if i >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+
+    end
+}
+
+/// Pseudorandom value for each byte, mixed into the rolling hash
+/// (SplitMix64 of the byte, so it's stable across builds)
+fn gear(byte: u8) -> u64 {
+    let mut z = u64::from(byte).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// SHA-256 digest of a chunk, used to find matching chunks
+fn digest(data: &[u8]) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    result.copy_from_slice(Sha256::digest(data).as_slice());
+    result
+}
+
+/// Add a range of the base version, extending the previous chunk if the
+/// ranges are contiguous
+fn push_range(chunks: &mut Vec<Chunk>, range: &Range<usize>) {
+    if let Some(last) = chunks.last_mut() {
+        if last.base_length != 0 && (last.base_offset + last.base_length) as usize == range.start {
+            last.base_length += range.len() as u64;
+            return;
+        }
+    }
+
+    chunks.push(Chunk {
+        base_offset: range.start as u64,
+        base_length: range.len() as u64,
+        data: vec![],
+    });
+}
+
+/// Add new data, extending the previous chunk if it's also new data
+fn push_data(chunks: &mut Vec<Chunk>, data: &[u8]) {
+    if let Some(last) = chunks.last_mut() {
+        if last.base_length == 0 {
+            last.data.extend_from_slice(data);
+            return;
+        }
+    }
+
+    chunks.push(Chunk {
+        base_offset: 0,
+        base_length: 0,
+        data: data.to_vec(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{boundaries, encode, reassemble, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+
+    /// Pseudorandom (but deterministic) test data
+    fn example_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let data = example_data(200_000, 1);
+        let chunks = boundaries(&data);
+
+        assert_eq!(chunks.first().unwrap().start, 0);
+        assert_eq!(chunks.last().unwrap().end, data.len());
+
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+            assert!(pair[0].len() >= MIN_CHUNK_SIZE && pair[0].len() <= MAX_CHUNK_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_encode_and_reassemble() {
+        let base = example_data(200_000, 2);
+
+        // Insert some new data in the middle of the file
+        let mut body = base[..100_000].to_vec();
+        body.extend_from_slice(b"a small edit");
+        body.extend_from_slice(&base[100_000..]);
+
+        let chunks = encode(&base, &body);
+        let new_data: usize = chunks.iter().map(|chunk| chunk.data.len()).sum();
+        assert!(new_data < body.len() / 4);
+
+        assert_eq!(reassemble(&base, &chunks, body.len()).unwrap(), body);
+        assert!(reassemble(&base, &chunks, body.len() - 1).is_err());
+        assert!(reassemble(&base[..1000], &chunks, body.len()).is_err());
+    }
+}
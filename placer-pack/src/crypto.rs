@@ -1,9 +1,15 @@
 //! Cryptographic functionality
 
+mod encryption_key;
 mod encryptor;
+#[cfg(feature = "keyrings")]
+pub mod random;
 mod signing;
 
-pub use self::encryptor::{Encryptor, ENCRYPTION_KEY_SIZE};
+pub use self::encryption_key::EncryptionKey;
+pub use self::encryptor::{Encryptor, ENCRYPTION_KEY_SIZE, TAG_SIZE};
 #[cfg(feature = "signer")]
 pub use self::signing::Signer;
-pub use self::signing::{PublicKey, SIGNING_KEY_SIZE};
+pub use self::signing::{
+    PublicKey, SignaturePolicy, SignatureScheme, ED25519PH_CONTEXT, SIGNING_KEY_SIZE,
+};
@@ -0,0 +1,97 @@
+//! Decoded symmetric encryption keys
+
+use clear_on_drop::clear::Clear;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::encryptor::{Encryptor, ENCRYPTION_KEY_SIZE};
+use crate::error::Error;
+use crate::keyuri::{self, bech32k, ENCRYPTION_KEY_PREFIX};
+
+/// A decoded encryption key, from which per-message `Encryptor`s are derived.
+///
+/// Decoding and fingerprinting a KeyURI only needs to happen once, after
+/// which deriving an `Encryptor` for a given salt is just HKDF. The HKDF
+/// pseudorandom key (PRK) can't be computed up front: HKDF-Extract uses the
+/// salt (i.e. each pack's UUID) as its HMAC key, so the PRK differs for every
+/// pack. It's wiped as soon as the `Encryptor`'s keys are expanded from it.
+pub struct EncryptionKey {
+    bytes: [u8; ENCRYPTION_KEY_SIZE],
+    fingerprint: String,
+}
+
+impl EncryptionKey {
+    /// Decode an encryption key from a secret KeyURI
+    pub fn from_keyuri(secret_keyuri: &str) -> Result<Self, Error> {
+        let fingerprint = keyuri::fingerprint(secret_keyuri);
+        let (prefix, mut decoded_key) = bech32k::decode(secret_keyuri)?;
+
+        if prefix != ENCRYPTION_KEY_PREFIX {
+            decoded_key.as_mut_slice().clear();
+            fail!(InvalidKey, "invalid encryption key prefix: {}", prefix);
+        }
+
+        if decoded_key.len() != ENCRYPTION_KEY_SIZE {
+            let len = decoded_key.len();
+            decoded_key.as_mut_slice().clear();
+            fail!(
+                InvalidKey,
+                "bad length for {}: {} (expected {})",
+                prefix,
+                len,
+                ENCRYPTION_KEY_SIZE
+            );
+        }
+
+        let mut bytes = [0u8; ENCRYPTION_KEY_SIZE];
+        bytes.copy_from_slice(&decoded_key);
+        decoded_key.as_mut_slice().clear();
+
+        Ok(Self { bytes, fingerprint })
+    }
+
+    /// Obtain SHA-256 KeyURI fingerprint
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Derive an `Encryptor` from this key using the given salt
+    pub fn encryptor(&self, salt: &[u8]) -> Encryptor {
+        let mut hkdf = Hkdf::<Sha256>::extract(salt, &self.bytes);
+
+        // We need 2 * AES keys for AES-SIV
+        let mut expanded_key =
+            hkdf.expand(ENCRYPTION_KEY_PREFIX.as_bytes(), ENCRYPTION_KEY_SIZE * 2);
+        hkdf.prk.as_mut_slice().clear();
+
+        let encryptor = Encryptor::from_expanded_key(&expanded_key, self.fingerprint.clone());
+        expanded_key.as_mut_slice().clear();
+
+        encryptor
+    }
+}
+
+impl Drop for EncryptionKey {
+    fn drop(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EncryptionKey;
+    use crate::crypto::{Encryptor, ENCRYPTION_KEY_SIZE};
+    use crate::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX};
+
+    #[test]
+    fn test_encryptor_matches_keyuri() {
+        let keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[1u8; ENCRYPTION_KEY_SIZE]);
+        let key = EncryptionKey::from_keyuri(&keyuri).unwrap();
+
+        let mut encryptor = Encryptor::from_keyuri(&keyuri, b"salt").unwrap();
+        assert_eq!(key.fingerprint(), encryptor.fingerprint());
+
+        let ciphertext = key.encryptor(b"salt").seal(&[b"ad"], b"plaintext");
+        assert_eq!(encryptor.open(&[b"ad"], &ciphertext).unwrap(), b"plaintext");
+    }
+}
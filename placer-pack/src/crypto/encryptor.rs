@@ -2,15 +2,16 @@
 
 use aes_siv::{aead::generic_array::GenericArray, siv::Aes256Siv};
 use clear_on_drop::clear::Clear;
-use hkdf::Hkdf;
-use sha2::Sha256;
 
+use super::encryption_key::EncryptionKey;
 use crate::error::Error;
-use crate::keyuri::{self, bech32k, ENCRYPTION_KEY_PREFIX};
 
 /// Size of an AES-256 key in bytes (which we expand to 2 * AES-256 keys with HKDF-SHA-256)
 pub const ENCRYPTION_KEY_SIZE: usize = 32;
 
+/// Size of an AES-SIV synthetic IV (i.e. authentication tag) in bytes
+pub const TAG_SIZE: usize = 16;
+
 /// A symmetric encryptor (providing AES-256-SIV)
 pub struct Encryptor {
     algorithm: Aes256Siv,
@@ -20,45 +21,30 @@ pub struct Encryptor {
 impl Encryptor {
     /// Create an encryptor from a secret key encoded as a KeyURI
     pub fn from_keyuri(secret_keyuri: &str, salt: &[u8]) -> Result<Self, Error> {
-        let fingerprint = keyuri::fingerprint(secret_keyuri);
-        let (prefix, mut decoded_key) = bech32k::decode(secret_keyuri)?;
-
-        if prefix != ENCRYPTION_KEY_PREFIX {
-            fail!(InvalidKey, "invalid encryption key prefix: {}", prefix);
-        }
-
-        if decoded_key.len() != ENCRYPTION_KEY_SIZE {
-            fail!(
-                InvalidKey,
-                "bad length for {}: {} (expected {})",
-                prefix,
-                decoded_key.len(),
-                ENCRYPTION_KEY_SIZE
-            );
-        }
-
-        // TODO: support salts?
-        let hkdf = Hkdf::<Sha256>::extract(salt, &decoded_key);
-        decoded_key.as_mut_slice().clear();
-
-        // We need 2 * AES keys for AES-SIV
-        let mut expanded_key =
-            hkdf.expand(ENCRYPTION_KEY_PREFIX.as_bytes(), ENCRYPTION_KEY_SIZE * 2);
-
-        let algorithm = Aes256Siv::new(GenericArray::clone_from_slice(&expanded_key));
-        expanded_key.as_mut_slice().clear();
+        Ok(EncryptionKey::from_keyuri(secret_keyuri)?.encryptor(salt))
+    }
 
-        Ok(Self {
-            algorithm,
+    /// Create an encryptor from HKDF-expanded key material (2 * AES-256 keys)
+    pub(crate) fn from_expanded_key(expanded_key: &[u8], fingerprint: String) -> Self {
+        Self {
+            algorithm: Aes256Siv::new(GenericArray::clone_from_slice(expanded_key)),
             fingerprint,
-        })
+        }
     }
 
-    /// Obtain SHA-256 KeyURI fingerprint
+    /// Obtain KeyURI fingerprint (SHA-256, unless this encryptor was looked
+    /// up by a fingerprint computed with another algorithm)
     pub fn fingerprint(&self) -> &str {
         &self.fingerprint
     }
 
+    /// Replace the fingerprint of this encryptor's key with one computed
+    /// using another algorithm
+    pub(crate) fn with_fingerprint(mut self, fingerprint: String) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
     /// Encrypt the message using the underlying encryption algorithm (AES-256-SIV)
     pub fn seal<I, T>(&mut self, associated_data: I, plaintext: &[u8]) -> Vec<u8>
     where
@@ -80,4 +66,97 @@ impl Encryptor {
             .decrypt(associated_data, ciphertext)
             .map_err(|e| err!(Crypto, "{}", e))
     }
+
+    /// Encrypt the message, returning the authentication tag and ciphertext separately
+    pub fn seal_detached<I, T>(
+        &mut self,
+        associated_data: I,
+        plaintext: &[u8],
+    ) -> ([u8; TAG_SIZE], Vec<u8>)
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let mut ciphertext = plaintext.to_vec();
+        let tag = self
+            .algorithm
+            .encrypt_in_place_detached(associated_data, &mut ciphertext)
+            .expect("SIV error");
+
+        let mut result = [0u8; TAG_SIZE];
+        result.copy_from_slice(&tag);
+        (result, ciphertext)
+    }
+
+    /// Decrypt a message whose authentication tag is stored separately from its ciphertext
+    pub fn open_detached<I, T>(
+        &mut self,
+        associated_data: I,
+        tag: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        if tag.len() != TAG_SIZE {
+            fail!(
+                Crypto,
+                "invalid tag size: {} (expected {})",
+                tag.len(),
+                TAG_SIZE
+            );
+        }
+
+        let mut plaintext = ciphertext.to_vec();
+
+        if let Err(e) = self.algorithm.decrypt_in_place_detached(
+            associated_data,
+            &mut plaintext,
+            GenericArray::from_slice(tag),
+        ) {
+            plaintext.as_mut_slice().clear();
+            fail!(Crypto, "{}", e);
+        }
+
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encryptor, ENCRYPTION_KEY_SIZE, TAG_SIZE};
+    use crate::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX};
+
+    const EXAMPLE_AD: &[&[u8]] = &[b"associated data"];
+    const EXAMPLE_PLAINTEXT: &[u8] = b"attack at dawn";
+
+    fn encryptor() -> Encryptor {
+        let keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[42u8; ENCRYPTION_KEY_SIZE]);
+        Encryptor::from_keyuri(&keyuri, b"salt").unwrap()
+    }
+
+    #[test]
+    fn test_detached_roundtrip() {
+        let (tag, ciphertext) = encryptor().seal_detached(EXAMPLE_AD, EXAMPLE_PLAINTEXT);
+
+        // Detached output matches the combined (tag || ciphertext) format
+        let combined = encryptor().seal(EXAMPLE_AD, EXAMPLE_PLAINTEXT);
+        assert_eq!(&combined[..TAG_SIZE], &tag[..]);
+        assert_eq!(&combined[TAG_SIZE..], &ciphertext[..]);
+
+        let plaintext = encryptor()
+            .open_detached(EXAMPLE_AD, &tag, &ciphertext)
+            .unwrap();
+        assert_eq!(plaintext, EXAMPLE_PLAINTEXT);
+    }
+
+    #[test]
+    fn test_detached_bad_tag() {
+        let (mut tag, ciphertext) = encryptor().seal_detached(EXAMPLE_AD, EXAMPLE_PLAINTEXT);
+        tag[0] ^= 1;
+        assert!(encryptor()
+            .open_detached(EXAMPLE_AD, &tag, &ciphertext)
+            .is_err());
+    }
 }
@@ -0,0 +1,25 @@
+//! Random number generation (using the OS RNG)
+
+use crate::error::Error;
+use rand::{OsRng, Rng};
+
+/// Fill the given buffer with random bytes from the OS RNG
+pub fn fill(buffer: &mut [u8]) -> Result<(), Error> {
+    let mut rng =
+        OsRng::new().map_err(|e| err!(Crypto, "OS random number generator failure! {}", e))?;
+
+    rng.fill_bytes(buffer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fill;
+
+    #[test]
+    fn test_fill() {
+        let mut buffer = [0u8; 32];
+        fill(&mut buffer).unwrap();
+        assert_ne!(buffer, [0u8; 32]);
+    }
+}
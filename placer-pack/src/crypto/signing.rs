@@ -1,12 +1,19 @@
 //! Pack signing
 //!
-//! Presently supports Ed25519 as a digital signature algorithm
+//! Supports Ed25519 as a digital signature algorithm, either over the whole
+//! message or (as Ed25519ph) over its SHA-512 prehash
 
+mod policy;
 mod public_key;
+mod scheme;
 #[cfg(feature = "signer")]
 mod signer;
+#[cfg(all(test, feature = "signer"))]
+mod test_vectors;
 
+pub use self::policy::SignaturePolicy;
 pub use self::public_key::{PublicKey, PUBLIC_KEY_SIZE};
+pub use self::scheme::{SignatureScheme, ED25519PH_CONTEXT};
 #[cfg(feature = "signer")]
 pub use self::signer::Signer;
 
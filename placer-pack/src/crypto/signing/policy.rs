@@ -0,0 +1,112 @@
+//! k-of-n multi-signature policies
+
+use super::PublicKey;
+use crate::error::Error;
+use std::collections::BTreeSet;
+
+/// Policy requiring a pack to be signed by at least `threshold` distinct
+/// keys out of a set of authorized signing keys
+#[derive(Clone, Debug)]
+pub struct SignaturePolicy {
+    /// Minimum number of distinct authorized keys which must have signed
+    threshold: usize,
+
+    /// Keys authorized to sign packs under this policy
+    authorized_keys: Vec<PublicKey>,
+}
+
+impl SignaturePolicy {
+    /// Create a new policy, requiring `threshold` of the given keys to sign
+    pub fn new(threshold: usize, authorized_keys: Vec<PublicKey>) -> Result<Self, Error> {
+        if threshold == 0 || threshold > authorized_keys.len() {
+            fail!(
+                InvalidKey,
+                "invalid signature threshold: {} (must be 1-{})",
+                threshold,
+                authorized_keys.len()
+            );
+        }
+
+        Ok(Self {
+            threshold,
+            authorized_keys,
+        })
+    }
+
+    /// Get the number of signatures required by this policy
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Verify the given `(fingerprint, signature)` pairs over a message,
+    /// succeeding only if at least `threshold` distinct authorized keys
+    /// produced a valid signature. Signatures by unauthorized keys and
+    /// duplicate signatures by the same key are ignored.
+    pub fn verify<'a, I>(&self, message: &[u8], signatures: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (&'a str, &'a [u8])>,
+    {
+        let mut verified = BTreeSet::new();
+
+        for (fingerprint, signature) in signatures {
+            let key = self
+                .authorized_keys
+                .iter()
+                .find(|key| key.matches_fingerprint(fingerprint));
+
+            // Signatures are counted by key (rather than by fingerprint), so
+            // the same key can't be counted twice under different algorithms
+            if let Some(key) = key {
+                if key.verify(message, signature).is_ok() {
+                    verified.insert(key.to_fingerprint());
+                }
+            }
+        }
+
+        if verified.len() < self.threshold {
+            fail!(
+                Signature,
+                "only {} of {} required signatures verified",
+                verified.len(),
+                self.threshold
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "signer"))]
+mod tests {
+    use super::SignaturePolicy;
+    use crate::crypto::Signer;
+
+    #[test]
+    fn test_threshold() {
+        let signers: Vec<_> = (1..=3u8)
+            .map(|i| Signer::from_bytes(&[i; 32]).unwrap())
+            .collect();
+        let keys: Vec<_> = signers.iter().map(|s| s.public_key().unwrap()).collect();
+        let policy = SignaturePolicy::new(2, keys.clone()).unwrap();
+
+        let fingerprints: Vec<_> = keys.iter().map(|k| k.to_fingerprint()).collect();
+        let signatures: Vec<_> = signers
+            .iter()
+            .map(|s| s.sign(b"ciphertext").unwrap().as_ref().to_vec())
+            .collect();
+
+        let one = vec![(fingerprints[0].as_str(), signatures[0].as_slice())];
+        assert!(policy.verify(b"ciphertext", one.clone()).is_err());
+
+        // The same key signing twice only counts once
+        let duplicate = vec![one[0], one[0]];
+        assert!(policy.verify(b"ciphertext", duplicate).is_err());
+
+        let two = vec![one[0], (fingerprints[2].as_str(), signatures[2].as_slice())];
+        assert!(policy.verify(b"ciphertext", two.clone()).is_ok());
+        assert!(policy.verify(b"tampered", two).is_err());
+
+        assert!(SignaturePolicy::new(0, keys.clone()).is_err());
+        assert!(SignaturePolicy::new(4, keys).is_err());
+    }
+}
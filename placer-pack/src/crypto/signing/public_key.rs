@@ -1,7 +1,11 @@
 //! Ed25519 public keys
 
+use super::{SignatureScheme, ED25519PH_CONTEXT};
 use crate::error::Error;
-use crate::keyuri::{bech32k, fingerprint, VERIFY_KEY_PREFIX};
+use crate::keyuri::{
+    bech32k, fingerprint, fingerprint_matches, fingerprint_with, FingerprintAlgorithm,
+};
+use ed25519_dalek::{Digest, Sha512};
 use signatory::{ed25519, signature::Verifier};
 use std::convert::TryFrom;
 
@@ -13,16 +17,21 @@ pub const SIGNATURE_SIZE: usize = 64;
 
 /// Ed25519 public key
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct PublicKey([u8; PUBLIC_KEY_SIZE]);
+pub struct PublicKey {
+    /// Raw Ed25519 public key
+    bytes: [u8; PUBLIC_KEY_SIZE],
+
+    /// Signature scheme this key verifies
+    scheme: SignatureScheme,
+}
 
 impl PublicKey {
     /// Create a public key from a KeyURI
     pub fn from_keyuri(keyuri: &str) -> Result<Self, Error> {
         let (prefix, bytes) = bech32k::decode(keyuri)?;
 
-        if prefix != VERIFY_KEY_PREFIX {
-            fail!(InvalidKey, "invalid encryption key prefix: {}", prefix);
-        }
+        let scheme = SignatureScheme::from_verify_key_prefix(&prefix)
+            .ok_or_else(|| err!(InvalidKey, "invalid verify key prefix: {}", prefix))?;
 
         if bytes.len() != PUBLIC_KEY_SIZE {
             fail!(
@@ -33,21 +42,32 @@ impl PublicKey {
             );
         }
 
+        Ok(Self::from_bytes(&bytes, scheme))
+    }
+
+    /// Create a public key for the given scheme from its raw bytes
+    pub(crate) fn from_bytes(bytes: &[u8], scheme: SignatureScheme) -> Self {
         let mut key = [0u8; PUBLIC_KEY_SIZE];
-        key.copy_from_slice(&bytes);
+        key.copy_from_slice(bytes);
 
-        Ok(PublicKey(key))
+        PublicKey { bytes: key, scheme }
     }
 
     /// Borrow the bytes of the public key as a slice
     #[inline]
     pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_SIZE] {
-        &self.0
+        &self.bytes
+    }
+
+    /// Get the signature scheme this key verifies
+    #[inline]
+    pub fn scheme(&self) -> SignatureScheme {
+        self.scheme
     }
 
     /// Serialize this public key as a KeyURI
     pub fn to_keyuri(&self) -> String {
-        bech32k::encode(VERIFY_KEY_PREFIX, self.as_bytes())
+        bech32k::encode(self.scheme.verify_key_prefix(), self.as_bytes())
     }
 
     /// Create a public key fingerprint for this public key as a KeyURI
@@ -55,29 +75,54 @@ impl PublicKey {
         fingerprint(&self.to_keyuri())
     }
 
-    /// Verify a message with this key
+    /// Create a public key fingerprint using the given fingerprint algorithm
+    pub fn to_fingerprint_with(&self, algorithm: FingerprintAlgorithm) -> String {
+        fingerprint_with(algorithm, &self.to_keyuri())
+    }
+
+    /// Does the given fingerprint (computed with any supported algorithm)
+    /// identify this public key?
+    pub fn matches_fingerprint(&self, fingerprint: &str) -> bool {
+        fingerprint_matches(&self.to_keyuri(), fingerprint)
+    }
+
+    /// Verify a message with this key, using the key's signature scheme
     pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), Error> {
         let sig = ed25519::Signature::try_from(signature).map_err(|_| {
             err!(
-                Crypto,
+                Signature,
                 "invalid signature size: {} (expected {})",
                 signature.len(),
                 SIGNATURE_SIZE
             )
         })?;
 
-        ed25519::VerifyingKey::from_bytes(&self.0)
-            .unwrap()
-            .verify(message, &sig)
-            .map_err(|_| err!(Crypto, "signature verification failed!"))
+        let verified = match self.scheme {
+            SignatureScheme::Ed25519 => ed25519::VerifyingKey::from_bytes(&self.bytes)
+                .unwrap()
+                .verify(message, &sig)
+                .is_ok(),
+            SignatureScheme::Ed25519ph => {
+                let mut prehash = Sha512::new();
+                prehash.update(message);
+
+                ed25519_dalek::PublicKey::from_bytes(&self.bytes)
+                    .and_then(|key| key.verify_prehashed(prehash, Some(ED25519PH_CONTEXT), &sig))
+                    .is_ok()
+            }
+        };
+
+        if !verified {
+            fail!(Signature, "signature verification failed!");
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(feature = "signer")]
 impl From<ed25519::VerifyingKey> for PublicKey {
     fn from(key: ed25519::VerifyingKey) -> PublicKey {
-        let mut result = [0u8; PUBLIC_KEY_SIZE];
-        result.copy_from_slice(key.as_ref());
-        PublicKey(result)
+        PublicKey::from_bytes(key.as_ref(), SignatureScheme::Ed25519)
     }
 }
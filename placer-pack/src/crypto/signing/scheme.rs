@@ -0,0 +1,56 @@
+//! Digital signature schemes, selected by KeyURI prefix
+
+use crate::keyuri::{
+    SIGNING_KEY_PREFIX, SIGNING_KEY_PREHASH_PREFIX, VERIFY_KEY_PREFIX, VERIFY_KEY_PREHASH_PREFIX,
+};
+
+/// Context string for Ed25519ph signatures, binding the scheme (and its
+/// use for placer packs) into the signed data
+pub const ED25519PH_CONTEXT: &[u8] = b"placer-pack:ed25519ph";
+
+/// Signature scheme used to sign packs
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SignatureScheme {
+    /// Ed25519 over the entire ciphertext
+    Ed25519,
+
+    /// Ed25519ph (RFC 8032) over the SHA-512 prehash of the ciphertext,
+    /// allowing it to be signed/verified incrementally
+    Ed25519ph,
+}
+
+impl SignatureScheme {
+    /// Find the scheme for the given verify key KeyURI prefix
+    pub fn from_verify_key_prefix(prefix: &str) -> Option<Self> {
+        match prefix {
+            VERIFY_KEY_PREFIX => Some(SignatureScheme::Ed25519),
+            VERIFY_KEY_PREHASH_PREFIX => Some(SignatureScheme::Ed25519ph),
+            _ => None,
+        }
+    }
+
+    /// Find the scheme for the given signing key KeyURI prefix
+    pub fn from_signing_key_prefix(prefix: &str) -> Option<Self> {
+        match prefix {
+            SIGNING_KEY_PREFIX => Some(SignatureScheme::Ed25519),
+            SIGNING_KEY_PREHASH_PREFIX => Some(SignatureScheme::Ed25519ph),
+            _ => None,
+        }
+    }
+
+    /// KeyURI prefix for verify keys of this scheme
+    pub fn verify_key_prefix(self) -> &'static str {
+        match self {
+            SignatureScheme::Ed25519 => VERIFY_KEY_PREFIX,
+            SignatureScheme::Ed25519ph => VERIFY_KEY_PREHASH_PREFIX,
+        }
+    }
+
+    /// KeyURI prefix for signing keys of this scheme
+    pub fn signing_key_prefix(self) -> &'static str {
+        match self {
+            SignatureScheme::Ed25519 => SIGNING_KEY_PREFIX,
+            SignatureScheme::Ed25519ph => SIGNING_KEY_PREHASH_PREFIX,
+        }
+    }
+}
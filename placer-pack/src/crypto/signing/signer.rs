@@ -1,27 +1,93 @@
 use crate::error::Error;
+use ed25519_dalek::{Digest, Sha512};
 use signatory::{
     ed25519::{Signature, SigningKey},
     signature::Signer as _,
 };
 
-use super::PublicKey;
+use super::{PublicKey, SignatureScheme, ED25519PH_CONTEXT};
 
 /// Pack signer (using Ed25519 digital signature algorithm)
-pub struct Signer(SigningKey);
+pub enum Signer {
+    /// Ed25519 over the entire message
+    Ed25519(SigningKey),
+
+    /// Ed25519ph over the SHA-512 prehash of the message
+    Ed25519ph(ed25519_dalek::Keypair),
+}
 
 impl Signer {
     /// Software-backed signer based on ed25519-dalek
     pub fn from_bytes(seed: &[u8]) -> Result<Self, Error> {
-        Ok(Signer(SigningKey::from_bytes(seed).unwrap()))
+        Self::from_bytes_with_scheme(seed, SignatureScheme::Ed25519)
+    }
+
+    /// Software-backed signer for the given signature scheme
+    pub fn from_bytes_with_scheme(seed: &[u8], scheme: SignatureScheme) -> Result<Self, Error> {
+        match scheme {
+            SignatureScheme::Ed25519 => Ok(Signer::Ed25519(SigningKey::from_bytes(seed).unwrap())),
+            SignatureScheme::Ed25519ph => {
+                let secret = ed25519_dalek::SecretKey::from_bytes(seed)
+                    .map_err(|e| err!(InvalidKey, "invalid Ed25519ph key: {}", e))?;
+                let public = ed25519_dalek::PublicKey::from(&secret);
+                Ok(Signer::Ed25519ph(ed25519_dalek::Keypair { secret, public }))
+            }
+        }
+    }
+
+    /// Get the signature scheme used by this signer
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Signer::Ed25519(_) => SignatureScheme::Ed25519,
+            Signer::Ed25519ph(_) => SignatureScheme::Ed25519ph,
+        }
     }
 
     /// Obtain public key for this signer
     pub fn public_key(&self) -> Result<PublicKey, Error> {
-        Ok(self.0.verifying_key().into())
+        match self {
+            Signer::Ed25519(key) => Ok(key.verifying_key().into()),
+            Signer::Ed25519ph(keypair) => Ok(PublicKey::from_bytes(
+                keypair.public.as_bytes(),
+                SignatureScheme::Ed25519ph,
+            )),
+        }
     }
 
     /// Sign a message
     pub fn sign(&self, msg: &[u8]) -> Result<Signature, Error> {
-        Ok(self.0.sign(msg))
+        match self {
+            Signer::Ed25519(key) => Ok(key.sign(msg)),
+            Signer::Ed25519ph(keypair) => {
+                let mut prehash = Sha512::new();
+                prehash.update(msg);
+
+                keypair
+                    .sign_prehashed(prehash, Some(ED25519PH_CONTEXT))
+                    .map_err(|e| err!(Crypto, "Ed25519ph signing failed: {}", e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Signer;
+    use crate::crypto::{PublicKey, SignatureScheme};
+
+    #[test]
+    fn test_ed25519ph_sign_and_verify() {
+        let signer =
+            Signer::from_bytes_with_scheme(&[1u8; 32], SignatureScheme::Ed25519ph).unwrap();
+        let public_key = signer.public_key().unwrap();
+        let signature = signer.sign(b"ciphertext").unwrap();
+
+        assert!(public_key.to_keyuri().starts_with("public.key:ed25519ph;"));
+        assert!(public_key.verify(b"ciphertext", signature.as_ref()).is_ok());
+        assert!(public_key.verify(b"tampered", signature.as_ref()).is_err());
+
+        // The same key can't be used to reinterpret the signature as plain Ed25519
+        let plain_key = PublicKey::from_bytes(public_key.as_bytes(), SignatureScheme::Ed25519);
+        assert!(plain_key.verify(b"ciphertext", signature.as_ref()).is_err());
     }
 }
@@ -0,0 +1,93 @@
+//! Ed25519 known-answer tests from RFC 8032 (section 7.1)
+
+use super::{PublicKey, SignatureScheme, Signer};
+
+/// RFC 8032 test vector
+struct TestVector {
+    /// Secret key (i.e. seed), hex-encoded
+    secret_key: &'static str,
+
+    /// Public key, hex-encoded
+    public_key: &'static str,
+
+    /// Message, hex-encoded
+    message: &'static str,
+
+    /// Signature, hex-encoded
+    signature: &'static str,
+}
+
+/// Ed25519 test vectors (TEST 1-3)
+const TEST_VECTORS: &[TestVector] = &[
+    TestVector {
+        secret_key: "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        public_key: "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        message: "",
+        signature: "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555\
+                    fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+    },
+    TestVector {
+        secret_key: "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        public_key: "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        message: "72",
+        signature: "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da0\
+                    85ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+    },
+    TestVector {
+        secret_key: "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+        public_key: "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+        message: "af82",
+        signature: "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac1\
+                    8ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+    },
+];
+
+/// Decode a hex string
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_rfc8032_sign() {
+    for vector in TEST_VECTORS {
+        let signer = Signer::from_bytes(&hex(vector.secret_key)).unwrap();
+        let signature = signer.sign(&hex(vector.message)).unwrap();
+        let signature_bytes: &[u8] = signature.as_ref();
+
+        assert_eq!(signature_bytes, hex(vector.signature).as_slice());
+        assert_eq!(
+            &signer.public_key().unwrap().as_bytes()[..],
+            hex(vector.public_key).as_slice()
+        );
+    }
+}
+
+#[test]
+fn test_rfc8032_verify() {
+    for vector in TEST_VECTORS {
+        let public_key = PublicKey::from_bytes(&hex(vector.public_key), SignatureScheme::Ed25519);
+        let message = hex(vector.message);
+        let mut signature = hex(vector.signature);
+
+        assert!(public_key.verify(&message, &signature).is_ok());
+
+        signature[0] ^= 1;
+        assert!(public_key.verify(&message, &signature).is_err());
+    }
+}
+
+#[test]
+fn test_keyuri_round_trip() {
+    for vector in TEST_VECTORS {
+        let public_key = Signer::from_bytes(&hex(vector.secret_key))
+            .unwrap()
+            .public_key()
+            .unwrap();
+
+        let keyuri = public_key.to_keyuri();
+        assert_eq!(PublicKey::from_keyuri(&keyuri).unwrap(), public_key);
+    }
+}
@@ -2,12 +2,13 @@
 
 use crate::keyuri::bech32k;
 use std::io;
+use thiserror::Error;
 
 /// placer-pack's error type
-#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+#[derive(Clone, Eq, PartialEq, Debug, Error)]
 pub enum Error {
     /// Error in configuration file
-    #[fail(display = "invalid config file: {}", description)]
+    #[error("invalid config file: {description}")]
     #[allow(clippy::enum_variant_names)]
     Config {
         /// Description of the error
@@ -15,39 +16,74 @@ pub enum Error {
     },
 
     /// Error in a cryptographic algorithm or provider
-    #[fail(display = "{}", description)]
+    #[error("{description}")]
     Crypto {
         /// Description of the error
         description: String,
     },
 
     /// Malformatted or otherwise invalid cryptographic key
-    #[fail(display = "{}", description)]
+    #[error("{description}")]
     InvalidKey {
         /// Description of the error
         description: String,
     },
 
+    /// Delta pack can't be applied to the given base pack
+    #[error("{description}")]
+    Delta {
+        /// Description of the error
+        description: String,
+    },
+
     /// Input/output error
-    #[fail(display = "{}", description)]
+    #[error("{description}")]
     Io {
         /// Description of the error
         description: String,
     },
 
     /// Error parsing data
-    #[fail(display = "{}", description)]
+    #[error("{description}")]
     Parse {
         /// Description of the error
         description: String,
     },
 
     /// Error serializing a value
-    #[fail(display = "{}", description)]
+    #[error("{description}")]
     Serialization {
         /// Description of the error
         description: String,
     },
+
+    /// Pack contains a file larger than the per-file limit
+    #[error("{description}")]
+    FileTooLarge {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Invalid digital signature
+    #[error("{description}")]
+    Signature {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Timestamp outside the acceptable range (e.g. in the future)
+    #[error("{description}")]
+    Timestamp {
+        /// Description of the error
+        description: String,
+    },
+
+    /// Pack contains more files than the limit
+    #[error("{description}")]
+    TooManyFiles {
+        /// Description of the error
+        description: String,
+    },
 }
 
 impl From<io::Error> for Error {
@@ -0,0 +1,54 @@
+//! Looking up the keys needed to verify and decrypt a pack
+
+use crate::crypto::{Encryptor, PublicKey};
+use crate::error::Error;
+use crate::pack::Fingerprints;
+use uuid::Uuid;
+
+/// Source of the keys used to verify and decrypt packs
+pub trait KeyLookup {
+    /// Look up the signing public key and encryptor for the given key
+    /// fingerprints and pack UUID (used as the encryption salt), returning an
+    /// error describing why they couldn't be found if they aren't available
+    fn lookup(
+        &self,
+        fingerprints: &Fingerprints,
+        uuid: &Uuid,
+    ) -> Result<(PublicKey, Encryptor), Error>;
+}
+
+/// Closures returning `Option` can be used as key lookups, although any
+/// context about why a lookup failed is lost
+impl<F> KeyLookup for F
+where
+    F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)>,
+{
+    fn lookup(
+        &self,
+        fingerprints: &Fingerprints,
+        uuid: &Uuid,
+    ) -> Result<(PublicKey, Encryptor), Error> {
+        self(fingerprints, uuid).ok_or_else(|| err!(InvalidKey, "key lookup failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyLookup;
+    use crate::crypto::{Encryptor, PublicKey};
+    use crate::pack::Fingerprints;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_closure_lookup() {
+        let lookup = |_: &Fingerprints, _: &Uuid| -> Option<(PublicKey, Encryptor)> { None };
+
+        let fingerprints = Fingerprints {
+            signing_key: "signing".to_owned(),
+            encryption_key: "encryption".to_owned(),
+        };
+
+        let error = lookup.lookup(&fingerprints, &Uuid::nil()).err().unwrap();
+        assert_eq!(error, err!(InvalidKey, "key lookup failed"));
+    }
+}
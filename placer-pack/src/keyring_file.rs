@@ -0,0 +1,73 @@
+//! Reading keyring configuration files: shared by placer-pack's keyrings and
+//! the placer daemon's, whose keys differ but which have the same sections
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Sections every keyring configuration must contain (even if empty)
+pub const SECTIONS: &[&str] = &["signing", "encryption"];
+
+/// Read a keyring configuration file, which must be valid UTF-8
+pub fn read_to_string(file: &mut File, filename: &Path) -> Result<String, Error> {
+    let mut data = String::new();
+
+    file.read_to_string(&mut data).map_err(|e| {
+        // read_to_string reports invalid UTF-8 as `InvalidData`
+        if e.kind() == io::ErrorKind::InvalidData {
+            err!(Config, "{} isn't valid UTF-8", filename.to_string_lossy())
+        } else {
+            err!(Io, "couldn't read {}: {}", filename.to_string_lossy(), e)
+        }
+    })?;
+
+    Ok(data)
+}
+
+/// If deserializing a keyring configuration failed because it's missing one
+/// of its sections, name the missing section (with an example of what the
+/// sections should contain) rather than leaving serde to report a bare
+/// "missing field"
+pub fn missing_section(filename: &Path, error: &dyn Display, example: &str) -> Option<Error> {
+    let message = error.to_string();
+
+    SECTIONS
+        .iter()
+        .find(|section| message.contains(&format!("missing field `{}`", section)))
+        .map(|section| {
+            err!(
+                Config,
+                "{} is missing its [{}] section (keyrings must contain both \
+                 sections, even if one is empty), e.g.:\n\n{}",
+                filename.to_string_lossy(),
+                section,
+                example
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::missing_section;
+    use std::path::Path;
+
+    #[test]
+    fn test_missing_section() {
+        let path = Path::new("keyring.toml");
+
+        let err = missing_section(path, &"missing field `encryption`", "[encryption]").unwrap();
+        assert!(err.to_string().contains("missing its [encryption] section"));
+        assert!(err.to_string().ends_with("e.g.:\n\n[encryption]"));
+
+        let err = missing_section(path, &"missing field `signing` at line 1 column 1", "");
+        assert!(err
+            .unwrap()
+            .to_string()
+            .contains("missing its [signing] section"));
+
+        assert!(missing_section(path, &"invalid type: integer `1`", "").is_none());
+    }
+}
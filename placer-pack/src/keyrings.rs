@@ -1,19 +1,22 @@
 //! Cryptographic keyrings
 
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
 use std::path::Path;
 
 mod config;
 mod encryption;
 mod signing;
 
-pub use self::config::DEFAULT_KEY_LABEL;
-use self::config::{Config, REQUIRED_FILE_PERMISSIONS};
+use self::config::Config;
+pub use self::config::{DEFAULT_KEY_LABEL, ENCRYPTION_KEY_ENV_VAR, SIGNING_KEY_ENV_VAR};
 use self::encryption::EncryptionKeyring;
 use self::signing::SigningKeyring;
+use crate::crypto::{Encryptor, PublicKey};
 use crate::error::Error;
+use crate::key_lookup::KeyLookup;
+use crate::keyuri::{self, normalize_fingerprint, FingerprintAlgorithm};
+use crate::pack::{Fingerprints, Uuid};
 
 /// Cryptographic keyrings
 pub struct Keyrings {
@@ -32,54 +35,380 @@ impl Keyrings {
 
     /// Load the keyring configuration from a file
     pub fn load(path: &Path) -> Result<Self, Error> {
-        let config = Config::load(path)?;
+        Self::from_config(&Config::load(path)?)
+    }
+
+    /// Load the default signing and encryption keys from the
+    /// `PLACER_SIGNING_KEY` and `PLACER_ENCRYPTION_KEY` environment variables
+    pub fn from_env() -> Result<Self, Error> {
+        Self::from_config(&Config::from_env()?)
+    }
+
+    /// Read the default signing and encryption keys (as whitespace-separated
+    /// KeyURIs) from the given reader, e.g. stdin
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        Self::from_config(&Config::from_reader(reader)?)
+    }
 
+    /// Create keyrings from a parsed configuration
+    fn from_config(config: &Config) -> Result<Self, Error> {
         Ok(Self {
             encryption: EncryptionKeyring::new(&config.encryption)?,
             signing: SigningKeyring::new(&config.signing)?,
         })
     }
 
+    /// Look up the keys needed to verify and decrypt a pack by their fingerprints
+    pub fn key_lookup(
+        &self,
+        fingerprints: &Fingerprints,
+        uuid: &Uuid,
+    ) -> Result<(PublicKey, Encryptor), Error> {
+        let public_key = self.signing.get_by_fingerprint(&fingerprints.signing_key)?;
+        let encryptor = self
+            .encryption
+            .get_by_fingerprint(&fingerprints.encryption_key, uuid.as_bytes())?;
+
+        Ok((*public_key, encryptor))
+    }
+
+    /// Get the (normalized) fingerprints of every key in these keyrings under
+    /// every supported fingerprint algorithm, as `(signing, encryption)`. Compare them with `required_fingerprints` to
+    /// check a keyring can verify and decrypt a set of packs before it's
+    /// deployed.
+    pub fn covered_fingerprints(&self) -> (BTreeSet<String>, BTreeSet<String>) {
+        let signing = self
+            .signing
+            .public_keys
+            .values()
+            .flat_map(|key| {
+                FingerprintAlgorithm::ALL.iter().map(move |&algorithm| {
+                    normalize_fingerprint(&key.to_fingerprint_with(algorithm))
+                })
+            })
+            .collect();
+
+        let encryption = self
+            .encryption
+            .keys
+            .values()
+            .flat_map(|key| {
+                FingerprintAlgorithm::ALL.iter().map(move |&algorithm| {
+                    normalize_fingerprint(&keyuri::fingerprint_with(algorithm, key))
+                })
+            })
+            .collect();
+
+        (signing, encryption)
+    }
+
     /// Export verifier keys to the given file
     pub fn export_verify_keys(&self, output: &Path) -> Result<(), Error> {
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .mode(REQUIRED_FILE_PERMISSIONS)
-            .open(output)
-            .map_err(|e| {
-                err!(
-                    Io,
-                    "couldn't open {} for writing: {}",
-                    output.to_string_lossy(),
-                    e
-                )
-            })?;
-
-        writeln!(
-            &mut file,
-            "# placer client keyring: contains pack signature verification keys"
-        )?;
-        writeln!(
-            &mut file,
-            "# Protect this file! It also contains pack decryption keys!\n"
-        )?;
-
-        // TODO: serde-powered serializer?
-        writeln!(&mut file, "[signing]")?;
-
-        for (label, signer) in &self.signing.keys {
-            let public_key = signer.public_key()?;
-            writeln!(&mut file, "{} = \"{}\"", label, public_key.to_keyuri())?;
+        let mut signing = BTreeMap::new();
+
+        for (label, public_key) in &self.signing.public_keys {
+            signing.insert(label.clone(), public_key.to_keyuri());
         }
 
-        writeln!(&mut file, "\n[encryption]")?;
+        let config = Config {
+            signing,
+            encryption: self.encryption.keys.clone(),
+        };
+
+        config.write(
+            output,
+            "# placer client keyring: contains pack signature verification keys\n\
+             # Protect this file! It also contains pack decryption keys!\n",
+        )
+    }
+}
+
+impl KeyLookup for Keyrings {
+    fn lookup(
+        &self,
+        fingerprints: &Fingerprints,
+        uuid: &Uuid,
+    ) -> Result<(PublicKey, Encryptor), Error> {
+        self.key_lookup(fingerprints, uuid)
+    }
+}
+
+#[cfg(all(test, feature = "signer"))]
+mod tests {
+    use super::{Config, Keyrings, DEFAULT_KEY_LABEL};
+    use crate::crypto::SignaturePolicy;
+    use crate::error::Error;
+    use crate::pack::{ChunkedFile, Pack, PackFile, PackVersion, Uuid};
+    use crate::protos::pack::Pack as PackProto;
+    use chrono::Utc;
+    use prost::Message;
 
-        for (label, key) in &self.encryption.keys {
-            writeln!(&mut file, "{} = \"{}\"", label, key)?;
+    /// Build a single-file pack
+    fn unsealed_pack() -> Pack {
+        Pack {
+            uuid: Uuid::new_v4(),
+            date: Utc::now(),
+            fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: Some("example pack".to_owned()),
+            channel: None,
+            files: vec![PackFile {
+                filename: "/etc/motd".to_owned(),
+                content_type: String::new(),
+                modified_at: None,
+                body: b"hello".to_vec(),
+            }],
+            chunked_files: vec![],
+            limits: Default::default(),
         }
+    }
+
+    /// Encrypt and sign a single-file pack with the given keyrings
+    fn example_pack(keyrings: &Keyrings) -> (Uuid, Vec<u8>) {
+        let pack = unsealed_pack();
+        let uuid = pack.uuid;
+        let mut encryptor = keyrings
+            .encryption
+            .get(DEFAULT_KEY_LABEL, uuid.as_bytes())
+            .unwrap();
+        let signer = keyrings.signing.get(DEFAULT_KEY_LABEL).unwrap();
+
+        (uuid, pack.encrypt_and_sign(&mut encryptor, signer).unwrap())
+    }
+
+    /// Encrypt a single-file pack for each of the given keyrings, signing it
+    /// with the first one's signing key
+    fn multi_recipient_pack(keyrings: &[&Keyrings]) -> (Uuid, Vec<u8>) {
+        let pack = unsealed_pack();
+        let uuid = pack.uuid;
+        let mut recipients: Vec<_> = keyrings
+            .iter()
+            .map(|keys| {
+                keys.encryption
+                    .get(DEFAULT_KEY_LABEL, uuid.as_bytes())
+                    .unwrap()
+            })
+            .collect();
+        let signer = keyrings[0].signing.get(DEFAULT_KEY_LABEL).unwrap();
+
+        let bytes = pack
+            .encrypt_and_sign_for_recipients(&mut recipients, signer)
+            .unwrap();
+
+        (uuid, bytes)
+    }
+
+    /// Decode a serialized pack, modify it, and re-encode it
+    fn tamper<F: FnOnce(&mut PackProto)>(bytes: &[u8], f: F) -> Vec<u8> {
+        let (version, body) = PackVersion::parse(bytes).unwrap();
+        let mut proto = PackProto::decode(body).unwrap();
+        f(&mut proto);
+
+        let mut output = Vec::from(version.magic_string());
+        proto.encode(&mut output).unwrap();
+        output
+    }
+
+    /// Encrypt and sign a pack with the given keyrings, then relabel it as a
+    /// v0.1 pack (without re-signing it)
+    fn relabelled_pack(keyrings: &Keyrings, pack: Pack) -> Vec<u8> {
+        assert_eq!(pack.format_version(), PackVersion::V0_2);
+
+        let mut encryptor = keyrings
+            .encryption
+            .get(DEFAULT_KEY_LABEL, pack.uuid().as_bytes())
+            .unwrap();
+        let signer = keyrings.signing.get(DEFAULT_KEY_LABEL).unwrap();
+        let bytes = pack.encrypt_and_sign(&mut encryptor, signer).unwrap();
+        assert!(Pack::verify_and_decrypt_with(&bytes, keyrings).is_ok());
+
+        let (_, body) = PackVersion::parse(&bytes).unwrap();
+        let mut relabelled = Vec::from(PackVersion::V0_1.magic_string());
+        relabelled.extend_from_slice(body);
+        relabelled
+    }
+
+    /// Generate keyrings sharing the given config's signing keys
+    fn cosigning_keyrings(config: &Config) -> Keyrings {
+        let mut other_config = Config::generate_random().unwrap();
+        other_config.signing = config.signing.clone();
+        Keyrings::from_config(&other_config).unwrap()
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let config = Config::generate_random().unwrap();
+        let input = format!(
+            "{}\n{}\n",
+            config.encryption[DEFAULT_KEY_LABEL], config.signing[DEFAULT_KEY_LABEL]
+        );
+
+        let keyrings = Keyrings::from_reader(input.as_bytes()).unwrap();
+        assert!(keyrings.signing.get(DEFAULT_KEY_LABEL).is_ok());
+        assert!(keyrings.encryption.get(DEFAULT_KEY_LABEL, b"salt").is_ok());
+
+        let signing_only = config.signing[DEFAULT_KEY_LABEL].clone();
+        assert!(Keyrings::from_reader(signing_only.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_covered_fingerprints() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let other_keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+
+        let (_, bytes) = example_pack(&keys);
+        let recipient = &Pack::inspect(&bytes).unwrap().recipients[0];
+
+        let (signing, encryption) = keys.covered_fingerprints();
+        assert!(signing.contains(&recipient.signing_key.to_ascii_lowercase()));
+        assert!(encryption.contains(&recipient.encryption_key.to_ascii_lowercase()));
+
+        let (signing, encryption) = other_keys.covered_fingerprints();
+        assert!(!signing.contains(&recipient.signing_key.to_ascii_lowercase()));
+        assert!(!encryption.contains(&recipient.encryption_key.to_ascii_lowercase()));
+    }
+
+    #[test]
+    fn test_reencrypt() {
+        let old_keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let new_keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+
+        let (uuid, old_bytes) = example_pack(&old_keys);
+
+        let new_bytes = Pack::reencrypt(&old_bytes, &old_keys, &new_keys).unwrap();
+        assert!(Pack::verify_and_decrypt_with(&new_bytes, &old_keys).is_err());
+
+        let pack = Pack::verify_and_decrypt_with(&new_bytes, &new_keys).unwrap();
+        assert_eq!(pack.uuid(), &uuid);
+        assert_eq!(pack.description.as_deref(), Some("example pack"));
+        assert_eq!(pack.get_file("/etc/motd").unwrap().body, b"hello");
+    }
+
+    #[test]
+    fn test_cosign() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let cosigner_keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let signer = keys.signing.get(DEFAULT_KEY_LABEL).unwrap();
+        let cosigner = cosigner_keys.signing.get(DEFAULT_KEY_LABEL).unwrap();
+
+        let authorized_keys = vec![signer.public_key().unwrap(), cosigner.public_key().unwrap()];
+        let policy = SignaturePolicy::new(2, authorized_keys).unwrap();
+
+        let (_, bytes) = example_pack(&keys);
+        assert!(Pack::verify_and_decrypt_with_policy(&bytes, &keys, &policy).is_err());
+        assert!(Pack::cosign(&bytes, signer).is_err());
+
+        let cosigned = Pack::cosign(&bytes, cosigner).unwrap();
+        assert!(Pack::verify_and_decrypt_with_policy(&cosigned, &keys, &policy).is_ok());
+        assert!(Pack::verify_and_decrypt_with(&cosigned, &keys).is_ok());
+    }
+
+    #[test]
+    fn test_verify_signature_only() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let other_keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let public_key = keys.signing.public_keys[DEFAULT_KEY_LABEL];
+        let other_public_key = other_keys.signing.public_keys[DEFAULT_KEY_LABEL];
+
+        let (_, mut bytes) = example_pack(&keys);
+        let fingerprints = Pack::verify_signature_only(&bytes, &public_key).unwrap();
+        assert_eq!(fingerprints.signing_key, public_key.to_fingerprint());
+        assert!(Pack::verify_signature_only(&bytes, &other_public_key).is_err());
+
+        // Flip a bit in the ciphertext (at the end of the pack)
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(Pack::verify_signature_only(&bytes, &public_key).is_err());
+    }
+
+    #[test]
+    fn test_multiple_recipients() {
+        let config = Config::generate_random().unwrap();
+        let keys = Keyrings::from_config(&config).unwrap();
+        let other_keys = cosigning_keyrings(&config);
+        let unrelated_keys = cosigning_keyrings(&config);
+
+        let (uuid, bytes) = multi_recipient_pack(&[&keys, &other_keys]);
+        assert_eq!(Pack::inspect(&bytes).unwrap().recipients.len(), 2);
+
+        for recipient_keys in &[&keys, &other_keys] {
+            let pack = Pack::verify_and_decrypt_with(&bytes, *recipient_keys).unwrap();
+            assert_eq!(pack.uuid(), &uuid);
+            assert_eq!(pack.get_file("/etc/motd").unwrap().body, b"hello");
+        }
+
+        assert!(Pack::verify_and_decrypt_with(&bytes, &unrelated_keys).is_err());
+
+        // Dropping a recipient invalidates the signature
+        let bytes = tamper(&bytes, |proto| {
+            proto.recipients.pop();
+        });
+        assert!(matches!(
+            Pack::verify_and_decrypt_with(&bytes, &keys),
+            Err(Error::Signature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_tampered_signature_before_unwrap() {
+        let config = Config::generate_random().unwrap();
+        let keys = Keyrings::from_config(&config).unwrap();
+        let other_keys = cosigning_keyrings(&config);
+        let (_, bytes) = multi_recipient_pack(&[&keys, &other_keys]);
+
+        // Corrupt both the signature and our wrapped content key: if the key
+        // were unwrapped first, this would fail with a crypto error instead
+        let bytes = tamper(&bytes, |proto| {
+            proto.signature[0] ^= 1;
+            proto.recipients[0].wrapped_key[0] ^= 1;
+        });
+        assert!(matches!(
+            Pack::verify_and_decrypt_with(&bytes, &keys),
+            Err(Error::Signature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_relabelled_delta_pack() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+
+        let mut pack = unsealed_pack();
+        pack.base_uuid = Some(Uuid::new_v4());
+        pack.chunked_files.push(ChunkedFile {
+            filename: "/etc/issue".to_owned(),
+            content_type: String::new(),
+            modified_at: None,
+            chunks: vec![],
+        });
+
+        let bytes = relabelled_pack(&keys, pack);
+        assert!(Pack::verify_and_decrypt_with(&bytes, &keys).is_err());
+    }
+
+    #[test]
+    fn test_relabelled_channel_pack() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+
+        // A v0.1 build would ignore the channel, so this must not verify
+        let mut pack = unsealed_pack();
+        pack.channel = Some("staging".to_owned());
+
+        let bytes = relabelled_pack(&keys, pack);
+        assert!(Pack::verify_and_decrypt_with(&bytes, &keys).is_err());
+    }
+
+    #[test]
+    fn test_inspect() {
+        let keys = Keyrings::from_config(&Config::generate_random().unwrap()).unwrap();
+        let (uuid, bytes) = example_pack(&keys);
 
-        Ok(())
+        let header = Pack::inspect(&bytes).unwrap();
+        assert_eq!(header.uuid, uuid);
+        assert_eq!(header.recipients.len(), 1);
+        assert_eq!(
+            header.recipients[0].signing_key,
+            keys.signing.public_keys[DEFAULT_KEY_LABEL].to_fingerprint()
+        );
+        assert!(Pack::inspect(b"not a pack").is_err());
     }
 }
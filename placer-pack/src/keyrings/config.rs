@@ -1,16 +1,19 @@
 //! Keyring configuration file
 
 use crate::error::Error;
+use crate::keyring_file;
 use crate::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX, SIGNING_KEY_PREFIX};
 use clear_on_drop::clear::Clear;
-use rand::{OsRng, Rng};
 use std::collections::BTreeMap;
+use std::env;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
+use std::mem;
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
+use std::str;
 
-use crate::crypto::{ENCRYPTION_KEY_SIZE, SIGNING_KEY_SIZE};
+use crate::crypto::{random, ENCRYPTION_KEY_SIZE, SIGNING_KEY_SIZE};
 
 /// Mandatory file permissions for config file.
 /// Restricted since they (may) contain encryption keys
@@ -19,6 +22,18 @@ pub const REQUIRED_FILE_PERMISSIONS: u32 = 0o600;
 /// Name of the default key in a keyring
 pub const DEFAULT_KEY_LABEL: &str = "default";
 
+/// Environment variable a signing KeyURI can be read from (instead of a file)
+pub const SIGNING_KEY_ENV_VAR: &str = "PLACER_SIGNING_KEY";
+
+/// Environment variable an encryption KeyURI can be read from (instead of a file)
+pub const ENCRYPTION_KEY_ENV_VAR: &str = "PLACER_ENCRYPTION_KEY";
+
+/// Example of the sections a keyring configuration must contain
+const EXAMPLE_SECTIONS: &str = "[signing]\n\
+                                default = \"secret.key:ed25519...\"\n\n\
+                                [encryption]\n\
+                                default = \"secret.key:aes256siv+hks256...\"";
+
 /// Keyring configuration
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -33,12 +48,8 @@ pub struct Config {
 impl Config {
     /// Create a new random set of keys
     pub fn generate_random() -> Result<Self, Error> {
-        let mut rng = OsRng::new().unwrap_or_else(|e| {
-            panic!("OS random number generator failure! {}", e);
-        });
-
         let mut signing_key_bytes = [0u8; SIGNING_KEY_SIZE];
-        rng.fill_bytes(&mut signing_key_bytes[..]);
+        random::fill(&mut signing_key_bytes)?;
 
         let mut signing_keys = BTreeMap::new();
         signing_keys.insert(
@@ -48,7 +59,7 @@ impl Config {
         signing_key_bytes.clear();
 
         let mut encryption_key_bytes = [0u8; ENCRYPTION_KEY_SIZE];
-        rng.fill_bytes(&mut encryption_key_bytes[..]);
+        random::fill(&mut encryption_key_bytes)?;
 
         let mut encryption_keys = BTreeMap::new();
         encryption_keys.insert(
@@ -63,28 +74,120 @@ impl Config {
         })
     }
 
+    /// Create a keyring configuration containing the given signing and
+    /// encryption KeyURIs (as the default keys)
+    pub fn from_keyuris(signing_key: String, encryption_key: String) -> Self {
+        let mut signing = BTreeMap::new();
+        signing.insert(DEFAULT_KEY_LABEL.to_owned(), signing_key);
+
+        let mut encryption = BTreeMap::new();
+        encryption.insert(DEFAULT_KEY_LABEL.to_owned(), encryption_key);
+
+        Self {
+            signing,
+            encryption,
+        }
+    }
+
+    /// Read the signing and encryption KeyURIs from the `PLACER_SIGNING_KEY`
+    /// and `PLACER_ENCRYPTION_KEY` environment variables, removing them from
+    /// the environment so they aren't inherited by child processes
+    pub fn from_env() -> Result<Self, Error> {
+        let read_var = |name: &str| {
+            let value = env::var(name).map_err(|e| err!(Config, "{}: {}", name, e));
+            env::remove_var(name);
+            value
+        };
+
+        let signing_key = read_var(SIGNING_KEY_ENV_VAR);
+        let encryption_key = read_var(ENCRYPTION_KEY_ENV_VAR);
+
+        Ok(Self::from_keyuris(signing_key?, encryption_key?))
+    }
+
+    /// Read a signing and an encryption KeyURI (separated by whitespace, in
+    /// either order) from the given reader, e.g. stdin
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut data = vec![];
+        let result = reader
+            .read_to_end(&mut data)
+            .map_err(|e| err!(Io, "couldn't read keys: {}", e))
+            .and_then(|_| {
+                let keyuris =
+                    str::from_utf8(&data).map_err(|_| err!(Config, "keys aren't valid UTF-8"))?;
+
+                let mut signing_key = None;
+                let mut encryption_key = None;
+
+                for keyuri in keyuris.split_whitespace() {
+                    let prefix = keyuri.split(bech32k::SEPARATOR).next().unwrap();
+
+                    let key = if prefix == ENCRYPTION_KEY_PREFIX {
+                        &mut encryption_key
+                    } else {
+                        &mut signing_key
+                    };
+
+                    if key.is_some() {
+                        fail!(Config, "more than one {} key given", prefix);
+                    }
+
+                    *key = Some(keyuri.to_owned());
+                }
+
+                match (signing_key, encryption_key) {
+                    (Some(signing_key), Some(encryption_key)) => {
+                        Ok(Self::from_keyuris(signing_key, encryption_key))
+                    }
+                    (None, _) => fail!(Config, "no signing key given"),
+                    (_, None) => fail!(Config, "no encryption key given"),
+                }
+            });
+
+        data.as_mut_slice().clear();
+        result
+    }
+
     /// Load the keyring configuration from a file
     pub fn load(filename: &Path) -> Result<Self, Error> {
         let mut file = File::open(filename)
             .map_err(|e| err!(Io, "couldn't open {}: {}", filename.to_string_lossy(), e))?;
 
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
+        let data = keyring_file::read_to_string(&mut file, filename)?;
+        let result = Self::parse(filename, &data);
+        data.into_bytes().as_mut_slice().clear();
+        result
+    }
 
-        toml::from_str(&data).map_err(|e| {
-            err!(
-                Config,
-                "couldn't parse {}: {}",
-                filename.to_string_lossy(),
-                e
-            )
+    /// Parse a keyring configuration (read from the given file)
+    fn parse(filename: &Path, data: &str) -> Result<Self, Error> {
+        toml::from_str(data).map_err(|e| {
+            keyring_file::missing_section(filename, &e, EXAMPLE_SECTIONS).unwrap_or_else(|| {
+                err!(
+                    Config,
+                    "couldn't parse {}: {}",
+                    filename.to_string_lossy(),
+                    e
+                )
+            })
         })
     }
 
     /// Save the keyring configuration to a file
     pub fn save(&self, path: &Path) -> Result<(), Error> {
+        self.write(
+            path,
+            "# placer signing key configuration\n\
+             # PROTECT THIS FILE!!! It contains all of your secret keys!\n",
+        )
+    }
+
+    /// Serialize the keyring configuration as TOML and write it to a file,
+    /// prefixed with the given comment header
+    pub(crate) fn write(&self, path: &Path, header: &str) -> Result<(), Error> {
         let filename = path.to_string_lossy();
-        let mut toml = toml::to_string(self).unwrap();
+        let mut toml = toml::to_string(self)
+            .map_err(|e| err!(Serialization, "couldn't serialize keyring: {}", e))?;
 
         let mut file = OpenOptions::new()
             .create(true)
@@ -94,11 +197,7 @@ impl Config {
             .open(path)
             .map_err(|e| err!(Io, "couldn't open {} for writing: {}", filename, e))?;
 
-        writeln!(&mut file, "# placer signing key configuration")?;
-        writeln!(
-            &mut file,
-            "# PROTECT THIS FILE!!! It contains all of your secret keys!\n"
-        )?;
+        writeln!(&mut file, "{}", header)?;
 
         file.write_all(toml.as_bytes())
             .map_err(|e| err!(Io, "couldn't write to {}: {}", filename, e))?;
@@ -110,12 +209,12 @@ impl Config {
 
 impl Drop for Config {
     fn drop(&mut self) {
-        for v in self.signing.values_mut() {
-            v.as_bytes().clear();
-        }
-
-        for v in self.encryption.values_mut() {
-            v.as_bytes().clear();
+        for v in self
+            .signing
+            .values_mut()
+            .chain(self.encryption.values_mut())
+        {
+            mem::take(v).into_bytes().as_mut_slice().clear();
         }
     }
 }
@@ -1,9 +1,12 @@
 //! Encryption keyring
 
+use clear_on_drop::clear::Clear;
 use std::collections::BTreeMap;
+use std::mem;
 
 use crate::crypto::Encryptor;
 use crate::error::Error;
+use crate::keyuri::{self, FingerprintAlgorithm};
 
 /// Keyring of `Encryptor` values
 pub struct EncryptionKeyring {
@@ -25,4 +28,27 @@ impl EncryptionKeyring {
             None => fail!(InvalidKey, "unknown encryption key: \"{}\"", key),
         }
     }
+
+    /// Get an `Encryptor` for the key with the given KeyURI fingerprint
+    /// (computed with any supported fingerprint algorithm)
+    pub fn get_by_fingerprint(&self, fingerprint: &str, salt: &[u8]) -> Result<Encryptor, Error> {
+        let key = self
+            .keys
+            .values()
+            .find(|k| keyuri::fingerprint_matches(k, fingerprint));
+
+        match (key, FingerprintAlgorithm::of(fingerprint)) {
+            (Some(k), Some(algorithm)) => Ok(Encryptor::from_keyuri(k, salt)?
+                .with_fingerprint(keyuri::fingerprint_with(algorithm, k))),
+            _ => fail!(InvalidKey, "unknown encryption key: \"{}\"", fingerprint),
+        }
+    }
+}
+
+impl Drop for EncryptionKeyring {
+    fn drop(&mut self) {
+        for key in self.keys.values_mut() {
+            mem::take(key).into_bytes().as_mut_slice().clear();
+        }
+    }
 }
@@ -2,27 +2,42 @@
 
 use std::collections::BTreeMap;
 
-use crate::crypto::{Signer, SIGNING_KEY_SIZE};
+use crate::crypto::{PublicKey, SignatureScheme, Signer, SIGNING_KEY_SIZE};
 use crate::error::Error;
-use crate::keyuri::{bech32k, SIGNING_KEY_PREFIX};
+use crate::keyuri::{bech32k, VERIFY_KEY_PREFIX};
 
-/// Keyring of `Signer` values
+/// Keyring of `Signer` values (and/or their `PublicKey`s, for verify keyrings)
 pub struct SigningKeyring {
     /// Signing keys
     pub(crate) keys: BTreeMap<String, Signer>,
+
+    /// Public keys for every key in the keyring (including signing keys)
+    pub(crate) public_keys: BTreeMap<String, PublicKey>,
 }
 
 impl SigningKeyring {
+    /// Create a new signing keyring from secret and/or public KeyURIs
     pub fn new(keys: &BTreeMap<String, String>) -> Result<Self, Error> {
         let mut signers = BTreeMap::new();
+        let mut public_keys = BTreeMap::new();
 
         for (label, encoded_key) in keys {
-            let (prefix, mut decoded_key) = bech32k::decode(encoded_key)?;
+            if encoded_key.starts_with(VERIFY_KEY_PREFIX) {
+                if public_keys
+                    .insert(label.to_owned(), PublicKey::from_keyuri(encoded_key)?)
+                    .is_some()
+                {
+                    fail!(InvalidKey, "duplicate signing key: \"{}\"", label);
+                }
 
-            if prefix != SIGNING_KEY_PREFIX {
-                fail!(InvalidKey, "invalid signing key type: \"{}\"", prefix);
+                continue;
             }
 
+            let (prefix, mut decoded_key) = bech32k::decode(encoded_key)?;
+
+            let scheme = SignatureScheme::from_signing_key_prefix(&prefix)
+                .ok_or_else(|| err!(InvalidKey, "invalid signing key type: \"{}\"", prefix))?;
+
             if decoded_key.len() != SIGNING_KEY_SIZE {
                 fail!(
                     InvalidKey,
@@ -34,15 +49,22 @@ impl SigningKeyring {
             }
 
             // TODO: support signers other than dalek
-            let signer = Signer::from_bytes(&decoded_key).unwrap();
+            let signer = Signer::from_bytes_with_scheme(&decoded_key, scheme)?;
             decoded_key.clear();
 
-            if signers.insert(label.to_owned(), signer).is_some() {
+            if public_keys
+                .insert(label.to_owned(), signer.public_key()?)
+                .is_some()
+                || signers.insert(label.to_owned(), signer).is_some()
+            {
                 fail!(InvalidKey, "duplicate signing key: \"{}\"", label);
             }
         }
 
-        Ok(SigningKeyring { keys: signers })
+        Ok(SigningKeyring {
+            keys: signers,
+            public_keys,
+        })
     }
 
     /// Get an `Signer` from the keyring
@@ -52,4 +74,13 @@ impl SigningKeyring {
             .get(key)
             .ok_or_else(|| err!(InvalidKey, "unknown signing key: \"{}\"", key))
     }
+
+    /// Get the `PublicKey` with the given KeyURI fingerprint (computed with
+    /// any supported fingerprint algorithm) from the keyring
+    pub fn get_by_fingerprint(&self, fingerprint: &str) -> Result<&PublicKey, Error> {
+        self.public_keys
+            .values()
+            .find(|key| key.matches_fingerprint(fingerprint))
+            .ok_or_else(|| err!(InvalidKey, "unknown signing key: \"{}\"", fingerprint))
+    }
 }
@@ -3,9 +3,6 @@
 #![crate_name = "placer_pack"]
 #![deny(missing_docs, unsafe_code, unused_import_braces, unused_qualifications)]
 
-#[macro_use]
-extern crate failure;
-
 #[macro_use]
 extern crate prost_derive;
 
@@ -19,8 +16,11 @@ extern crate toml;
 #[macro_use]
 mod macros;
 
+pub mod chunking;
 pub mod crypto;
 pub mod error;
+pub mod key_lookup;
+pub mod keyring_file;
 #[cfg(feature = "keyrings")]
 pub mod keyrings;
 pub mod pack;
@@ -28,6 +28,10 @@ pub mod pack;
 pub mod keyuri;
 mod protos;
 
+pub use crate::key_lookup::KeyLookup;
 #[cfg(feature = "keyrings")]
 pub use crate::keyrings::Keyrings;
-pub use crate::pack::{Fingerprints, Pack, PackFile, MAX_PACK_SIZE};
+pub use crate::pack::{
+    required_fingerprints, ChunkedFile, Fingerprints, Limits, Pack, PackFile, PackHeader,
+    PackVersion, MAX_PACK_SIZE,
+};
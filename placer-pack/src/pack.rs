@@ -1,25 +1,41 @@
 //! Create a placer pack from a set of input files
 
 use chrono::{DateTime, Utc};
+use clear_on_drop::clear::Clear;
 use prost::Message;
-#[cfg(feature = "signer")]
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::fs::File as StdFile;
 use std::io::Read;
-use std::path::Path;
-#[cfg(feature = "signer")]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::slice::Iter;
+use std::vec::IntoIter;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+#[cfg(feature = "async")]
+use tokio::task;
 pub use uuid::Uuid;
 
+use crate::chunking;
 #[cfg(feature = "signer")]
 use crate::crypto::Signer;
-use crate::crypto::{Encryptor, PublicKey};
+#[cfg(feature = "signer")]
+use crate::crypto::{random, ENCRYPTION_KEY_SIZE};
+use crate::crypto::{Encryptor, PublicKey, SignaturePolicy};
 use crate::error::Error;
+use crate::key_lookup::KeyLookup;
+#[cfg(feature = "keyrings")]
+use crate::keyrings::Keyrings;
+#[cfg(feature = "signer")]
+use crate::keyrings::DEFAULT_KEY_LABEL;
+use crate::keyuri::{bech32k, normalize_fingerprint, ENCRYPTION_KEY_PREFIX};
+pub use crate::protos::pack::ChunkedFile;
+#[cfg(feature = "signer")]
+use crate::protos::pack::Cosignature;
 pub use crate::protos::pack::File as PackFile;
 use crate::protos::pack::Pack as PackProto;
-use crate::protos::pack::Payload;
-#[cfg(feature = "signer")]
+use crate::protos::pack::{Payload, Recipient};
 use crate::protos::timestamp::Tai64n;
 
 /// Maximum length of a pack in bytes
@@ -29,15 +45,133 @@ use crate::protos::timestamp::Tai64n;
 /// files, but that's a "nice to have".
 pub const MAX_PACK_SIZE: usize = 1_048_576;
 
+/// Default maximum number of files in a pack
+pub const MAX_PACK_FILES: usize = 1024;
+
+/// Default maximum size of an individual file in a pack, in bytes
+pub const MAX_FILE_SIZE: usize = MAX_PACK_SIZE;
+
 /// Magic string which identifies a placer pack (v0)
 pub const PACK_V0_MAGIC_STRING: &[u8] = b"placer-pack:v0.1";
 
+/// Magic string which identifies a placer pack containing chunked files
+pub const PACK_V0_2_MAGIC_STRING: &[u8] = b"placer-pack:v0.2";
+
+/// Prefix of every pack's magic string, which is followed by its version
+pub const PACK_MAGIC_PREFIX: &[u8] = b"placer-pack:";
+
+/// Versions of the pack format this build can decode
+pub const SUPPORTED_PACK_VERSIONS: &[PackVersion] = &[PackVersion::V0_1, PackVersion::V0_2];
+
+/// Versions of the pack format
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PackVersion {
+    /// `placer-pack:v0.1`
+    V0_1,
+
+    /// `placer-pack:v0.2`: adds chunked files to delta packs and channels
+    /// (builds which only support v0.1 would ignore them, so they must reject
+    /// these packs)
+    V0_2,
+}
+
+impl PackVersion {
+    /// Version of the pack format this build produces (for packs without
+    /// chunked files or a channel, which require `V0_2`)
+    pub const CURRENT: PackVersion = PackVersion::V0_1;
+
+    /// Magic string which starts packs of this version
+    pub fn magic_string(self) -> &'static [u8] {
+        match self {
+            PackVersion::V0_1 => PACK_V0_MAGIC_STRING,
+            PackVersion::V0_2 => PACK_V0_2_MAGIC_STRING,
+        }
+    }
+
+    /// Version string (e.g. `v0.1`)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PackVersion::V0_1 => "v0.1",
+            PackVersion::V0_2 => "v0.2",
+        }
+    }
+
+    /// Identify the version of a serialized pack by its magic string,
+    /// returning it along with the rest of the pack
+    pub fn parse(bytes: &[u8]) -> Result<(Self, &[u8]), Error> {
+        for &version in SUPPORTED_PACK_VERSIONS {
+            let magic = version.magic_string();
+
+            // The whole magic string must match: `v0.1` isn't a prefix match
+            // for e.g. `v0.10`
+            let matches = bytes.starts_with(magic)
+                && !bytes
+                    .get(magic.len())
+                    .map_or(false, |&byte| byte == b'.' || byte.is_ascii_digit());
+
+            if matches {
+                return Ok((version, &bytes[magic.len()..]));
+            }
+        }
+
+        if !bytes.starts_with(PACK_MAGIC_PREFIX) {
+            fail!(
+                Parse,
+                "pack does not start with magic string (\"{}\")",
+                String::from_utf8_lossy(PACK_MAGIC_PREFIX)
+            );
+        }
+
+        // Protobuf data immediately follows the version, so only take the
+        // characters which could be part of one (e.g. `v1.2`)
+        let unsupported: String = bytes[PACK_MAGIC_PREFIX.len()..]
+            .iter()
+            .take(16)
+            .take_while(|&&byte| byte == b'v' || byte == b'.' || byte.is_ascii_digit())
+            .map(|&byte| byte as char)
+            .collect();
+
+        let supported: Vec<_> = SUPPORTED_PACK_VERSIONS.iter().map(|v| v.as_str()).collect();
+
+        fail!(
+            Parse,
+            "unsupported pack version {} (this build supports {})",
+            if unsupported.is_empty() {
+                "(unknown)"
+            } else {
+                unsupported.as_str()
+            },
+            supported.join(", ")
+        );
+    }
+}
+
 /// Default Content-Type for all files in the pack
 pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
 
 /// Maximum amount of clock skew (into the future) we allow on pack file timestamps (in seconds)
 pub const MAX_PACK_TIMESTAMP_SKEW: i64 = 86_400; // 24h
 
+/// Unverified header of a serialized pack, readable without any keys
+#[derive(Debug)]
+pub struct PackHeader {
+    /// UUID of the pack
+    pub uuid: Uuid,
+
+    /// Date when the pack was produced
+    pub date: DateTime<Utc>,
+
+    /// Pairs of signing and encryption key fingerprints, any of which can be
+    /// used to verify and decrypt the pack (one per recipient)
+    pub recipients: Vec<Fingerprints>,
+
+    /// Length of the encrypted payload in bytes
+    pub ciphertext_len: usize,
+
+    /// Length of the signature in bytes
+    pub signature_len: usize,
+}
+
 /// Packs of files
 pub struct Pack {
     /// UUID that uniquely identifies this pack (we hope!)
@@ -49,17 +183,92 @@ pub struct Pack {
     /// Fingerprints of the keys used to sign the pack
     pub fingerprints: Option<Fingerprints>,
 
+    /// UUID of the pack this delta pack applies to (`None` for full packs)
+    pub base_uuid: Option<Uuid>,
+
+    /// Names of files deleted from the base pack (delta packs only)
+    pub deletions: Vec<String>,
+
+    /// Free-text description of the pack (encrypted and signed along with
+    /// its files)
+    pub description: Option<String>,
+
+    /// Channel (i.e. environment) the pack is intended for, which placer
+    /// checks against the channels it accepts
+    pub channel: Option<String>,
+
     /// Files in the pack
     pub files: Vec<PackFile>,
+
+    /// Files stored as chunks of their versions in the base pack plus new
+    /// data (delta packs only)
+    pub chunked_files: Vec<ChunkedFile>,
+
+    /// Limits on the files in this pack, enforced when it's encrypted
+    pub limits: Limits,
+}
+
+/// Limits on the number and size of files in a pack, enforced both when a
+/// pack is created and when it's decrypted
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// Maximum number of files in a pack
+    pub max_files: usize,
+
+    /// Maximum size of an individual file body in bytes
+    pub max_file_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_files: MAX_PACK_FILES,
+            max_file_size: MAX_FILE_SIZE,
+        }
+    }
+}
+
+impl Limits {
+    /// Ensure the given files (and chunked files, which count towards the
+    /// maximum number of files) are within these limits
+    pub fn check(&self, files: &[PackFile], chunked_files: &[ChunkedFile]) -> Result<(), Error> {
+        let count = files.len() + chunked_files.len();
+
+        if count > self.max_files {
+            fail!(
+                TooManyFiles,
+                "too many files in pack: {} (max {})",
+                count,
+                self.max_files
+            );
+        }
+
+        for file in files {
+            if file.body.len() > self.max_file_size {
+                fail!(
+                    FileTooLarge,
+                    "file too large: {} is {}-bytes (max {})",
+                    file.filename,
+                    file.body.len(),
+                    self.max_file_size
+                );
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Fingerprints for the keys used to sign a pack
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "keyrings", derive(Serialize, Deserialize))]
 pub struct Fingerprints {
     /// Signing public key fingerprint (in KeyURI format)
+    #[cfg_attr(feature = "keyrings", serde(rename = "signing_key_fingerprint"))]
     pub signing_key: String,
 
     /// Encryption key fingerprint (in KeyURI format)
+    #[cfg_attr(feature = "keyrings", serde(rename = "encryption_key_fingerprint"))]
     pub encryption_key: String,
 }
 
@@ -67,15 +276,47 @@ impl Pack {
     /// Create a new pack from the given files
     #[cfg(feature = "signer")]
     pub fn create(uuid: Uuid, base: &Path, input: &[PathBuf]) -> Result<Self, Error> {
+        Self::create_modified_within(uuid, base, input, None, None)
+    }
+
+    /// Create a new pack from those of the given files which were modified
+    /// within the given time range (inclusive), erroring if none were
+    #[cfg(feature = "signer")]
+    pub fn create_modified_within(
+        uuid: Uuid,
+        base: &Path,
+        input: &[PathBuf],
+        since: Option<&DateTime<Utc>>,
+        until: Option<&DateTime<Utc>>,
+    ) -> Result<Self, Error> {
         let mut files = vec![];
         let canonical_base = base.canonicalize()?;
 
         for filename in input {
             let path = canonical_base.join(&filename).canonicalize()?;
+
+            // Refuse `..` or absolute paths (or symlinks) leading outside the base
+            let relative_path = path.strip_prefix(&canonical_base).map_err(|_| {
+                err!(
+                    Io,
+                    "{} is outside of {}",
+                    path.to_string_lossy(),
+                    canonical_base.to_string_lossy()
+                )
+            })?;
+
             let modified_at = fs::metadata(&path)
                 .and_then(|meta| meta.modified())
                 .map_err(|e| err!(Io, "couldn't stat {}: {}", path.to_string_lossy(), e))?;
 
+            let modified_date = DateTime::<Utc>::from(modified_at);
+
+            if since.map_or(false, |since| modified_date < *since)
+                || until.map_or(false, |until| modified_date > *until)
+            {
+                continue;
+            }
+
             let mut file = StdFile::open(&path)
                 .map_err(|e| err!(Io, "couldn't open {}: {}", path.to_string_lossy(), e))?;
 
@@ -83,8 +324,7 @@ impl Pack {
             file.read_to_end(&mut body)?;
 
             // All paths for files in the pack are absolute
-            let absolute_path =
-                PathBuf::from("/").join(&path.strip_prefix(&canonical_base).unwrap());
+            let absolute_path = PathBuf::from("/").join(relative_path);
 
             files.push(PackFile {
                 filename: absolute_path.to_string_lossy().to_string(),
@@ -94,89 +334,220 @@ impl Pack {
             });
         }
 
+        if files.is_empty() {
+            if since.is_some() || until.is_some() {
+                fail!(Io, "no files were modified within the given time range");
+            }
+
+            fail!(
+                Serialization,
+                "refusing to create an empty pack (no input files)"
+            );
+        }
+
         Ok(Self {
             uuid,
             date: Utc::now(),
             fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: None,
+            channel: None,
             files,
+            chunked_files: vec![],
+            limits: Limits::default(),
         })
     }
 
+    /// Make this pack reproducible from its inputs: set its date, and the
+    /// modification time of every file, to the given date. Encrypting and
+    /// signing packs with the same UUID, date, files, and keys then produces
+    /// byte-identical output, as AES-SIV and Ed25519 are both deterministic.
+    ///
+    /// Packs for multiple recipients are never reproducible, since they're
+    /// encrypted under a random content key.
+    pub fn set_reproducible_date(&mut self, date: DateTime<Utc>) {
+        self.date = date;
+
+        for file in &mut self.files {
+            file.modified_at = Some(date.into());
+        }
+
+        for file in &mut self.chunked_files {
+            file.modified_at = Some(date.into());
+        }
+    }
+
     /// Load an encrypted pack from a file
     pub fn load<F>(path: &Path, key_lookup: F) -> Result<Self, Error>
     where
         F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)>,
     {
-        let mut file = StdFile::open(path)
+        let file = StdFile::open(path)
             .map_err(|e| err!(Io, "couldn't open {}: {}", path.to_string_lossy(), e))?;
 
-        let mut data = vec![];
-        file.read_to_end(&mut data)?;
+        Self::from_reader(file, key_lookup)
+    }
 
-        if data.len() > MAX_PACK_SIZE {
-            fail!(
-                Serialization,
-                "pack too large: {}-bytes (max {})",
-                data.len(),
-                MAX_PACK_SIZE
-            )
-        }
+    /// Read an encrypted pack from the given reader, verifying and decrypting it.
+    /// Reads at most `MAX_PACK_SIZE + 1` bytes, so oversized input is rejected
+    /// without being buffered in its entirety.
+    pub fn from_reader<R, F>(reader: R, key_lookup: F) -> Result<Self, Error>
+    where
+        R: Read,
+        F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)>,
+    {
+        let mut data = vec![];
+        reader
+            .take(MAX_PACK_SIZE as u64 + 1)
+            .read_to_end(&mut data)?;
+        ensure_max_size(data.len())?;
 
         Self::verify_and_decrypt(&data, key_lookup)
     }
 
+    /// Load an encrypted pack from an async reader, verifying and decrypting
+    /// it on Tokio's blocking thread pool
+    #[cfg(feature = "async")]
+    pub async fn load_async<R, F>(reader: R, key_lookup: F) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin,
+        F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)> + Send + 'static,
+    {
+        let mut data = vec![];
+        reader
+            .take(MAX_PACK_SIZE as u64 + 1)
+            .read_to_end(&mut data)
+            .await?;
+        ensure_max_size(data.len())?;
+
+        task::spawn_blocking(move || Self::verify_and_decrypt(&data, key_lookup))
+            .await
+            .map_err(|e| err!(Crypto, "pack verification task failed: {}", e))?
+    }
+
     /// Parse an encrypted pack, first verifying its signature and then decrypting it
     pub fn verify_and_decrypt<F>(bytes: &[u8], key_lookup: F) -> Result<Self, Error>
     where
         F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)>,
     {
-        if bytes.len() < PACK_V0_MAGIC_STRING.len() {
-            fail!(
-                Parse,
-                "pack too short: expected at least {} bytes, got {}",
-                PACK_V0_MAGIC_STRING.len(),
-                bytes.len()
-            );
-        }
+        Self::verify_and_decrypt_with_limits(bytes, Limits::default(), key_lookup)
+    }
 
-        if &bytes[..PACK_V0_MAGIC_STRING.len()] != PACK_V0_MAGIC_STRING {
-            fail!(
-                Parse,
-                "pack does not start with magic string (\"{}\")",
-                String::from_utf8(PACK_V0_MAGIC_STRING.to_vec()).unwrap()
-            );
-        }
+    /// Parse an encrypted pack, first verifying its signature and then
+    /// decrypting it, rejecting it if its files exceed the given limits
+    pub fn verify_and_decrypt_with_limits<F>(
+        bytes: &[u8],
+        limits: Limits,
+        key_lookup: F,
+    ) -> Result<Self, Error>
+    where
+        F: Fn(&Fingerprints, &Uuid) -> Option<(PublicKey, Encryptor)>,
+    {
+        Self::open(bytes, limits, None, &key_lookup)
+    }
 
-        let proto = PackProto::decode(&bytes[PACK_V0_MAGIC_STRING.len()..])
-            .map_err(|e| err!(Parse, "pack parsing error: {}", e))?;
+    /// Parse an encrypted pack, first verifying its signature and then
+    /// decrypting it, using the given `KeyLookup` to find its keys. Unlike
+    /// `verify_and_decrypt`, errors from the key lookup are preserved.
+    pub fn verify_and_decrypt_with<L>(bytes: &[u8], key_lookup: &L) -> Result<Self, Error>
+    where
+        L: KeyLookup + ?Sized,
+    {
+        Self::open(bytes, Limits::default(), None, key_lookup)
+    }
 
-        let uuid = Uuid::parse_str(&proto.uuid)
-            .map_err(|e| err!(Parse, "invalid UUID: \"{}\" ({})", proto.uuid, e))?;
+    /// Parse an encrypted pack, requiring it to be signed by at least as many
+    /// of the policy's authorized keys as its threshold. The pack's primary
+    /// signature and its cosignatures all count towards the threshold.
+    pub fn verify_and_decrypt_with_policy<L>(
+        bytes: &[u8],
+        key_lookup: &L,
+        policy: &SignaturePolicy,
+    ) -> Result<Self, Error>
+    where
+        L: KeyLookup + ?Sized,
+    {
+        Self::open(bytes, Limits::default(), Some(policy), key_lookup)
+    }
 
-        let fingerprints = Fingerprints {
-            signing_key: proto.signing_key_fingerprint.clone(),
-            encryption_key: proto.encryption_key_fingerprint.clone(),
-        };
+    /// Verify and decrypt a pack, rejecting it if its files exceed the limits
+    /// or it isn't signed in accordance with the given policy (if any)
+    fn open<L>(
+        bytes: &[u8],
+        limits: Limits,
+        policy: Option<&SignaturePolicy>,
+        key_lookup: &L,
+    ) -> Result<Self, Error>
+    where
+        L: KeyLookup + ?Sized,
+    {
+        let (version, proto) = decode_proto(bytes)?;
 
-        let (public_key, mut encryptor) = key_lookup(&fingerprints, &uuid)
-            .ok_or_else(|| err!(InvalidKey, "key lookup failed"))?;
+        let uuid = Uuid::parse_str(&proto.uuid)
+            .map_err(|e| err!(Parse, "invalid UUID: \"{}\" ({})", proto.uuid, e))?;
 
         let date_proto = proto
             .date
+            .as_ref()
             .ok_or_else(|| err!(Parse, "date missing from pack file"))?;
 
         let date = date_proto
             .to_datetime_utc()
             .ok_or_else(|| err!(Parse, "couldn't parse date from pack file"))?;
 
-        public_key.verify(&proto.ciphertext, &proto.signature)?;
-        let plaintext = encryptor
+        let (public_key, encryptor, recipient, fingerprints) = if proto.recipients.is_empty() {
+            let fingerprints = Fingerprints {
+                signing_key: proto.signing_key_fingerprint.clone(),
+                encryption_key: proto.encryption_key_fingerprint.clone(),
+            };
+
+            let (public_key, encryptor) = key_lookup.lookup(&fingerprints, &uuid)?;
+
+            (public_key, encryptor, None, fingerprints)
+        } else {
+            let (public_key, recipient_key, recipient, fingerprints) =
+                find_recipient(&proto, &uuid, key_lookup)?;
+
+            (public_key, recipient_key, Some(recipient), fingerprints)
+        };
+
+        // Nothing (including the wrapped content key) is decrypted until the
+        // signature has been verified
+        let signed_data = signed_data(&proto);
+        public_key.verify(&signed_data, &proto.signature)?;
+
+        if let Some(policy) = policy {
+            let signatures = Some((
+                proto.signing_key_fingerprint.as_str(),
+                proto.signature.as_slice(),
+            ))
+            .into_iter()
+            .chain(proto.cosignatures.iter().map(|cosignature| {
+                (
+                    cosignature.signing_key_fingerprint.as_str(),
+                    cosignature.signature.as_slice(),
+                )
+            }));
+
+            policy.verify(&signed_data, signatures)?;
+        }
+
+        let mut encryptor = match recipient {
+            Some(recipient) => {
+                unwrap_content_key(&proto, version, date_proto, &uuid, recipient, encryptor)?
+            }
+            None => encryptor,
+        };
+
+        let mut plaintext = encryptor
             .open(
-                &[
+                associated_data(
+                    version,
                     &date_proto.value,
-                    proto.encryption_key_fingerprint.as_bytes(),
-                    proto.signing_key_fingerprint.as_bytes(),
-                ],
+                    &proto.encryption_key_fingerprint,
+                    &proto.signing_key_fingerprint,
+                ),
                 &proto.ciphertext,
             )
             .map_err(|_| err!(Crypto, "decryption failed"))?;
@@ -184,60 +555,609 @@ impl Pack {
         // Ensure pack has a timestamp in the past
         // If it does have a future timestamp, ensure it's within an acceptable skew threshold
         if date.signed_duration_since(Utc::now()).num_seconds() > MAX_PACK_TIMESTAMP_SKEW {
+            plaintext.as_mut_slice().clear();
             fail!(
-                Parse,
+                Timestamp,
                 "bogus future timestamp on pack: {}",
                 date.format("%a %b %e %T %Y")
             );
         }
 
-        let payload =
-            Payload::decode(&plaintext).map_err(|e| err!(Parse, "payload parsing error: {}", e))?;
+        let payload_result = Payload::decode(&plaintext);
+        plaintext.as_mut_slice().clear();
+
+        let payload = payload_result.map_err(|e| err!(Parse, "payload parsing error: {}", e))?;
+        limits.check(&payload.files, &payload.chunked_files)?;
+
+        let base_uuid = if payload.base_uuid.is_empty() {
+            None
+        } else {
+            Some(Uuid::parse_str(&payload.base_uuid).map_err(|e| {
+                err!(
+                    Parse,
+                    "invalid base UUID: \"{}\" ({})",
+                    payload.base_uuid,
+                    e
+                )
+            })?)
+        };
+
+        if base_uuid.is_none() && !payload.chunked_files.is_empty() {
+            fail!(Parse, "chunked files are only allowed in delta packs");
+        }
+
+        if version == PackVersion::V0_1
+            && (!payload.chunked_files.is_empty() || !payload.channel.is_empty())
+        {
+            fail!(
+                Parse,
+                "pack {} uses {} features but is labelled {}",
+                uuid,
+                PackVersion::V0_2.as_str(),
+                version.as_str()
+            );
+        }
 
         Ok(Self {
             uuid,
             date,
             fingerprints: Some(fingerprints),
+            base_uuid,
+            deletions: payload.deletions,
+            description: Some(payload.description).filter(|d| !d.is_empty()),
+            channel: Some(payload.channel).filter(|c| !c.is_empty()),
             files: payload.files,
+            chunked_files: payload.chunked_files,
+            limits,
+        })
+    }
+
+    /// Read the header of a serialized pack without verifying or decrypting
+    /// it. Nothing in the header can be trusted until the pack is verified.
+    pub fn inspect(bytes: &[u8]) -> Result<PackHeader, Error> {
+        let (_, proto) = decode_proto(bytes)?;
+
+        let uuid = Uuid::parse_str(&proto.uuid)
+            .map_err(|e| err!(Parse, "invalid UUID: \"{}\" ({})", proto.uuid, e))?;
+
+        let date = proto
+            .date
+            .as_ref()
+            .and_then(Tai64n::to_datetime_utc)
+            .ok_or_else(|| err!(Parse, "missing or invalid date in pack file"))?;
+
+        let recipients = if proto.recipients.is_empty() {
+            vec![Fingerprints {
+                signing_key: proto.signing_key_fingerprint.clone(),
+                encryption_key: proto.encryption_key_fingerprint.clone(),
+            }]
+        } else {
+            proto
+                .recipients
+                .iter()
+                .map(|recipient| Fingerprints {
+                    signing_key: proto.signing_key_fingerprint.clone(),
+                    encryption_key: recipient.encryption_key_fingerprint.clone(),
+                })
+                .collect()
+        };
+
+        Ok(PackHeader {
+            uuid,
+            date,
+            recipients,
+            ciphertext_len: proto.ciphertext.len(),
+            signature_len: proto.signature.len(),
+        })
+    }
+
+    /// Verify a serialized pack's signature with the given key without
+    /// decrypting it, returning its key fingerprints. This only checks that
+    /// the pack is intact and was signed by `public_key`: nothing about its
+    /// contents is verified.
+    pub fn verify_signature_only(
+        bytes: &[u8],
+        public_key: &PublicKey,
+    ) -> Result<Fingerprints, Error> {
+        let (_, proto) = decode_proto(bytes)?;
+
+        if !public_key.matches_fingerprint(&proto.signing_key_fingerprint) {
+            fail!(
+                InvalidKey,
+                "pack was signed by a different key: {}",
+                proto.signing_key_fingerprint
+            );
+        }
+
+        public_key.verify(&signed_data(&proto), &proto.signature)?;
+
+        Ok(Fingerprints {
+            signing_key: proto.signing_key_fingerprint,
+            encryption_key: proto.encryption_key_fingerprint,
+        })
+    }
+
+    /// Get the signing public key embedded in a serialized pack, without
+    /// verifying anything other than that it matches the pack's signing key
+    /// fingerprint. This is only useful for trust-on-first-use: the key
+    /// itself must come from somewhere trusted before the pack is.
+    pub fn unverified_signing_key(bytes: &[u8]) -> Result<Option<PublicKey>, Error> {
+        let (_, proto) = decode_proto(bytes)?;
+
+        if proto.signing_public_key.is_empty() {
+            return Ok(None);
+        }
+
+        let public_key = PublicKey::from_keyuri(&proto.signing_public_key)?;
+
+        if !public_key.matches_fingerprint(&proto.signing_key_fingerprint) {
+            fail!(
+                InvalidKey,
+                "embedded signing key doesn't match fingerprint: {}",
+                proto.signing_key_fingerprint
+            );
+        }
+
+        Ok(Some(public_key))
+    }
+
+    /// Audit a pack previously loaded from the given serialized bytes,
+    /// checking that the stored fingerprints, the UUID (used as the
+    /// encryption salt), and the signature all agree with each other and
+    /// with the key material in the given keyrings. Unlike
+    /// `verify_and_decrypt`, this reports every inconsistency it finds.
+    #[cfg(feature = "keyrings")]
+    pub fn validate_self_consistency(
+        &self,
+        bytes: &[u8],
+        keyrings: &Keyrings,
+    ) -> Result<(), Error> {
+        let fingerprints = self
+            .fingerprints
+            .as_ref()
+            .ok_or_else(|| err!(Parse, "pack has no fingerprints (not loaded from a file?)"))?;
+
+        let (version, proto) = decode_proto(bytes)?;
+        let mut mismatches = vec![];
+
+        if Uuid::parse_str(&proto.uuid).ok() != Some(self.uuid) {
+            mismatches.push(format!("UUID mismatch: {} != {}", proto.uuid, self.uuid));
+        }
+
+        if normalize_fingerprint(&proto.signing_key_fingerprint)
+            != normalize_fingerprint(&fingerprints.signing_key)
+        {
+            mismatches.push("signing key fingerprint doesn't match pack".to_owned());
+        }
+
+        let single_recipient = proto.recipients.is_empty();
+
+        let encryption_key_in_pack = if single_recipient {
+            normalize_fingerprint(&proto.encryption_key_fingerprint)
+                == normalize_fingerprint(&fingerprints.encryption_key)
+        } else {
+            proto.recipients.iter().any(|recipient| {
+                normalize_fingerprint(&recipient.encryption_key_fingerprint)
+                    == normalize_fingerprint(&fingerprints.encryption_key)
+            })
+        };
+
+        if !encryption_key_in_pack {
+            mismatches.push("encryption key fingerprint doesn't match pack".to_owned());
+        }
+
+        match keyrings
+            .signing
+            .get_by_fingerprint(&fingerprints.signing_key)
+        {
+            Ok(public_key) => {
+                if !public_key.matches_fingerprint(&fingerprints.signing_key) {
+                    mismatches.push("recomputed signing key fingerprint differs".to_owned());
+                }
+
+                if public_key
+                    .verify(&signed_data(&proto), &proto.signature)
+                    .is_err()
+                {
+                    mismatches.push("signature doesn't verify".to_owned());
+                }
+            }
+            Err(e) => mismatches.push(format!("signing key: {}", e)),
+        }
+
+        match keyrings
+            .encryption
+            .get_by_fingerprint(&fingerprints.encryption_key, self.uuid.as_bytes())
+        {
+            Ok(mut encryptor) => {
+                if normalize_fingerprint(encryptor.fingerprint())
+                    != normalize_fingerprint(&fingerprints.encryption_key)
+                {
+                    mismatches.push("recomputed encryption key fingerprint differs".to_owned());
+                }
+
+                // Multi-recipient packs are encrypted under a content key,
+                // whose fingerprint was already checked when the pack was loaded
+                if single_recipient {
+                    let date = proto.date.as_ref().map(|d| d.value.as_slice());
+
+                    let associated_data = associated_data(
+                        version,
+                        date.unwrap_or_default(),
+                        &proto.encryption_key_fingerprint,
+                        &proto.signing_key_fingerprint,
+                    );
+
+                    match encryptor.open(associated_data, &proto.ciphertext) {
+                        Ok(mut plaintext) => plaintext.as_mut_slice().clear(),
+                        Err(_) => mismatches.push(
+                            "ciphertext doesn't decrypt with key derived from UUID".to_owned(),
+                        ),
+                    }
+                }
+            }
+            Err(e) => mismatches.push(format!("encryption key: {}", e)),
+        }
+
+        if !mismatches.is_empty() {
+            fail!(
+                Crypto,
+                "pack {} is inconsistent: {}",
+                self.uuid,
+                mismatches.join("; ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Convert this pack into a delta pack containing only the files which
+    /// were added or changed relative to the given base pack
+    pub fn into_delta(self, base: &Pack) -> Self {
+        let deletions = base
+            .files()
+            .filter(|file| !self.files.iter().any(|f| f.filename == file.filename))
+            .map(|file| file.filename.clone())
+            .collect();
+
+        let files = self
+            .files
+            .into_iter()
+            .filter(|file| {
+                !base
+                    .files()
+                    .any(|f| f.filename == file.filename && f.body == file.body)
+            })
+            .collect();
+
+        Self {
+            base_uuid: Some(base.uuid),
+            deletions,
+            files,
+            ..self
+        }
+    }
+
+    /// Convert this pack into a delta pack like `into_delta`, but also store
+    /// changed files as chunks of their versions in the base pack plus new
+    /// data (see the `chunking` module) wherever that's smaller
+    pub fn into_chunked_delta(self, base: &Pack) -> Self {
+        let mut delta = self.into_delta(base);
+        let mut files = vec![];
+
+        for file in delta.files.drain(..) {
+            let base_file = match base.get_file(&file.filename) {
+                Some(base_file) => base_file,
+                None => {
+                    files.push(file);
+                    continue;
+                }
+            };
+
+            let chunked_file = ChunkedFile {
+                filename: file.filename.clone(),
+                content_type: file.content_type.clone(),
+                modified_at: file.modified_at.clone(),
+                chunks: chunking::encode(&base_file.body, &file.body),
+            };
+
+            if chunked_file.encoded_len() < file.encoded_len() {
+                delta.chunked_files.push(chunked_file);
+            } else {
+                files.push(file);
+            }
+        }
+
+        delta.files = files;
+        delta
+    }
+
+    /// Apply a delta pack on top of this pack, producing a new full pack.
+    ///
+    /// Fails if the delta pack's base is not this pack.
+    pub fn apply_delta(&self, delta: Pack) -> Result<Self, Error> {
+        match delta.base_uuid {
+            Some(ref base_uuid) if base_uuid == &self.uuid => (),
+            Some(ref base_uuid) => fail!(
+                Delta,
+                "delta pack {} applies to base pack {} (current pack is {})",
+                delta.uuid,
+                base_uuid,
+                self.uuid
+            ),
+            None => fail!(Delta, "pack {} is not a delta pack", delta.uuid),
+        }
+
+        let mut files: Vec<PackFile> = self
+            .files()
+            .filter(|file| {
+                !delta.deletions.contains(&file.filename)
+                    && delta.get_file(&file.filename).is_none()
+                    && !delta
+                        .chunked_files
+                        .iter()
+                        .any(|chunked| chunked.filename == file.filename)
+            })
+            .cloned()
+            .collect();
+
+        files.extend(delta.files);
+
+        for chunked in delta.chunked_files {
+            let base_file = self.get_file(&chunked.filename).ok_or_else(|| {
+                err!(
+                    Delta,
+                    "chunked file {} isn't in base pack {}",
+                    chunked.filename,
+                    self.uuid
+                )
+            })?;
+
+            files.push(PackFile {
+                body: chunking::reassemble(
+                    &base_file.body,
+                    &chunked.chunks,
+                    self.limits.max_file_size,
+                )?,
+                filename: chunked.filename,
+                content_type: chunked.content_type,
+                modified_at: chunked.modified_at,
+            });
+        }
+
+        Ok(Self {
+            uuid: delta.uuid,
+            date: delta.date,
+            fingerprints: delta.fingerprints,
+            base_uuid: None,
+            deletions: vec![],
+            description: delta.description,
+            channel: delta.channel,
+            files,
+            chunked_files: vec![],
+            limits: self.limits,
         })
     }
 
+    /// Is this a delta pack (i.e. does it apply on top of a base pack)?
+    pub fn is_delta(&self) -> bool {
+        self.base_uuid.is_some()
+    }
+
+    /// Is this pack empty (i.e. it has no files, and no deletions either)?
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.chunked_files.is_empty() && self.deletions.is_empty()
+    }
+
+    /// Version of the pack format needed to serialize this pack
+    pub fn format_version(&self) -> PackVersion {
+        if self.chunked_files.is_empty() && self.channel.is_none() {
+            PackVersion::CURRENT
+        } else {
+            PackVersion::V0_2
+        }
+    }
+
     /// Encrypt and sign a pack with the given encryptor/signer keys
     #[cfg(feature = "signer")]
     pub fn encrypt_and_sign(
         self,
         encryptor: &mut Encryptor,
         signer: &Signer,
+    ) -> Result<Vec<u8>, Error> {
+        self.seal_and_sign(encryptor, vec![], signer)
+    }
+
+    /// Re-encrypt and re-sign a pack: verify and decrypt it with the old keys,
+    /// then encrypt and sign it with the default keys in `new_keys`. The UUID
+    /// and date are preserved, so delta packs built against it still apply.
+    #[cfg(feature = "signer")]
+    pub fn reencrypt<L>(bytes: &[u8], old_keys: &L, new_keys: &Keyrings) -> Result<Vec<u8>, Error>
+    where
+        L: KeyLookup + ?Sized,
+    {
+        let pack = Self::verify_and_decrypt_with(bytes, old_keys)?;
+        let mut encryptor = new_keys
+            .encryption
+            .get(DEFAULT_KEY_LABEL, pack.uuid.as_bytes())?;
+        let signer = new_keys.signing.get(DEFAULT_KEY_LABEL)?;
+
+        pack.encrypt_and_sign(&mut encryptor, signer)
+    }
+
+    /// Add a cosignature by the given signer to a serialized pack, for packs
+    /// which must be signed by multiple keys (see `SignaturePolicy`)
+    #[cfg(feature = "signer")]
+    pub fn cosign(bytes: &[u8], signer: &Signer) -> Result<Vec<u8>, Error> {
+        let (version, mut proto) = decode_proto(bytes)?;
+        let public_key = signer.public_key()?;
+        let signing_key_fingerprint = public_key.to_fingerprint();
+
+        let already_signed = public_key.matches_fingerprint(&proto.signing_key_fingerprint)
+            || proto
+                .cosignatures
+                .iter()
+                .any(|c| public_key.matches_fingerprint(&c.signing_key_fingerprint));
+
+        if already_signed {
+            fail!(
+                Signature,
+                "pack is already signed by {}",
+                signing_key_fingerprint
+            );
+        }
+
+        let signature = signer.sign(&signed_data(&proto))?.as_ref().into();
+        proto.cosignatures.push(Cosignature {
+            signing_key_fingerprint,
+            signature,
+        });
+
+        let mut output = Vec::from(version.magic_string());
+        proto
+            .encode(&mut output)
+            .map_err(|e| err!(Serialization, "couldn't encode pack: {}", e.to_string()))?;
+
+        ensure_max_size(output.len())?;
+        Ok(output)
+    }
+
+    /// Encrypt and sign a pack for multiple recipients.
+    ///
+    /// The payload is encrypted under a random content key, and a copy of the
+    /// content key is wrapped (i.e. encrypted) under each recipient's key.
+    #[cfg(feature = "signer")]
+    pub fn encrypt_and_sign_for_recipients(
+        self,
+        recipients: &mut [Encryptor],
+        signer: &Signer,
+    ) -> Result<Vec<u8>, Error> {
+        if recipients.is_empty() {
+            fail!(InvalidKey, "no recipients for pack");
+        }
+
+        let date = Tai64n::from(self.date);
+        let signing_key_fingerprint = signer.public_key()?.to_fingerprint();
+        let version = self.format_version();
+
+        let mut content_key = [0u8; ENCRYPTION_KEY_SIZE];
+        random::fill(&mut content_key)?;
+
+        let mut content_keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &content_key).into_bytes();
+        let content_encryptor = Encryptor::from_keyuri(
+            std::str::from_utf8(&content_keyuri).unwrap(),
+            self.uuid.as_bytes(),
+        );
+        content_keyuri.as_mut_slice().clear();
+        let mut content_encryptor = content_encryptor?;
+
+        let mut wrapped_keys = vec![];
+
+        for recipient in recipients.iter_mut() {
+            let encryption_key_fingerprint = recipient.fingerprint().to_owned();
+            let wrapped_key = recipient.seal(
+                associated_data(
+                    version,
+                    &date.value,
+                    &encryption_key_fingerprint,
+                    &signing_key_fingerprint,
+                ),
+                &content_key,
+            );
+
+            wrapped_keys.push(Recipient {
+                encryption_key_fingerprint,
+                wrapped_key,
+            });
+        }
+
+        content_key.clear();
+        self.seal_and_sign(&mut content_encryptor, wrapped_keys, signer)
+    }
+
+    /// Get the UUID for this pack
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// Get the date when this pack was produced
+    pub fn date(&self) -> &DateTime<Utc> {
+        &self.date
+    }
+
+    /// Iterate over the files in this pack
+    pub fn files(&self) -> Iter<'_, PackFile> {
+        self.files.iter()
+    }
+
+    /// Group the files in this pack by the directory they're placed in
+    pub fn files_by_dir(&self) -> BTreeMap<PathBuf, Vec<&PackFile>> {
+        let mut result: BTreeMap<PathBuf, Vec<&PackFile>> = BTreeMap::new();
+
+        for file in &self.files {
+            let dir = Path::new(&file.filename)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+
+            result.entry(dir).or_default().push(file);
+        }
+
+        result
+    }
+
+    /// Get the file with the given name (exact match), if it's in this pack
+    pub fn get_file(&self, filename: &str) -> Option<&PackFile> {
+        self.files.iter().find(|file| file.filename == filename)
+    }
+
+    /// Get the number of files in this pack
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Encrypt the payload with the given encryptor, sign it, and serialize the pack
+    #[cfg(feature = "signer")]
+    fn seal_and_sign(
+        self,
+        encryptor: &mut Encryptor,
+        recipients: Vec<Recipient>,
+        signer: &Signer,
     ) -> Result<Vec<u8>, Error> {
         let date = Tai64n::from(self.date);
         let uuid = self.uuid.to_string();
         let encryption_key_fingerprint = encryptor.fingerprint().to_owned();
         let signing_public_key = signer.public_key()?;
         let signing_key_fingerprint = signing_public_key.to_fingerprint();
+        let version = self.format_version();
 
         let mut plaintext = self.serialize()?;
         let ciphertext = encryptor.seal(
-            &[
+            associated_data(
+                version,
                 &date.value,
-                encryption_key_fingerprint.as_bytes(),
-                signing_key_fingerprint.as_bytes(),
-            ],
+                &encryption_key_fingerprint,
+                &signing_key_fingerprint,
+            ),
             &plaintext,
         );
-        plaintext.clear();
+        plaintext.as_mut_slice().clear();
 
-        let signature = signer.sign(&ciphertext)?.as_ref().into();
-        let mut output = Vec::from(PACK_V0_MAGIC_STRING);
-
-        let proto = PackProto {
+        let mut proto = PackProto {
             uuid,
             date: Some(date),
             signing_key_fingerprint,
             encryption_key_fingerprint,
-            signature,
+            signature: vec![],
+            recipients,
+            signing_public_key: signing_public_key.to_keyuri(),
+            cosignatures: vec![],
             ciphertext,
         };
 
+        proto.signature = signer.sign(&signed_data(&proto))?.as_ref().into();
+        let mut output = Vec::from(version.magic_string());
+
         proto
             .encode(&mut output)
             .map_err(|e| err!(Serialization, "couldn't encode pack: {}", e.to_string()))?;
@@ -245,26 +1165,564 @@ impl Pack {
         Ok(output)
     }
 
-    /// Iterate over the files in this pack
-    pub fn files(&self) -> Iter<'_, PackFile> {
-        self.files.iter()
-    }
-
     /// Serialize the payload of a pack
     #[cfg(feature = "signer")]
     fn serialize(self) -> Result<Vec<u8>, Error> {
+        if self.is_empty() {
+            fail!(
+                Serialization,
+                "refusing to serialize an empty pack (no files or deletions)"
+            );
+        }
+
+        self.limits.check(&self.files, &self.chunked_files)?;
+
         let mut output = vec![];
-        Payload { files: self.files }.encode(&mut output).unwrap();
+        let payload = Payload {
+            files: self.files,
+            base_uuid: self.base_uuid.map(|u| u.to_string()).unwrap_or_default(),
+            deletions: self.deletions,
+            description: self.description.unwrap_or_default(),
+            chunked_files: self.chunked_files,
+            channel: self.channel.unwrap_or_default(),
+        };
+        payload.encode(&mut output).unwrap();
+        ensure_max_size(output.len())?;
+        Ok(output)
+    }
+}
 
-        if output.len() > MAX_PACK_SIZE {
-            fail!(
+impl IntoIterator for Pack {
+    type Item = PackFile;
+    type IntoIter = IntoIter<PackFile>;
+
+    fn into_iter(self) -> IntoIter<PackFile> {
+        self.files.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Pack {
+    type Item = &'a PackFile;
+    type IntoIter = Iter<'a, PackFile>;
+
+    fn into_iter(self) -> Iter<'a, PackFile> {
+        self.files()
+    }
+}
+
+/// Get the (normalized) key fingerprints needed to verify and decrypt all of
+/// the given packs, without needing any keys. For packs with multiple
+/// recipients, every recipient's fingerprints are included, since any one of
+/// them is sufficient.
+pub fn required_fingerprints<P>(paths: &[P]) -> Result<BTreeSet<Fingerprints>, Error>
+where
+    P: AsRef<Path>,
+{
+    let mut result = BTreeSet::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        let bytes = fs::read(path)
+            .map_err(|e| err!(Io, "couldn't read {}: {}", path.to_string_lossy(), e))?;
+
+        let header =
+            Pack::inspect(&bytes).map_err(|e| err!(Parse, "{}: {}", path.to_string_lossy(), e))?;
+
+        for fingerprints in header.recipients {
+            result.insert(Fingerprints {
+                signing_key: normalize_fingerprint(&fingerprints.signing_key),
+                encryption_key: normalize_fingerprint(&fingerprints.encryption_key),
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+/// Get the data covered by a pack's signature(s): its ciphertext, preceded by
+/// the recipients of multi-recipient packs (so they can't be added, removed,
+/// or have their wrapped content keys replaced)
+fn signed_data(proto: &PackProto) -> Cow<'_, [u8]> {
+    if proto.recipients.is_empty() {
+        return Cow::Borrowed(&proto.ciphertext);
+    }
+
+    let mut data = vec![];
+    data.extend_from_slice(&(proto.recipients.len() as u32).to_be_bytes());
+
+    for recipient in &proto.recipients {
+        for field in &[
+            recipient.encryption_key_fingerprint.as_bytes(),
+            recipient.wrapped_key.as_slice(),
+        ] {
+            data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            data.extend_from_slice(field);
+        }
+    }
+
+    data.extend_from_slice(&proto.ciphertext);
+    Cow::Owned(data)
+}
+
+/// Find a recipient of a multi-recipient pack we have a key for, returning
+/// the signing public key and the recipient's key (which the content key can
+/// be unwrapped with once the signature has been verified)
+fn find_recipient<'a, L>(
+    proto: &'a PackProto,
+    uuid: &Uuid,
+    key_lookup: &L,
+) -> Result<(PublicKey, Encryptor, &'a Recipient, Fingerprints), Error>
+where
+    L: KeyLookup + ?Sized,
+{
+    let mut last_error = None;
+
+    for recipient in &proto.recipients {
+        let fingerprints = Fingerprints {
+            signing_key: proto.signing_key_fingerprint.clone(),
+            encryption_key: recipient.encryption_key_fingerprint.clone(),
+        };
+
+        match key_lookup.lookup(&fingerprints, uuid) {
+            Ok((public_key, recipient_key)) => {
+                return Ok((public_key, recipient_key, recipient, fingerprints))
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => fail!(
+            InvalidKey,
+            "key lookup failed for all {} recipients (last error: {})",
+            proto.recipients.len(),
+            e
+        ),
+        None => fail!(InvalidKey, "key lookup failed"),
+    }
+}
+
+/// Unwrap the content key of a multi-recipient pack with the given
+/// recipient's key. Only call this once the pack's signature is verified.
+fn unwrap_content_key(
+    proto: &PackProto,
+    version: PackVersion,
+    date: &Tai64n,
+    uuid: &Uuid,
+    recipient: &Recipient,
+    mut recipient_key: Encryptor,
+) -> Result<Encryptor, Error> {
+    let mut content_key = recipient_key
+        .open(
+            associated_data(
+                version,
+                &date.value,
+                &recipient.encryption_key_fingerprint,
+                &proto.signing_key_fingerprint,
+            ),
+            &recipient.wrapped_key,
+        )
+        .map_err(|_| err!(Crypto, "couldn't unwrap content key"))?;
+
+    let mut content_keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &content_key).into_bytes();
+    content_key.as_mut_slice().clear();
+
+    let encryptor = Encryptor::from_keyuri(
+        std::str::from_utf8(&content_keyuri).unwrap(),
+        uuid.as_bytes(),
+    );
+    content_keyuri.as_mut_slice().clear();
+    let encryptor = encryptor?;
+
+    if encryptor.fingerprint() != proto.encryption_key_fingerprint {
+        fail!(Crypto, "content key fingerprint mismatch");
+    }
+
+    Ok(encryptor)
+}
+
+/// Get the associated data for encrypting a pack's payload or wrapping its
+/// content key. Packs after v0.1 also authenticate their magic string, so
+/// they can't be relabelled as an older version which ignores their features.
+fn associated_data<'a>(
+    version: PackVersion,
+    date: &'a [u8],
+    encryption_key_fingerprint: &'a str,
+    signing_key_fingerprint: &'a str,
+) -> Vec<&'a [u8]> {
+    let mut associated_data = vec![
+        date,
+        encryption_key_fingerprint.as_bytes(),
+        signing_key_fingerprint.as_bytes(),
+    ];
+
+    if version != PackVersion::V0_1 {
+        associated_data.push(version.magic_string());
+    }
+
+    associated_data
+}
+
+/// Check a serialized pack's magic string and decode its protobuf
+fn decode_proto(bytes: &[u8]) -> Result<(PackVersion, PackProto), Error> {
+    if bytes.len() < PACK_V0_MAGIC_STRING.len() {
+        fail!(
+            Parse,
+            "pack too short: expected at least {} bytes, got {}",
+            PACK_V0_MAGIC_STRING.len(),
+            bytes.len()
+        );
+    }
+
+    let (version, body) = PackVersion::parse(bytes)?;
+    let proto = PackProto::decode(body).map_err(|e| err!(Parse, "pack parsing error: {}", e))?;
+
+    Ok((version, proto))
+}
+
+/// Ensure a serialized pack doesn't exceed `MAX_PACK_SIZE`
+fn ensure_max_size(len: usize) -> Result<(), Error> {
+    if len > MAX_PACK_SIZE {
+        fail!(
+            Serialization,
+            "pack too large: {}-bytes (max {})",
+            len,
+            MAX_PACK_SIZE
+        )
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkedFile, Limits, Pack, PackFile, PackVersion, Uuid, MAX_PACK_SIZE};
+    use crate::error::Error;
+    use chrono::Utc;
+    use std::io;
+    use std::path::Path;
+    #[cfg(feature = "signer")]
+    use std::path::PathBuf;
+
+    /// Build a (non-delta) pack containing the given files
+    fn example_pack(uuid: Uuid, files: Vec<PackFile>) -> Pack {
+        Pack {
+            uuid,
+            date: Utc::now(),
+            fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: None,
+            channel: None,
+            files,
+            chunked_files: vec![],
+            limits: Default::default(),
+        }
+    }
+
+    fn example_file(filename: &str, body: Vec<u8>) -> PackFile {
+        PackFile {
+            filename: filename.to_owned(),
+            content_type: String::new(),
+            modified_at: None,
+            body,
+        }
+    }
+
+    /// Pseudorandom (but deterministic) file contents, large enough to chunk
+    fn example_body(seed: u64) -> Vec<u8> {
+        let mut state = seed;
+
+        (0..100_000)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    /// Files of a base pack and of a newer version of it: `/etc/big` has an
+    /// appended line, `/etc/gone` was deleted, and `/etc/new` was added
+    fn example_versions() -> (Vec<PackFile>, Vec<PackFile>) {
+        let base_body = example_body(1);
+        let mut new_body = base_body.clone();
+        new_body.extend_from_slice(b"one more line\n");
+
+        let base_files = vec![
+            example_file("/etc/big", base_body),
+            example_file("/etc/gone", b"old".to_vec()),
+            example_file("/etc/same", b"same".to_vec()),
+        ];
+
+        let new_files = vec![
+            example_file("/etc/big", new_body),
+            example_file("/etc/new", b"new".to_vec()),
+            example_file("/etc/same", b"same".to_vec()),
+        ];
+
+        (base_files, new_files)
+    }
+
+    fn sorted_files(pack: &Pack) -> Vec<(&str, &[u8])> {
+        let mut files: Vec<_> = pack
+            .files()
+            .map(|file| (file.filename.as_str(), file.body.as_slice()))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_from_reader_oversize() {
+        // An endless reader must be rejected after `MAX_PACK_SIZE + 1` bytes
+        let result = Pack::from_reader(io::repeat(0), |_, _| None);
+
+        assert_eq!(
+            result.err().unwrap(),
+            err!(
                 Serialization,
                 "pack too large: {}-bytes (max {})",
-                output.len(),
+                MAX_PACK_SIZE + 1,
                 MAX_PACK_SIZE
             )
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = b"placer-pack:v0.3".to_vec();
+        bytes.extend_from_slice(&[0x0a, 0x24, 0x00]);
+
+        assert_eq!(
+            Pack::inspect(&bytes).unwrap_err(),
+            err!(
+                Parse,
+                "unsupported pack version v0.3 (this build supports v0.1, v0.2)"
+            )
+        );
+
+        // Supported magic strings must match in full (not as a prefix)
+        let mut bytes = b"placer-pack:v0.10".to_vec();
+        bytes.extend_from_slice(&[0x0a, 0x24, 0x00]);
+
+        assert_eq!(
+            Pack::inspect(&bytes).unwrap_err(),
+            err!(
+                Parse,
+                "unsupported pack version v0.10 (this build supports v0.1, v0.2)"
+            )
+        );
+
+        assert_eq!(
+            Pack::inspect(b"not-a-placer-pack").unwrap_err(),
+            err!(
+                Parse,
+                "pack does not start with magic string (\"placer-pack:\")"
+            )
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_files: 1,
+            max_file_size: 4,
+        };
+
+        let file = |body: &[u8]| PackFile {
+            filename: "/example".to_owned(),
+            content_type: "text/plain".to_owned(),
+            modified_at: None,
+            body: body.to_vec(),
+        };
+
+        assert!(limits.check(&[file(b"ok")], &[]).is_ok());
+
+        match limits.check(&[file(b"ok"), file(b"ok")], &[]) {
+            Err(Error::TooManyFiles { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
         }
 
-        Ok(output)
+        let chunked_file = ChunkedFile {
+            filename: "/chunked".to_owned(),
+            content_type: "text/plain".to_owned(),
+            modified_at: None,
+            chunks: vec![],
+        };
+
+        match limits.check(&[file(b"ok")], &[chunked_file]) {
+            Err(Error::TooManyFiles { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match limits.check(&[file(b"too big")], &[]) {
+            Err(Error::FileTooLarge { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_files_by_dir() {
+        let file = |filename: &str| PackFile {
+            filename: filename.to_owned(),
+            content_type: String::new(),
+            modified_at: None,
+            body: vec![],
+        };
+
+        let pack = Pack {
+            uuid: Uuid::nil(),
+            date: Utc::now(),
+            fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: None,
+            channel: None,
+            files: vec![file("/etc/a.conf"), file("/var/b"), file("/etc/c.conf")],
+            chunked_files: vec![],
+            limits: Default::default(),
+        };
+
+        let by_dir = pack.files_by_dir();
+        let filenames = |dir: &str| -> Vec<&str> {
+            by_dir[Path::new(dir)]
+                .iter()
+                .map(|file| file.filename.as_str())
+                .collect()
+        };
+
+        assert_eq!(by_dir.len(), 2);
+        assert_eq!(filenames("/etc"), vec!["/etc/a.conf", "/etc/c.conf"]);
+        assert_eq!(filenames("/var"), vec!["/var/b"]);
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let (base_files, new_files) = example_versions();
+        let base = example_pack(Uuid::new_v4(), base_files);
+        let expected = example_pack(Uuid::new_v4(), example_versions().1);
+
+        let delta = example_pack(expected.uuid, new_files).into_delta(&base);
+        assert_eq!(delta.base_uuid, Some(base.uuid));
+        assert_eq!(delta.deletions, vec!["/etc/gone".to_owned()]);
+        assert!(delta.chunked_files.is_empty());
+
+        let mut changed: Vec<_> = delta.files().map(|file| file.filename.as_str()).collect();
+        changed.sort();
+        assert_eq!(changed, vec!["/etc/big", "/etc/new"]);
+
+        let applied = base.apply_delta(delta).unwrap();
+        assert_eq!(applied.uuid(), expected.uuid());
+        assert!(!applied.is_delta());
+        assert_eq!(sorted_files(&applied), sorted_files(&expected));
+    }
+
+    #[test]
+    fn test_chunked_delta_roundtrip() {
+        let (base_files, new_files) = example_versions();
+        let base = example_pack(Uuid::new_v4(), base_files);
+        let expected = example_pack(Uuid::new_v4(), example_versions().1);
+
+        let delta = example_pack(expected.uuid, new_files).into_chunked_delta(&base);
+        assert_eq!(delta.base_uuid, Some(base.uuid));
+        assert_eq!(delta.deletions, vec!["/etc/gone".to_owned()]);
+        assert_eq!(delta.chunked_files.len(), 1);
+        assert_eq!(delta.chunked_files[0].filename, "/etc/big");
+        assert_eq!(delta.file_count(), 1);
+        assert_eq!(delta.format_version(), PackVersion::V0_2);
+
+        let applied = base.apply_delta(delta).unwrap();
+        assert_eq!(applied.uuid(), expected.uuid());
+        assert_eq!(sorted_files(&applied), sorted_files(&expected));
+    }
+
+    #[test]
+    fn test_apply_delta_wrong_base() {
+        let (base_files, new_files) = example_versions();
+        let base = example_pack(Uuid::new_v4(), base_files);
+        let other = example_pack(Uuid::new_v4(), example_versions().0);
+
+        let delta = example_pack(Uuid::new_v4(), new_files).into_chunked_delta(&base);
+
+        match other.apply_delta(delta) {
+            Err(Error::Delta { .. }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|pack| pack.uuid)),
+        }
+
+        match base.apply_delta(example_pack(Uuid::new_v4(), vec![])) {
+            Err(Error::Delta { .. }) => (),
+            other => panic!("unexpected result: {:?}", other.map(|pack| pack.uuid)),
+        }
+    }
+
+    #[test]
+    fn test_apply_delta_missing_base_file() {
+        let (base_files, new_files) = example_versions();
+        let base = example_pack(Uuid::new_v4(), base_files);
+        let delta = example_pack(Uuid::new_v4(), new_files).into_chunked_delta(&base);
+
+        // Same UUID as the base pack, but without the file the delta chunks
+        let incomplete_base = example_pack(base.uuid, vec![]);
+
+        match incomplete_base.apply_delta(delta) {
+            Err(Error::Delta { description }) => {
+                assert!(description.contains("/etc/big"), "{}", description)
+            }
+            other => panic!("unexpected result: {:?}", other.map(|pack| pack.uuid)),
+        }
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn test_reproducible() {
+        use crate::crypto::{Encryptor, Signer};
+        use crate::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX};
+        use chrono::{TimeZone, Utc};
+
+        let uuid = Uuid::parse_str("c5b4e0b1-3f4c-4e0e-9a7b-2d0c5f0e6a41").unwrap();
+        let date = Utc.timestamp(1_500_000_000, 0);
+        let keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[2u8; 32]);
+        let signer = Signer::from_bytes(&[1u8; 32]).unwrap();
+
+        let build = || {
+            let mut pack =
+                Pack::create(uuid, Path::new("."), &[PathBuf::from("Cargo.toml")]).unwrap();
+            pack.set_reproducible_date(date);
+
+            let mut encryptor = Encryptor::from_keyuri(&keyuri, uuid.as_bytes()).unwrap();
+            pack.encrypt_and_sign(&mut encryptor, &signer).unwrap()
+        };
+
+        assert_eq!(build(), build());
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn test_create_empty() {
+        let result = Pack::create(Uuid::new_v4(), Path::new("."), &[]);
+
+        match result {
+            Err(Error::Serialization { .. }) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("created an empty pack"),
+        }
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn test_create_outside_base() {
+        let result = Pack::create(
+            Uuid::new_v4(),
+            Path::new("src"),
+            &[PathBuf::from("../Cargo.toml")],
+        );
+
+        match result {
+            Err(Error::Io { .. }) => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("packed a file outside of the base"),
+        }
     }
 }
@@ -2,11 +2,27 @@
 
 #![allow(dead_code)]
 
+use clear_on_drop::clear::Clear;
+
 /// Encrypted + signed packs of files
 pub mod pack {
     include!(concat!(env!("OUT_DIR"), "/placer.pack.rs"));
 }
 
+/// Wipe decrypted file bodies (which may be secrets) from memory
+impl Drop for pack::File {
+    fn drop(&mut self) {
+        self.body.as_mut_slice().clear();
+    }
+}
+
+/// Wipe new data in chunked files (decrypted pieces of file bodies)
+impl Drop for pack::Chunk {
+    fn drop(&mut self) {
+        self.data.as_mut_slice().clear();
+    }
+}
+
 /// TAI64N timestamps
 pub mod timestamp {
     use chrono::{DateTime, Utc};
@@ -0,0 +1,408 @@
+//! HTTP client configuration (custom CA bundles, public key pinning,
+//! redirects, authentication, proxies, and address families)
+
+use failure::{bail, format_err, Error};
+use reqwest::header::HeaderMap;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client as HttpClient, ClientBuilder, Proxy, Response};
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{ClientConfig, RootCertStore, ServerName};
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::ResourceHash;
+
+/// Environment variable containing the path to a PEM-encoded CA bundle.
+/// If set, only these CAs are trusted (instead of the system trust store).
+pub const CA_BUNDLE_ENV_VAR: &str = "PLACER_HTTP_CA_BUNDLE";
+
+/// Environment variable containing a Base64-encoded SHA-256 digest of the
+/// server's SubjectPublicKeyInfo (SPKI), optionally prefixed with `sha256/`
+pub const SPKI_PIN_ENV_VAR: &str = "PLACER_HTTP_SPKI_PIN";
+
+/// Environment variable containing a bearer token to send with requests
+pub const BEARER_TOKEN_ENV_VAR: &str = "PLACER_HTTP_BEARER_TOKEN";
+
+/// Environment variable containing `user:password` credentials for HTTP
+/// Basic authentication
+pub const BASIC_AUTH_ENV_VAR: &str = "PLACER_HTTP_BASIC_AUTH";
+
+/// Environment variable containing the URL of a proxy to use for all
+/// requests, or `none` to ignore the system proxy settings (`https_proxy` etc)
+pub const PROXY_ENV_VAR: &str = "PLACER_HTTP_PROXY";
+
+/// Environment variable containing the URL of a proxy for `https://` URLs
+pub const HTTPS_PROXY_ENV_VAR: &str = "PLACER_HTTP_HTTPS_PROXY";
+
+/// Environment variable containing the URL of a proxy for `http://` URLs
+pub const HTTP_PROXY_ENV_VAR: &str = "PLACER_HTTP_HTTP_PROXY";
+
+/// Environment variable restricting connections to one address family
+/// (either `4` for IPv4 or `6` for IPv6)
+pub const IP_VERSION_ENV_VAR: &str = "PLACER_HTTP_IP_VERSION";
+
+/// Value of `PROXY_ENV_VAR` which disables proxies entirely
+const NO_PROXY: &str = "none";
+
+/// Maximum number of redirects to follow for a single request
+pub const MAX_REDIRECTS: usize = 5;
+
+/// Prefix for SPKI pins (as used by HPKP)
+const SPKI_PIN_PREFIX: &str = "sha256/";
+
+/// PEM boundary which ends each certificate in a CA bundle
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// HTTP client which enforces the configured TLS trust settings
+#[derive(Clone)]
+pub struct Client {
+    /// Underlying reqwest client
+    http: HttpClient,
+
+    /// Credentials to authenticate requests with
+    credentials: Option<Credentials>,
+
+    /// How connections are made, for describing connection errors
+    network: Network,
+}
+
+/// Network settings for outgoing connections
+#[derive(Clone, Debug)]
+struct Network {
+    /// Address family connections are restricted to (if any)
+    ip_version: Option<IpVersion>,
+
+    /// Are requests sent through an explicitly configured proxy?
+    proxied: bool,
+}
+
+/// IP address families
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum IpVersion {
+    /// IPv4 only
+    V4,
+
+    /// IPv6 only
+    V6,
+}
+
+/// Credentials for authenticated endpoints
+#[derive(Clone)]
+enum Credentials {
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+
+    /// HTTP Basic authentication
+    Basic {
+        /// Username
+        user: String,
+
+        /// Password (if any)
+        password: Option<String>,
+    },
+}
+
+impl Client {
+    /// Create a client, reading TLS configuration from the environment
+    pub fn from_env() -> Result<Self, Error> {
+        let mut builder = HttpClient::builder()
+            .danger_accept_invalid_certs(false)
+            .redirect(redirect_policy());
+
+        let ca_bundle = env::var_os(CA_BUNDLE_ENV_VAR);
+
+        if let Some(pin) = read_env_var(SPKI_PIN_ENV_VAR)? {
+            // The pin is checked during the TLS handshake, so nothing
+            // (including credentials) is ever sent to a server which doesn't
+            // match it, and every redirect is checked too. Plaintext HTTP
+            // can't be pinned, so it's refused entirely.
+            builder = builder
+                .use_preconfigured_tls(pinned_tls_config(
+                    parse_spki_pin(&pin)?,
+                    ca_bundle.as_deref(),
+                )?)
+                .https_only(true);
+        } else if let Some(path) = ca_bundle {
+            let bundle = fs::read_to_string(&path).map_err(|e| {
+                format_err!("couldn't read CA bundle {}: {}", path.to_string_lossy(), e)
+            })?;
+
+            // Trust only the CAs in the bundle, never the system trust store
+            builder = builder.tls_built_in_root_certs(false);
+
+            for pem in bundle.split_inclusive(PEM_CERTIFICATE_END) {
+                if pem.contains(PEM_CERTIFICATE_END) {
+                    builder = builder.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+                }
+            }
+        }
+
+        let (builder, network) = Network::from_env()?.configure(builder)?;
+
+        Ok(Self {
+            http: builder.build()?,
+            credentials: credentials_from_env()?,
+            network,
+        })
+    }
+
+    /// Send a GET request for the given URL
+    pub async fn get(&self, url: &str, headers: HeaderMap) -> Result<Response, Error> {
+        let mut request = self.http.get(url).headers(headers);
+
+        match self.credentials {
+            Some(Credentials::Bearer(ref token)) => request = request.bearer_auth(token),
+            Some(Credentials::Basic {
+                ref user,
+                ref password,
+            }) => request = request.basic_auth(user, password.as_ref()),
+            None => (),
+        }
+
+        request.send().await.map_err(|e| {
+            if e.is_connect() {
+                format_err!("couldn't connect ({}): {}", self.network, e)
+            } else {
+                e.into()
+            }
+        })
+    }
+}
+
+/// Verifies server certificates against the trusted CAs, then checks the
+/// SubjectPublicKeyInfo of the server's certificate matches the pin
+struct PinnedVerifier {
+    /// Verifier for the certificate chain
+    inner: WebPkiVerifier,
+
+    /// SHA-256 digest of the server's SubjectPublicKeyInfo
+    spki_pin: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        let (_, certificate) = x509_parser::parse_x509_certificate(&end_entity.0).map_err(|e| {
+            rustls::Error::General(format!("couldn't parse server certificate: {}", e))
+        })?;
+
+        if ResourceHash::digest(certificate.public_key().raw).0 != self.spki_pin {
+            return Err(rustls::Error::General(format!(
+                "SPKI pin mismatch (check {})",
+                SPKI_PIN_ENV_VAR
+            )));
+        }
+
+        Ok(verified)
+    }
+}
+
+/// Create a TLS configuration which only trusts servers matching the given
+/// SPKI pin and signed by the CAs in the given bundle (or the system trust
+/// store if there's no bundle)
+fn pinned_tls_config(spki_pin: [u8; 32], ca_bundle: Option<&OsStr>) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+
+    let certificates = match ca_bundle {
+        Some(path) => fs::read(path)
+            .and_then(|bundle| rustls_pemfile::certs(&mut bundle.as_slice()))
+            .map_err(|e| {
+                format_err!("couldn't read CA bundle {}: {}", path.to_string_lossy(), e)
+            })?,
+        None => rustls_native_certs::load_native_certs()
+            .map_err(|e| format_err!("couldn't load system trust store: {}", e))?
+            .into_iter()
+            .map(|certificate| certificate.0)
+            .collect(),
+    };
+
+    let (_, unparsable) = roots.add_parsable_certificates(&certificates);
+
+    if unparsable > 0 {
+        eprintln!("ignoring {} unparsable CA certificate(s)", unparsable);
+    }
+
+    if roots.is_empty() {
+        bail!("no trusted CA certificates");
+    }
+
+    let verifier = PinnedVerifier {
+        inner: WebPkiVerifier::new(roots, None),
+        spki_pin,
+    };
+
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+impl Network {
+    /// Read network settings from the environment
+    fn from_env() -> Result<Self, Error> {
+        let ip_version = match read_env_var(IP_VERSION_ENV_VAR)?.as_deref().map(str::trim) {
+            None => None,
+            Some("4") => Some(IpVersion::V4),
+            Some("6") => Some(IpVersion::V6),
+            Some(other) => bail!(
+                "invalid {}: {:?} (expected 4 or 6)",
+                IP_VERSION_ENV_VAR,
+                other
+            ),
+        };
+
+        Ok(Self {
+            ip_version,
+            proxied: false,
+        })
+    }
+
+    /// Apply these settings (and any configured proxies) to a client builder
+    fn configure(mut self, mut builder: ClientBuilder) -> Result<(ClientBuilder, Self), Error> {
+        builder = match self.ip_version {
+            // Binding to an unspecified local address of one family restricts
+            // connections to remote addresses of the same family
+            Some(IpVersion::V4) => builder.local_address(IpAddr::from(Ipv4Addr::UNSPECIFIED)),
+            Some(IpVersion::V6) => builder.local_address(IpAddr::from(Ipv6Addr::UNSPECIFIED)),
+            None => builder,
+        };
+
+        for &name in &[PROXY_ENV_VAR, HTTPS_PROXY_ENV_VAR, HTTP_PROXY_ENV_VAR] {
+            let url = match read_env_var(name)? {
+                Some(url) => url.trim().to_owned(),
+                None => continue,
+            };
+
+            if name == PROXY_ENV_VAR && url == NO_PROXY {
+                builder = builder.no_proxy();
+                continue;
+            }
+
+            let proxy = match name {
+                PROXY_ENV_VAR => Proxy::all(url.as_str()),
+                HTTPS_PROXY_ENV_VAR => Proxy::https(url.as_str()),
+                _ => Proxy::http(url.as_str()),
+            }
+            .map_err(|e| format_err!("invalid {}: {}", name, e))?;
+
+            // Configuring any proxy disables the system proxy settings
+            builder = builder.proxy(proxy);
+            self.proxied = true;
+        }
+
+        Ok((builder, self))
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip_version {
+            Some(IpVersion::V4) => write!(f, "IPv4 only")?,
+            Some(IpVersion::V6) => write!(f, "IPv6 only")?,
+            None => write!(f, "IPv4 or IPv6")?,
+        }
+
+        if self.proxied {
+            write!(f, ", via configured proxy")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Follow a bounded number of redirects, logging each one so operators can
+/// update their configuration to point at the new location
+fn redirect_policy() -> Policy {
+    Policy::custom(|attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            let error = format!("too many redirects (max {})", MAX_REDIRECTS);
+            return attempt.error(error);
+        }
+
+        eprintln!(
+            "following {} redirect: {} -> {}",
+            attempt.status(),
+            attempt.previous().last().map(|u| u.as_str()).unwrap_or(""),
+            attempt.url()
+        );
+
+        attempt.follow()
+    })
+}
+
+/// Read authentication credentials from the environment
+fn credentials_from_env() -> Result<Option<Credentials>, Error> {
+    let token = read_env_var(BEARER_TOKEN_ENV_VAR)?;
+    let basic_auth = read_env_var(BASIC_AUTH_ENV_VAR)?;
+
+    match (token, basic_auth) {
+        (Some(_), Some(_)) => bail!(
+            "only one of {} or {} can be set",
+            BEARER_TOKEN_ENV_VAR,
+            BASIC_AUTH_ENV_VAR
+        ),
+        (Some(token), None) => Ok(Some(Credentials::Bearer(token))),
+        (None, Some(basic_auth)) => {
+            let mut parts = basic_auth.splitn(2, ':');
+            let user = parts.next().unwrap().to_owned();
+            let password = parts.next().map(str::to_owned);
+            Ok(Some(Credentials::Basic { user, password }))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Read an optional environment variable
+pub fn read_env_var(name: &str) -> Result<Option<String>, Error> {
+    match env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => bail!("invalid {}: {}", name, e),
+    }
+}
+
+/// Parse a Base64-encoded SHA-256 SPKI pin
+fn parse_spki_pin(pin: &str) -> Result<[u8; 32], Error> {
+    let encoded = pin.trim();
+    let encoded = if encoded.starts_with(SPKI_PIN_PREFIX) {
+        &encoded[SPKI_PIN_PREFIX.len()..]
+    } else {
+        encoded
+    };
+
+    let decoded =
+        base64::decode(encoded).map_err(|e| format_err!("invalid {}: {}", SPKI_PIN_ENV_VAR, e))?;
+
+    if decoded.len() != 32 {
+        bail!(
+            "invalid {}: expected 32-byte SHA-256 digest, got {} bytes",
+            SPKI_PIN_ENV_VAR,
+            decoded.len()
+        );
+    }
+
+    let mut result = [0u8; 32];
+    result.copy_from_slice(&decoded);
+    Ok(result)
+}
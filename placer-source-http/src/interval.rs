@@ -0,0 +1,124 @@
+//! How long to wait between fetches of each URL
+
+use failure::{bail, Error};
+use rand::Rng;
+use std::time::Duration;
+
+use crate::client::read_env_var;
+
+/// Environment variable which, if set to `1`, disables the randomized jitter
+/// and fetches every `MIN_JITTER_SECS` seconds instead (for testing)
+pub const NO_JITTER_ENV_VAR: &str = "PLACER_HTTP_NO_JITTER";
+
+/// Environment variable containing a fixed interval (in milliseconds) to
+/// wait between fetches instead of the randomized jitter (for testing)
+pub const INTERVAL_ENV_VAR: &str = "PLACER_HTTP_INTERVAL_MS";
+
+/// Minimum number of seconds to wait between fetches by default
+const MIN_JITTER_SECS: u64 = 1;
+
+/// Maximum number of seconds (exclusive) to wait between fetches by default
+const MAX_JITTER_SECS: u64 = 15;
+
+/// Interval to wait between fetches
+#[derive(Copy, Clone, Debug)]
+pub enum Interval {
+    /// Wait a random number of seconds (the default)
+    Jittered,
+
+    /// Always wait the same amount of time
+    Fixed(Duration),
+}
+
+impl Interval {
+    /// Read the fetch interval from the environment
+    pub fn from_env() -> Result<Self, Error> {
+        if let Some(millis) = read_env_var(INTERVAL_ENV_VAR)? {
+            return match millis.trim().parse() {
+                Ok(millis) => Ok(Interval::Fixed(Duration::from_millis(millis))),
+                Err(e) => bail!("invalid {}: {}", INTERVAL_ENV_VAR, e),
+            };
+        }
+
+        match read_env_var(NO_JITTER_ENV_VAR)?.as_deref() {
+            None | Some("") | Some("0") => Ok(Interval::Jittered),
+            Some("1") => Ok(Interval::Fixed(Duration::from_secs(MIN_JITTER_SECS))),
+            Some(other) => bail!("invalid {} (expected 0 or 1): {}", NO_JITTER_ENV_VAR, other),
+        }
+    }
+
+    /// Get the amount of time to wait before the next fetch
+    pub fn next(self) -> Duration {
+        match self {
+            Interval::Jittered => {
+                Duration::from_secs(rand::thread_rng().gen_range(MIN_JITTER_SECS, MAX_JITTER_SECS))
+            }
+            Interval::Fixed(duration) => duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interval, INTERVAL_ENV_VAR, MIN_JITTER_SECS, NO_JITTER_ENV_VAR};
+    use std::env;
+    use std::time::Duration;
+
+    /// Read the interval with the given environment variables set (or unset).
+    /// Only one test touches these variables, as the environment is shared
+    /// by every test thread.
+    fn interval_with(interval_ms: Option<&str>, no_jitter: Option<&str>) -> Option<Interval> {
+        for (name, value) in &[
+            (INTERVAL_ENV_VAR, interval_ms),
+            (NO_JITTER_ENV_VAR, no_jitter),
+        ] {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+
+        let result = Interval::from_env().ok();
+        env::remove_var(INTERVAL_ENV_VAR);
+        env::remove_var(NO_JITTER_ENV_VAR);
+        result
+    }
+
+    #[test]
+    fn test_from_env() {
+        // Unset: jittered
+        match interval_with(None, None) {
+            Some(Interval::Jittered) => (),
+            other => panic!("unexpected interval: {:?}", other),
+        }
+
+        // Valid values
+        match interval_with(Some(" 250\n"), None) {
+            Some(Interval::Fixed(duration)) => assert_eq!(duration, Duration::from_millis(250)),
+            other => panic!("unexpected interval: {:?}", other),
+        }
+
+        match interval_with(None, Some("1")) {
+            Some(Interval::Fixed(duration)) => {
+                assert_eq!(duration, Duration::from_secs(MIN_JITTER_SECS))
+            }
+            other => panic!("unexpected interval: {:?}", other),
+        }
+
+        match interval_with(None, Some("0")) {
+            Some(Interval::Jittered) => (),
+            other => panic!("unexpected interval: {:?}", other),
+        }
+
+        // A fixed interval takes precedence over disabling jitter
+        match interval_with(Some("5"), Some("1")) {
+            Some(Interval::Fixed(duration)) => assert_eq!(duration, Duration::from_millis(5)),
+            other => panic!("unexpected interval: {:?}", other),
+        }
+
+        // Invalid values
+        assert!(interval_with(Some("soon"), None).is_none());
+        assert!(interval_with(Some("-1"), None).is_none());
+        assert!(interval_with(None, Some("yes")).is_none());
+    }
+}
@@ -3,57 +3,126 @@
 #![crate_name = "placer_source_http"]
 #![deny(missing_docs, unsafe_code, unused_import_braces, unused_qualifications)]
 
-use bytes::Bytes;
-use failure::{bail, Error};
-use rand::Rng;
-use reqwest::header::{HeaderMap, ETAG};
-use reqwest::Client as HttpClient;
-use reqwest::{Response, StatusCode};
+mod client;
+mod interval;
+mod state;
+
+use bytes::{Bytes, BytesMut};
+use failure::{bail, format_err, Error};
+use placer_pack::MAX_PACK_SIZE;
+use reqwest::header::{
+    HeaderMap, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+};
+use reqwest::{Response, StatusCode, Url};
 use sha2::{Digest, Sha256};
 use std::io::Write;
-use std::time::Duration;
-use std::{io, process, thread};
+use std::sync::{Arc, Mutex};
+use std::{io, process};
+
+use crate::client::Client;
+use crate::interval::Interval;
+use crate::state::{Entry, StateFile};
+
+/// Number of bytes at the start of a pack to fetch with an HTTP Range request
+/// when checking whether it has changed. This covers the magic string, UUID,
+/// and date, which are unique to every pack.
+const PACK_HEADER_SIZE: usize = 128;
 
 #[tokio::main]
 async fn main() {
     let version = env!("CARGO_PKG_VERSION");
 
-    // Send source worker greeting
-    println!("OK placer-source-http {} started", version);
+    let client = Client::from_env().unwrap_or_else(|e| {
+        eprintln!("error configuring HTTP client: {}", e);
+        process::exit(1);
+    });
+
+    let interval = Interval::from_env().unwrap_or_else(|e| {
+        eprintln!("error configuring fetch interval: {}", e);
+        process::exit(1);
+    });
+
+    let state = StateFile::from_env()
+        .unwrap_or_else(|e| {
+            eprintln!("error loading state file: {}", e);
+            process::exit(1);
+        })
+        .map(|state| Arc::new(Mutex::new(state)));
+
+    // Send source worker greeting, advertising that we acknowledge URLs
+    println!("OK placer-source-http {} started +ack", version);
 
     let urls = read_urls_from_stdin();
 
-    let mut resources: Vec<Resource> = urls.iter().map(|url| Resource::new(url)).collect();
+    // Acknowledge the URLs we'll fetch
+    for url in &urls {
+        println!("ACK {}", url);
+    }
+    println!();
 
-    while let Some(resource) = resources.pop() {
-        worker_loop(resource).await;
+    // Fetch every URL concurrently
+    let workers: Vec<_> = urls
+        .iter()
+        .map(|url| {
+            let resource = Resource::new(url, &client, state.clone());
+            tokio::spawn(worker_loop(resource, interval))
+        })
+        .collect();
+
+    for worker in workers {
+        if let Err(e) = worker.await {
+            eprintln!("fetch worker failed: {}", e);
+            process::exit(1);
+        }
     }
 }
 
+/// Line which terminates the list of URLs sent by placer
+const END_OF_URLS: &str = ".";
+
+/// Read the URLs to fetch from STDIN, one per line, until `END_OF_URLS` or
+/// EOF. Blank lines and `#` comments are skipped, as are invalid URLs (which
+/// are reported on STDERR, and never acknowledged).
 fn read_urls_from_stdin() -> Vec<String> {
     let mut urls = vec![];
 
     loop {
         let mut line = String::new();
 
-        io::stdin().read_line(&mut line).unwrap_or_else(|e| {
+        let len = io::stdin().read_line(&mut line).unwrap_or_else(|e| {
             eprintln!("error reading URLs to fetch from STDIN: {}", e);
             process::exit(1);
         });
 
-        // Remove trailing newline
-        let len = line.trim_end().len();
-        line.truncate(len);
+        // Remove trailing newline (including CRLF) and surrounding whitespace
+        let line = line.trim();
 
-        if line.is_empty() {
+        if len == 0 || line == END_OF_URLS {
             return urls;
-        } else {
-            urls.push(line);
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match parse_url(line) {
+            Ok(()) => urls.push(line.to_owned()),
+            Err(e) => eprintln!("skipping invalid URL: {} ({})", line, e),
         }
     }
 }
 
-async fn worker_loop(mut resource: Resource) {
+/// Ensure a URL is a valid HTTP(S) URL
+fn parse_url(url: &str) -> Result<(), Error> {
+    let parsed = Url::parse(url)?;
+
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => bail!("unsupported scheme: {}", scheme),
+    }
+}
+
+async fn worker_loop(mut resource: Resource, interval: Interval) {
     loop {
         match resource.fetch().await {
             Ok(Some(body)) => {
@@ -70,8 +139,7 @@ async fn worker_loop(mut resource: Resource) {
             Err(e) => eprintln!("error fetching URL: {} {}", resource.url, e),
         }
 
-        let jitter = rand::thread_rng().gen_range(1, 15);
-        thread::sleep(Duration::from_millis(jitter * 1000));
+        tokio::time::sleep(interval.next()).await;
     }
 }
 
@@ -80,57 +148,169 @@ struct ResourceHash([u8; 32]);
 
 impl ResourceHash {
     pub fn digest(bytes: &[u8]) -> Self {
-        let mut hasher = Sha256::default();
-        hasher.input(bytes);
+        let mut hasher = ResourceHasher::default();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+}
+
+/// Computes a `ResourceHash` incrementally (e.g. as a body is downloaded)
+#[derive(Default)]
+struct ResourceHasher(Sha256);
+
+impl ResourceHasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0.input(bytes);
+    }
+
+    pub fn finish(self) -> ResourceHash {
         let mut hash = [0u8; 32];
-        hash.copy_from_slice(hasher.result().as_slice());
+        hash.copy_from_slice(self.0.result().as_slice());
         ResourceHash(hash)
     }
 }
 
 struct Resource {
+    pub client: Client,
     pub etag: Option<String>,
+    pub last_modified: Option<String>,
     pub url: String,
     pub sha256: Option<ResourceHash>,
+    pub header: Option<Bytes>,
+    pub state: Option<Arc<Mutex<StateFile>>>,
+    /// Pack cached before a restart which hasn't been sent to placer yet
+    pub cached: Option<Bytes>,
+    /// Has the server sent a whole pack in response to a Range request?
+    pub ranges_unsupported: bool,
 }
 
 impl Resource {
-    fn new(url: &str) -> Self {
+    fn new(url: &str, client: &Client, state: Option<Arc<Mutex<StateFile>>>) -> Self {
+        // Resume from the state saved before a restart (if any), but only if
+        // the cached pack is intact, since placer needs the first pack for
+        // every URL even if it's unchanged
+        let saved = state.as_ref().and_then(|state| {
+            let state = state.lock().unwrap();
+            let entry = state.get(url)?.clone();
+            let pack = state.cached_pack(&entry)?;
+
+            if ResourceHash::digest(&pack) == ResourceHash(entry.sha256) {
+                Some((entry, Bytes::from(pack)))
+            } else {
+                None
+            }
+        });
+
+        let (etag, sha256, cached) = match saved {
+            Some((entry, pack)) => (entry.etag, Some(ResourceHash(entry.sha256)), Some(pack)),
+            None => (None, None, None),
+        };
+
         Self {
-            etag: None,
+            client: client.clone(),
+            etag,
+            last_modified: None,
             url: url.to_owned(),
-            sha256: None,
+            sha256,
+            header: None,
+            state,
+            cached,
+            ranges_unsupported: false,
         }
     }
 
     async fn fetch(&mut self) -> Result<Option<Bytes>, Error> {
-        let mut headers = HeaderMap::new();
-        if let Some(ref etag) = self.etag {
-            headers.insert("Cache-Control", "max-age=0".parse()?);
-            headers.insert("If-None-Match", etag.parse()?);
-        }
+        // If we've already seen a pack for this resource, fetch only its header
+        // and skip downloading the full body if the header is unchanged
+        if self.header.is_some() && !self.ranges_unsupported {
+            let mut headers = self.conditional_headers()?;
+            headers.insert(RANGE, format!("bytes=0-{}", PACK_HEADER_SIZE - 1).parse()?);
 
-        let response = HttpClient::new()
-            .get(&self.url)
-            .headers(headers)
-            .send()
-            .await?;
+            let response = self.client.get(&self.url, headers).await?;
 
-        match response.status() {
-            StatusCode::NOT_MODIFIED => Ok(None),
-            StatusCode::OK => {
-                self.handle_etag(&response);
-                let body = response.bytes().await?;
-                let hash = ResourceHash::digest(&body);
-                if let Some(ref h) = self.sha256 {
-                    if h == &hash {
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT => {
+                    let (header, _) = read_body(response).await?;
+                    if self.header.as_ref() == Some(&header) {
                         return Ok(None);
                     }
                 }
-                self.sha256 = Some(hash);
-                Ok(Some(body))
+                StatusCode::NOT_MODIFIED => return Ok(None),
+                // Server doesn't support Range requests and sent the whole pack,
+                // so only make plain conditional requests from now on
+                StatusCode::OK => {
+                    eprintln!(
+                        "server ignored Range request, disabling for URL: {}",
+                        self.url
+                    );
+                    self.ranges_unsupported = true;
+                    return self.handle_body(response).await;
+                }
+                status => return Err(status_error(status)),
+            }
+        }
+
+        let headers = self.conditional_headers()?;
+        let response = self.client.get(&self.url, headers).await?;
+
+        match response.status() {
+            StatusCode::NOT_MODIFIED => Ok(self.replay_cached()),
+            StatusCode::OK => self.handle_body(response).await,
+            status => Err(status_error(status)),
+        }
+    }
+
+    async fn handle_body(&mut self, response: Response) -> Result<Option<Bytes>, Error> {
+        self.handle_etag(&response);
+        self.handle_last_modified(&response);
+        let (body, hash) = read_body(response).await?;
+        self.header = Some(body.slice(..body.len().min(PACK_HEADER_SIZE)));
+        if let Some(ref h) = self.sha256 {
+            if h == &hash {
+                // Send the pack if it's unchanged since before a restart
+                return Ok(self.cached.take().map(|_| body));
+            }
+        }
+        self.save_state(&hash, &body);
+        self.sha256 = Some(hash);
+        self.cached = None;
+        Ok(Some(body))
+    }
+
+    /// Headers for a conditional request (if we've seen the resource before)
+    fn conditional_headers(&self) -> Result<HeaderMap, Error> {
+        // Prefer ETags for conditional requests, falling back to Last-Modified
+        let mut headers = HeaderMap::new();
+        if let Some(ref etag) = self.etag {
+            headers.insert(CACHE_CONTROL, "max-age=0".parse()?);
+            headers.insert(IF_NONE_MATCH, etag.parse()?);
+        } else if let Some(ref last_modified) = self.last_modified {
+            headers.insert(CACHE_CONTROL, "max-age=0".parse()?);
+            headers.insert(IF_MODIFIED_SINCE, last_modified.parse()?);
+        }
+        Ok(headers)
+    }
+
+    /// Send the pack cached before a restart if the server reports it's
+    /// unchanged and it hasn't been sent yet
+    fn replay_cached(&mut self) -> Option<Bytes> {
+        let body = self.cached.take()?;
+        self.header = Some(body.slice(..body.len().min(PACK_HEADER_SIZE)));
+        Some(body)
+    }
+
+    /// Persist the state of this resource (if a state file is configured)
+    fn save_state(&self, hash: &ResourceHash, body: &[u8]) {
+        if let Some(ref state) = self.state {
+            let entry = Entry {
+                url: self.url.clone(),
+                sha256: hash.0,
+                etag: self.etag.clone(),
+            };
+
+            if let Err(e) = state.lock().unwrap().update(entry, body) {
+                eprintln!("error saving state: {}", e);
             }
-            e => bail!("Unexpected status code: {}", e),
         }
     }
 
@@ -139,4 +319,54 @@ impl Resource {
             self.etag = Some(etag.to_str().unwrap().to_owned());
         }
     }
+
+    fn handle_last_modified(&mut self, response: &Response) {
+        if let Some(last_modified) = response.headers().get(LAST_MODIFIED) {
+            self.last_modified = last_modified.to_str().ok().map(str::to_owned);
+        }
+    }
+}
+
+/// Describe an unexpected HTTP status, calling out authentication failures
+/// so they aren't mistaken for transient errors
+fn status_error(status: StatusCode) -> Error {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => format_err!(
+            "Authentication failed ({}): check {} or {}",
+            status,
+            client::BEARER_TOKEN_ENV_VAR,
+            client::BASIC_AUTH_ENV_VAR
+        ),
+        _ => format_err!("Unexpected status code: {}", status),
+    }
+}
+
+/// Read the response body, aborting the download if it exceeds `MAX_PACK_SIZE`.
+/// The body is hashed as it's downloaded, rather than in a second pass.
+async fn read_body(mut response: Response) -> Result<(Bytes, ResourceHash), Error> {
+    let content_length = response.content_length();
+
+    if let Some(length) = content_length {
+        if length > MAX_PACK_SIZE as u64 {
+            bail!("Response too large ({} bytes)", length);
+        }
+    }
+
+    // Avoid reallocating as the body arrives when its length is known
+    let mut body = BytesMut::with_capacity(content_length.unwrap_or(0) as usize);
+    let mut hasher = ResourceHasher::default();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_PACK_SIZE {
+            bail!(
+                "Response exceeded maximum pack size ({} bytes)",
+                MAX_PACK_SIZE
+            );
+        }
+
+        hasher.update(&chunk);
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok((body.freeze(), hasher.finish()))
 }
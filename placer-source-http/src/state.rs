@@ -0,0 +1,487 @@
+//! State about fetched URLs (ETags and SHA-256 digests) persisted across
+//! restarts, so restarting the source doesn't re-download every unchanged
+//! pack.
+//!
+//! The last pack fetched from each URL is cached in a `<state file>.packs`
+//! directory alongside the state file, so it can be replayed to placer after
+//! a restart when the server reports it's unchanged (placer needs the first
+//! pack for every URL after it starts, e.g. as the base for deltas).
+//!
+//! Each line of the state file contains a URL, the hex-encoded SHA-256
+//! digest of the last pack fetched from it, and its ETag (if any), with the
+//! most recently updated URL last:
+//!
+//! ```text
+//! https://example.com/my.pack 5e88...2a1f "33a64df5"
+//! ```
+
+use failure::{bail, format_err, Error};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::client::read_env_var;
+
+/// Environment variable containing the path to the state file (if unset,
+/// state is only kept in memory)
+pub const STATE_FILE_ENV_VAR: &str = "PLACER_HTTP_STATE_FILE";
+
+/// Maximum number of URLs to remember: the least recently updated URLs are
+/// forgotten first
+pub const MAX_STATE_ENTRIES: usize = 1024;
+
+/// Permissions the state file is created with (and must have when loaded)
+const STATE_FILE_MODE: u32 = 0o600;
+
+/// Permissions the pack cache directory is created with (and must have when
+/// loaded)
+const PACK_CACHE_MODE: u32 = 0o700;
+
+/// Last known state of a URL
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Entry {
+    /// URL the pack was fetched from
+    pub url: String,
+
+    /// SHA-256 digest of the pack
+    pub sha256: [u8; 32],
+
+    /// ETag of the pack (if the server sent one)
+    pub etag: Option<String>,
+}
+
+/// State file containing the last known state of fetched URLs
+pub struct StateFile {
+    /// Path to the state file
+    path: PathBuf,
+
+    /// Directory containing the last pack fetched from each URL, named by
+    /// their hex-encoded SHA-256 digests
+    pack_cache: PathBuf,
+
+    /// Entries, from least to most recently updated
+    entries: VecDeque<Entry>,
+}
+
+impl StateFile {
+    /// Load the state file configured in the environment (if any)
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        match read_env_var(STATE_FILE_ENV_VAR)? {
+            Some(path) => Ok(Some(Self::load(Path::new(path.trim()))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Load the state file at the given path, starting empty if it doesn't
+    /// exist. Refuses state files readable or writable by anyone else.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let entries = match fs::metadata(path) {
+            Ok(metadata) => {
+                if metadata.permissions().mode() & 0o777 != STATE_FILE_MODE {
+                    bail!(
+                        "bad file permissions for {} (must be chmod {:o})",
+                        path.display(),
+                        STATE_FILE_MODE
+                    );
+                }
+
+                let data = fs::read_to_string(path)
+                    .map_err(|e| format_err!("couldn't read {}: {}", path.display(), e))?;
+
+                parse(&data).map_err(|e| format_err!("{}: {}", path.display(), e))?
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => bail!("couldn't stat {}: {}", path.display(), e),
+        };
+
+        let mut pack_cache = path.to_owned().into_os_string();
+        pack_cache.push(".packs");
+        let pack_cache = PathBuf::from(pack_cache);
+
+        match fs::metadata(&pack_cache) {
+            Ok(metadata) => {
+                if !metadata.is_dir() || metadata.permissions().mode() & 0o777 != PACK_CACHE_MODE {
+                    bail!(
+                        "bad file permissions for {} (must be a directory with chmod {:o})",
+                        pack_cache.display(),
+                        PACK_CACHE_MODE
+                    );
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => fs::DirBuilder::new()
+                .mode(PACK_CACHE_MODE)
+                .create(&pack_cache)
+                .map_err(|e| format_err!("couldn't create {}: {}", pack_cache.display(), e))?,
+            Err(e) => bail!("couldn't stat {}: {}", pack_cache.display(), e),
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            pack_cache,
+            entries,
+        })
+    }
+
+    /// Get the last known state of the given URL
+    pub fn get(&self, url: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.url == url)
+    }
+
+    /// Read the cached copy of the last pack fetched from the given entry's
+    /// URL (if any). The caller must check it matches the entry's digest.
+    pub fn cached_pack(&self, entry: &Entry) -> Option<Vec<u8>> {
+        fs::read(self.pack_cache.join(encode_digest(&entry.sha256))).ok()
+    }
+
+    /// Update the state of a URL and cache its pack, forgetting the least
+    /// recently updated URL if there are too many, and save the state file
+    pub fn update(&mut self, entry: Entry, pack: &[u8]) -> Result<(), Error> {
+        if entry.url.contains(char::is_whitespace)
+            || entry
+                .etag
+                .as_ref()
+                .map_or(false, |etag| etag.contains(char::is_whitespace))
+        {
+            bail!("can't save state for {:?}: contains whitespace", entry.url);
+        }
+
+        let pack_path = self.pack_cache.join(encode_digest(&entry.sha256));
+        write_atomically(&pack_path, pack)?;
+
+        self.entries.retain(|existing| existing.url != entry.url);
+        self.entries.push_back(entry);
+
+        while self.entries.len() > MAX_STATE_ENTRIES {
+            self.entries.pop_front();
+        }
+
+        self.save()?;
+        self.prune_pack_cache();
+        Ok(())
+    }
+
+    /// Remove cached packs which are no longer the last pack fetched from
+    /// any URL. Errors are ignored, as stale packs are removed next time.
+    fn prune_pack_cache(&self) {
+        let cached = match fs::read_dir(&self.pack_cache) {
+            Ok(cached) => cached,
+            Err(_) => return,
+        };
+
+        for file in cached.filter_map(Result::ok) {
+            let name = file.file_name();
+            let referenced = self
+                .entries
+                .iter()
+                .any(|entry| name.to_str() == Some(encode_digest(&entry.sha256).as_str()));
+
+            if !referenced {
+                let _ = fs::remove_file(file.path());
+            }
+        }
+    }
+
+    /// Write the state file atomically (i.e. to a temporary file which is
+    /// then renamed over it)
+    fn save(&self) -> Result<(), Error> {
+        write_atomically(&self.path, serialize(&self.entries).as_bytes())
+    }
+}
+
+/// Write a file atomically (i.e. to a temporary file which is then renamed
+/// over it), readable only by the current user. The temporary file is always
+/// newly created, so it's never written through a symlink or with whatever
+/// permissions a leftover file had.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp_path = path.to_owned().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    // Remove a temporary file left behind by a crash
+    match fs::remove_file(&tmp_path) {
+        Ok(()) => (),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => bail!("couldn't remove {}: {}", tmp_path.display(), e),
+    }
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(STATE_FILE_MODE)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        bail!("couldn't write {}: {}", path.display(), e);
+    }
+
+    Ok(())
+}
+
+/// Parse the contents of a state file
+fn parse(data: &str) -> Result<VecDeque<Entry>, Error> {
+    let mut entries = VecDeque::new();
+
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+
+        let (url, digest, etag) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(url), Some(digest), etag) if fields.next().is_none() => (url, digest, etag),
+            _ => bail!("malformed entry on line {}", i + 1),
+        };
+
+        let sha256 = decode_digest(digest)
+            .ok_or_else(|| format_err!("bad SHA-256 digest on line {}", i + 1))?;
+
+        entries.push_back(Entry {
+            url: url.to_owned(),
+            sha256,
+            etag: etag.map(str::to_owned),
+        });
+    }
+
+    while entries.len() > MAX_STATE_ENTRIES {
+        entries.pop_front();
+    }
+
+    Ok(entries)
+}
+
+/// Serialize entries in the state file format
+fn serialize(entries: &VecDeque<Entry>) -> String {
+    let mut output = String::new();
+
+    for entry in entries {
+        output.push_str(&entry.url);
+        output.push(' ');
+        output.push_str(&encode_digest(&entry.sha256));
+
+        if let Some(ref etag) = entry.etag {
+            output.push(' ');
+            output.push_str(etag);
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Hex-encode a SHA-256 digest
+fn encode_digest(digest: &[u8; 32]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode a hex-encoded SHA-256 digest
+fn decode_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut digest = [0u8; 32];
+
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_digest, parse, serialize, write_atomically, Entry, StateFile};
+    use super::{MAX_STATE_ENTRIES, STATE_FILE_MODE};
+    use sha2::{Digest, Sha256};
+    use std::collections::VecDeque;
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+
+    /// Create an empty directory for a test's state file
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("placer-http-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Entry for the given URL and pack
+    fn example_entry(url: &str, pack: &[u8], etag: Option<&str>) -> Entry {
+        let mut sha256 = [0u8; 32];
+        sha256.copy_from_slice(&Sha256::digest(pack));
+
+        Entry {
+            url: url.to_owned(),
+            sha256,
+            etag: etag.map(str::to_owned),
+        }
+    }
+
+    /// Names of the packs in a state file's pack cache
+    fn cached_packs(state: &StateFile) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&state.pack_cache)
+            .unwrap()
+            .map(|file| file.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_parse_serialize() {
+        let entries: VecDeque<_> = vec![
+            example_entry("https://example.com/a.pack", b"a", Some("\"33a64df5\"")),
+            example_entry("https://example.com/b.pack", b"b", None),
+        ]
+        .into_iter()
+        .collect();
+
+        let serialized = serialize(&entries);
+        assert_eq!(
+            serialized,
+            "https://example.com/a.pack \
+             ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb \"33a64df5\"\n\
+             https://example.com/b.pack \
+             3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d\n"
+        );
+        assert_eq!(parse(&serialized).unwrap(), entries);
+
+        assert!(parse("https://example.com/a.pack").is_err());
+        assert!(parse("https://example.com/a.pack abcd").is_err());
+        assert!(parse(&format!(
+            "{} etag extra",
+            serialized.lines().next().unwrap()
+        ))
+        .is_err());
+        assert!(decode_digest(&"zz".repeat(32)).is_none());
+    }
+
+    #[test]
+    fn test_parse_max_entries() {
+        let entries: VecDeque<_> = (0..=MAX_STATE_ENTRIES)
+            .map(|i| example_entry(&format!("https://example.com/{}.pack", i), b"", None))
+            .collect();
+
+        // The least recently updated entry is dropped
+        let parsed = parse(&serialize(&entries)).unwrap();
+        assert_eq!(parsed.len(), MAX_STATE_ENTRIES);
+        assert_eq!(parsed.front(), entries.get(1));
+        assert_eq!(parsed.back(), entries.back());
+    }
+
+    #[test]
+    fn test_update_eviction() {
+        let dir = test_dir("eviction");
+        let mut state = StateFile::load(&dir.join("state")).unwrap();
+
+        for i in 0..MAX_STATE_ENTRIES {
+            state.entries.push_back(example_entry(
+                &format!("https://example.com/{}.pack", i),
+                b"",
+                None,
+            ));
+        }
+
+        // Updating a URL makes it the most recently updated
+        let first = example_entry("https://example.com/0.pack", b"new", None);
+        state.update(first.clone(), b"new").unwrap();
+        assert_eq!(state.entries.back(), Some(&first));
+        assert_eq!(state.entries.len(), MAX_STATE_ENTRIES);
+
+        // Adding a URL forgets the least recently updated one
+        let added = example_entry("https://example.com/added.pack", b"added", None);
+        state.update(added.clone(), b"added").unwrap();
+        assert_eq!(state.entries.len(), MAX_STATE_ENTRIES);
+        assert!(state.get("https://example.com/1.pack").is_none());
+        assert_eq!(state.get(&added.url), Some(&added));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pack_cache() {
+        let dir = test_dir("pack-cache");
+        let mut state = StateFile::load(&dir.join("state")).unwrap();
+        let url = "https://example.com/a.pack";
+
+        let v1 = example_entry(url, b"v1", Some("\"1\""));
+        state.update(v1.clone(), b"v1").unwrap();
+        assert_eq!(state.cached_pack(&v1).unwrap(), b"v1");
+
+        let other = example_entry("https://example.com/b.pack", b"b", None);
+        state.update(other.clone(), b"b").unwrap();
+
+        // Packs which are no longer the latest for any URL are pruned
+        let v2 = example_entry(url, b"v2", Some("\"2\""));
+        state.update(v2.clone(), b"v2").unwrap();
+        assert_eq!(state.cached_pack(&v2).unwrap(), b"v2");
+        assert!(state.cached_pack(&v1).is_none());
+        assert_eq!(cached_packs(&state).len(), 2);
+
+        // Stray files are pruned too
+        fs::write(state.pack_cache.join("stray"), b"stray").unwrap();
+        state.prune_pack_cache();
+        assert!(!cached_packs(&state).contains(&"stray".to_owned()));
+        assert_eq!(cached_packs(&state).len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restart() {
+        let dir = test_dir("restart");
+        let path = dir.join("state");
+        let url = "https://example.com/a.pack";
+        let entry = example_entry(url, b"pack", Some("\"33a64df5\""));
+
+        StateFile::load(&path)
+            .unwrap()
+            .update(entry.clone(), b"pack")
+            .unwrap();
+
+        // After a restart, an unchanged pack can be replayed from the cache
+        let state = StateFile::load(&path).unwrap();
+        assert_eq!(state.get(url), Some(&entry));
+        assert_eq!(state.cached_pack(&entry).unwrap(), b"pack");
+        assert!(state.get("https://example.com/other.pack").is_none());
+
+        // State files accessible by anyone else are refused
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        assert!(StateFile::load(&path).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = test_dir("write");
+        let path = dir.join("state");
+        let target = dir.join("target");
+
+        // A leftover temporary file (even a symlink) is replaced, not reused
+        symlink(&target, dir.join("state.tmp")).unwrap();
+        write_atomically(&path, b"data").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+        assert!(!target.exists());
+        assert!(!dir.join("state.tmp").exists());
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, STATE_FILE_MODE);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,9 +2,12 @@
 
 mod cache;
 mod file_config;
+mod key_policy;
 mod log;
 mod quarantine;
+mod readiness;
 mod source;
+mod strict;
 
 use std::collections::BTreeMap;
 use std::fs::File;
@@ -14,17 +17,34 @@ use std::path::{Path, PathBuf};
 use crate::error::Error;
 
 pub use self::cache::CacheConfig;
-pub use self::file_config::{FileConfig, HookConfig};
+pub use self::file_config::{FileConfig, FragmentConfig, HookConfig};
+pub use self::key_policy::KeyPolicyConfig;
 pub use self::log::LogConfig;
 pub use self::quarantine::QuarantineConfig;
+pub use self::readiness::ReadinessConfig;
 pub use self::source::SourceConfig;
+pub use self::strict::StrictConfig;
 
 /// Toplevel attributes of a placer configuration file
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// Path to a TOML file containing keyring configuration
-    pub keyrings: PathBuf,
+    pub keyrings: Option<PathBuf>,
+
+    /// Load keyrings from `PLACER_SIGNING_*` and `PLACER_ENCRYPTION_*`
+    /// environment variables instead of a file
+    #[serde(default)]
+    pub keyrings_from_env: bool,
+
+    /// Policy for which keys may be loaded into the keyrings
+    #[serde(default)]
+    pub key_policy: KeyPolicyConfig,
+
+    /// Path to a trust-on-first-use store of signing keys. If set, packs
+    /// signed by keys not in the keyring are accepted, and the first key seen
+    /// for each pack is pinned (like SSH `known_hosts`). Disabled by default.
+    pub trust_on_first_use: Option<PathBuf>,
 
     /// Sources where new/updated packs are fetched from
     pub sources: BTreeMap<String, SourceConfig>,
@@ -40,6 +60,46 @@ pub struct Config {
 
     /// File to be placed
     pub files: BTreeMap<PathBuf, FileConfig>,
+
+    /// Strict mode: report (or exit on) targets which are never satisfied
+    pub strict: Option<StrictConfig>,
+
+    /// Signal readiness (e.g. to systemd) once every target is satisfied
+    pub readiness: Option<ReadinessConfig>,
+
+    /// Seconds to wait after receiving a pack for further packs, placing only
+    /// the newest of each pack received in that window (disabled if unset)
+    pub debounce_window: Option<u64>,
+
+    /// Number of consecutive verification failures of a pack after which its
+    /// failures are only logged periodically (and it's dead-lettered, if
+    /// enabled in the quarantine config). Failures are always logged if unset.
+    pub failure_threshold: Option<u32>,
+
+    /// Place updated files under this directory (mirroring their real paths)
+    /// for review instead of at their real paths. Staged files are moved into
+    /// place with `placer promote`.
+    pub staging_root: Option<PathBuf>,
+
+    /// Number of threads used to place files (and run their hooks) concurrently
+    #[serde(default = "default_placement_threads")]
+    pub placement_threads: usize,
+
+    /// Consider a file unchanged (without re-reading and hashing it) if its
+    /// size and modification time are the same as when placer last placed or
+    /// compared it. Faster for many large files, but misses changes which
+    /// preserve both. Disabled by default.
+    #[serde(default)]
+    pub compare_by_metadata: bool,
+
+    /// Write the pack each target was most recently placed from (and the
+    /// digest of its contents) to this file, for `placer status`
+    pub status_file: Option<PathBuf>,
+}
+
+/// Default number of threads used to place files
+fn default_placement_threads() -> usize {
+    4
 }
 
 impl Config {
@@ -52,6 +112,6 @@ impl Config {
         let mut data = String::new();
         file.read_to_string(&mut data)?;
 
-        toml::from_str(&data).map_err(|e| err!(Config, "couldn't parse {}: {}", filename, e))
+        toml::from_str(&data).map_err(|e| Error::from_toml(path, &e))
     }
 }
@@ -7,9 +7,15 @@ use std::path::PathBuf;
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct FileConfig {
-    /// Pack that this file belongs to
+    /// Pack that this file belongs to (unset for files composed of fragments)
+    #[serde(default)]
     pub pack: String,
 
+    /// Compose this file by concatenating files from one or more packs, in
+    /// the order given, instead of placing a single file from its pack
+    #[serde(default)]
+    pub fragments: Vec<FragmentConfig>,
+
     /// User which owns this file
     pub user: String,
 
@@ -19,6 +25,56 @@ pub struct FileConfig {
     /// Permissions of logfile (in octal)
     pub mode: String,
 
+    /// Create missing parent directories when placing this file
+    #[serde(default)]
+    pub create_parents: bool,
+
+    /// User which owns created parent directories (default "root")
+    pub parent_user: Option<String>,
+
+    /// Group which owns created parent directories (default "root")
+    pub parent_group: Option<String>,
+
+    /// Permissions of created parent directories (in octal, default "755")
+    pub parent_mode: Option<String>,
+
+    /// Allow placing this file in a directory which is writable by users
+    /// other than root or the file's owner (dangerous!)
+    #[serde(default)]
+    pub allow_untrusted_parent: bool,
+
+    /// Allow modes with setuid/setgid/sticky bits or which are world-writable
+    /// (for both the file and its created parent directories)
+    #[serde(default)]
+    pub allow_dangerous_mode: bool,
+
+    /// Allow replacing a FIFO, socket, or device node at this file's path
+    /// with a regular file (otherwise placer refuses to). Directories are
+    /// never replaced.
+    #[serde(default)]
+    pub allow_special_file: bool,
+
+    /// Content-Type this file must be declared with in its pack (if set,
+    /// files with any other Content-Type are refused)
+    pub content_type: Option<String>,
+
+    /// Flush the file and its parent directory to disk when placing it, so
+    /// it survives a crash or power loss (slower)
+    #[serde(default)]
+    pub fsync: bool,
+
+    /// This file is a secret (e.g. a TLS key): require a mode which grants
+    /// no group/other permissions, and zero copies of its contents in memory
+    /// once it's been placed
+    #[serde(default)]
+    pub secret: bool,
+
+    /// Hooks which must all succeed for a file to be placed on this host
+    /// (run before anything is written; a failing precondition skips the
+    /// file, and isn't an error)
+    #[serde(rename = "precondition-hook")]
+    pub precondition_hooks: Option<BTreeMap<PathBuf, HookConfig>>,
+
     /// Hooks to run before a file is placed
     #[serde(rename = "before-hook")]
     pub before_hooks: Option<BTreeMap<PathBuf, HookConfig>>,
@@ -28,16 +84,38 @@ pub struct FileConfig {
     pub after_hooks: Option<BTreeMap<PathBuf, HookConfig>>,
 }
 
+/// Configuration for a fragment of a composed file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FragmentConfig {
+    /// Pack containing the fragment
+    pub pack: String,
+
+    /// Path of the fragment within the pack
+    pub file: PathBuf,
+}
+
 /// Configuration for an individual hook
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct HookConfig {
-    /// User the hook should be run as
-    pub user: String,
+    /// User the hook should be run as (defaults to the file's owner)
+    pub user: Option<String>,
 
-    /// Group the hook should be run as
-    pub group: String,
+    /// Group the hook should be run as (defaults to the file's group)
+    pub group: Option<String>,
 
     /// Arguments to pass to the program
     pub args: Option<Vec<String>>,
+
+    /// Working directory to run the hook in (must be a canonical path)
+    pub cwd: Option<PathBuf>,
+
+    /// Run the hook with an empty environment, instead of inheriting placer's
+    #[serde(default)]
+    pub clear_env: bool,
+
+    /// Names of environment variables to pass through when `clear_env` is set
+    #[serde(default)]
+    pub pass_env: Vec<String>,
 }
@@ -0,0 +1,12 @@
+//! Key policy configuration
+
+/// Policy for which keys may be loaded into the keyrings
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPolicyConfig {
+    /// KeyURI prefixes (i.e. key types/algorithms) which are allowed in the
+    /// keyrings, e.g. `secret.key:aes256siv+hks256`. Any key whose prefix
+    /// isn't listed is rejected. If empty, all supported key types are allowed.
+    #[serde(default)]
+    pub allowed_prefixes: Vec<String>,
+}
@@ -17,6 +17,15 @@ pub struct QuarantineConfig {
 
     /// File permissions of files in the cache directory (in octal)
     pub mode: String,
+
+    /// Allow a mode with setuid/setgid/sticky bits or which is world-writable
+    #[serde(default)]
+    pub allow_dangerous_mode: bool,
+
+    /// Write packs which reach the failure threshold to the quarantine
+    /// directory as "dead letters" for offline analysis
+    #[serde(default)]
+    pub dead_letters: bool,
 }
 
 impl Default for QuarantineConfig {
@@ -26,6 +35,8 @@ impl Default for QuarantineConfig {
             user: "nobody".to_owned(),
             group: "nobody".to_owned(),
             mode: "0000".to_owned(),
+            allow_dangerous_mode: false,
+            dead_letters: false,
         }
     }
 }
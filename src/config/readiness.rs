@@ -0,0 +1,23 @@
+//! Readiness configuration: signal when every target has been satisfied
+
+use std::path::PathBuf;
+
+/// Readiness config. Readiness is signaled once every configured target has
+/// received its file at least once, or the timeout elapses (whichever is
+/// first). Without a readiness config, placer is ready as soon as it starts.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReadinessConfig {
+    /// Seconds to wait after startup for every target to be satisfied before
+    /// signaling readiness anyway
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+
+    /// Create this file when ready (it's removed on startup)
+    pub file: Option<PathBuf>,
+}
+
+/// Default readiness timeout (in seconds)
+fn default_timeout() -> u64 {
+    300
+}
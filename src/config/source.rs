@@ -14,4 +14,37 @@ pub struct SourceConfig {
 
     /// Names of packs to fetch and a location identifier to pass to the source (e.g. a URI)
     pub packs: BTreeMap<String, String>,
+
+    /// Expect packs from this source to contain files with no config (don't warn about them)
+    #[serde(default)]
+    pub allow_extra_files: bool,
+
+    /// Maximum number of packs which may be fetched from this source
+    #[serde(default = "default_max_resources")]
+    pub max_resources: usize,
+
+    /// Only accept packs from this source tagged with one of these channels
+    /// (i.e. environments, e.g. "production"). Any pack is accepted if empty.
+    #[serde(default)]
+    pub channels: Vec<String>,
+
+    /// Maximum number of packs this source may deliver per minute. Packs
+    /// delivered faster are held back until they're within the limit.
+    pub max_packs_per_minute: Option<u32>,
+
+    /// Maximum number of packs from this source waiting to be verified.
+    /// When the queue is full, the oldest queued pack is dropped (with a
+    /// warning) to make room.
+    #[serde(default = "default_max_queued_packs")]
+    pub max_queued_packs: usize,
+}
+
+/// Default maximum number of packs fetched from a single source
+fn default_max_resources() -> usize {
+    256
+}
+
+/// Default maximum number of packs from a single source waiting to be verified
+fn default_max_queued_packs() -> usize {
+    16
 }
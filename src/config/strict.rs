@@ -0,0 +1,21 @@
+//! Strict mode configuration: require every target to be satisfied
+
+/// Strict mode config. Enabling strict mode also rejects empty packs.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StrictConfig {
+    /// Seconds to wait after startup for every configured target's file to
+    /// arrive in its pack before reporting it as unsatisfied
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+
+    /// Exit with a non-zero status (instead of only logging an error) if any
+    /// target is unsatisfied after the grace period
+    #[serde(default)]
+    pub fatal: bool,
+}
+
+/// Default strict mode grace period (in seconds)
+fn default_grace_period() -> u64 {
+    300
+}
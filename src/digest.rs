@@ -2,6 +2,7 @@
 
 use sha2::Digest as Sha2Digest;
 use sha2::Sha256;
+use std::fmt;
 
 /// SHA-256 digests
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -16,6 +17,16 @@ impl Digest {
     }
 }
 
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
 impl AsRef<[u8]> for Digest {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -1,6 +1,7 @@
 //! Error types
 
 use std::io;
+use std::path::Path;
 
 /// placer's error type
 #[derive(Clone, Eq, PartialEq, Debug, Fail)]
@@ -12,6 +13,22 @@ pub enum Error {
         description: String,
     },
 
+    /// Syntax or type error in a configuration file, with its location
+    #[fail(display = "{}", description)]
+    ConfigParse {
+        /// Description of the error (including its location)
+        description: String,
+
+        /// Path to the configuration file
+        path: String,
+
+        /// Line where the error occurred (1-based), if known
+        line: Option<usize>,
+
+        /// Column where the error occurred (1-based), if known
+        column: Option<usize>,
+    },
+
     /// Error executing a before/after hook
     #[fail(display = "{}", description)]
     Hook {
@@ -41,8 +58,80 @@ pub enum Error {
     },
 }
 
+impl Error {
+    /// Create an error for a TOML file which couldn't be parsed, preserving
+    /// the line/column where parsing failed
+    pub fn from_toml(path: &Path, error: &toml::de::Error) -> Self {
+        let path = path.to_string_lossy().to_string();
+        let mut message = error.to_string();
+
+        let (line, column) = match error.line_col() {
+            Some((line, column)) => {
+                // The TOML error message ends with its location, which we
+                // report in `path:line:column` form instead
+                let suffix = format!(" at line {} column {}", line + 1, column + 1);
+
+                if message.ends_with(&suffix) {
+                    let len = message.len() - suffix.len();
+                    message.truncate(len);
+                }
+
+                (Some(line + 1), Some(column + 1))
+            }
+            None => (None, None),
+        };
+
+        let description = match (line, column) {
+            (Some(line), Some(column)) => {
+                format!("couldn't parse {}:{}:{}: {}", path, line, column, message)
+            }
+            _ => format!("couldn't parse {}: {}", path, message),
+        };
+
+        Error::ConfigParse {
+            description,
+            path,
+            line,
+            column,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(other: io::Error) -> Self {
         err!(Io, "{}", other)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use std::path::Path;
+
+    #[test]
+    fn test_from_toml() {
+        #[derive(Deserialize, Debug)]
+        struct Example {
+            #[allow(dead_code)]
+            mode: String,
+        }
+
+        let toml_error = toml::from_str::<Example>("\nmode = 600\n").unwrap_err();
+
+        match Error::from_toml(Path::new("placer.toml"), &toml_error) {
+            Error::ConfigParse {
+                description,
+                line,
+                column,
+                ..
+            } => {
+                assert_eq!(line, Some(2));
+                assert!(column.is_some());
+                assert!(description.starts_with("couldn't parse placer.toml:2:"));
+                assert!(description.contains("`mode`"));
+                assert!(!description.contains("at line"));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+}
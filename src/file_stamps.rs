@@ -0,0 +1,106 @@
+//! Cheap "probably unchanged" checks for placed files, by their size and
+//! modification time instead of re-reading and hashing them (opt-in with
+//! `compare_by_metadata`, since a change which preserves both goes unnoticed)
+
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::digest::Digest;
+
+/// Size and modification time of a file, along with the digest of its
+/// contents when they were recorded
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct FileStamp {
+    /// Size of the file in bytes
+    len: u64,
+
+    /// Modification time of the file
+    modified: SystemTime,
+
+    /// SHA-256 digest of the file's contents
+    digest: Digest,
+}
+
+impl FileStamp {
+    /// Create a stamp from a regular file's metadata, if it has an mtime
+    fn new(metadata: &Metadata, digest: Digest) -> Option<Self> {
+        if !metadata.file_type().is_file() {
+            return None;
+        }
+
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok()?,
+            digest,
+        })
+    }
+}
+
+/// Stamps of files placer has placed (or found to be up-to-date), by path
+#[derive(Debug, Default)]
+pub struct FileStamps(Mutex<BTreeMap<PathBuf, FileStamp>>);
+
+impl FileStamps {
+    /// Is the file at the given path probably identical to contents with the
+    /// given digest? This is the case if it was recorded with those contents
+    /// and its size and modification time haven't changed since.
+    pub fn probably_unchanged(&self, path: &Path, digest: &Digest) -> bool {
+        let current = match fs::symlink_metadata(path) {
+            Ok(metadata) => FileStamp::new(&metadata, digest.clone()),
+            Err(_) => None,
+        };
+
+        match current {
+            Some(stamp) => self.0.lock().unwrap().get(path) == Some(&stamp),
+            None => false,
+        }
+    }
+
+    /// Record the size and modification time of the file at the given path
+    /// (from its metadata), whose contents have the given digest
+    pub fn record(&self, path: &Path, metadata: &Metadata, digest: Digest) {
+        let mut stamps = self.0.lock().unwrap();
+
+        match FileStamp::new(metadata, digest) {
+            Some(stamp) => stamps.insert(path.to_owned(), stamp),
+            None => stamps.remove(path),
+        };
+    }
+
+    /// Forget the stamp for the given path (e.g. because it couldn't be read)
+    pub fn forget(&self, path: &Path) {
+        self.0.lock().unwrap().remove(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileStamps;
+    use crate::digest::Digest;
+    use std::fs;
+
+    #[test]
+    fn test_probably_unchanged() {
+        let path = std::env::temp_dir().join(format!("placer-stamps-{}", std::process::id()));
+        fs::write(&path, b"hello").unwrap();
+
+        let stamps = FileStamps::default();
+        let digest = Digest::for_bytes(b"hello");
+        assert!(!stamps.probably_unchanged(&path, &digest));
+
+        stamps.record(&path, &fs::symlink_metadata(&path).unwrap(), digest.clone());
+        assert!(stamps.probably_unchanged(&path, &digest));
+        assert!(!stamps.probably_unchanged(&path, &Digest::for_bytes(b"other")));
+
+        // A change in size is noticed even if the mtime is the same
+        fs::write(&path, b"hello, world").unwrap();
+        assert!(!stamps.probably_unchanged(&path, &digest));
+
+        stamps.forget(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(!stamps.probably_unchanged(&path, &digest));
+    }
+}
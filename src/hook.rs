@@ -1,9 +1,10 @@
 //! Commands which are run either before or after placing a file
 
+use std::env;
 use std::ffi::OsString;
 use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, ExitStatus};
 use users::{gid_t, uid_t, Groups, Users, UsersCache};
 
 use crate::config::HookConfig;
@@ -26,13 +27,24 @@ pub struct Hook {
 
     /// Arguments to pass to the hook
     pub args: Vec<OsString>,
+
+    /// Working directory to run the hook in (inherited from placer if unset)
+    pub cwd: Option<PathBuf>,
+
+    /// Run the hook with an empty environment (except for `pass_env`)
+    pub clear_env: bool,
+
+    /// Environment variables passed through to the hook if `clear_env` is set
+    pub pass_env: Vec<String>,
 }
 
 impl Hook {
-    /// Validate HookConfig and create a hook to be run
+    /// Validate HookConfig and create a hook to be run, as the given owner of
+    /// the file it's run against unless the config names a user/group
     pub fn new(
         non_canonical_path: &Path,
         config: &HookConfig,
+        (owner_uid, owner_gid): (uid_t, gid_t),
         users_cache: &mut UsersCache,
     ) -> Result<Self, Error> {
         let non_canonical_parent = non_canonical_path
@@ -59,13 +71,59 @@ impl Hook {
             canonical_path.to_string_lossy()
         );
 
-        let user = users_cache
-            .get_user_by_name(&config.user)
-            .ok_or_else(|| err!(Config, "invalid user: {}", &config.user))?;
+        let uid = match config.user {
+            Some(ref user) => users_cache
+                .get_user_by_name(user)
+                .ok_or_else(|| err!(Config, "invalid user: {}", user))?
+                .uid(),
+            None => owner_uid,
+        };
+
+        let gid = match config.group {
+            Some(ref group) => users_cache
+                .get_group_by_name(group)
+                .ok_or_else(|| err!(Config, "invalid group: {}", group))?
+                .gid(),
+            None => owner_gid,
+        };
+
+        let cwd = match config.cwd {
+            Some(ref cwd) => {
+                let canonical_cwd = cwd.canonicalize().map_err(|e| {
+                    err!(
+                        Config,
+                        "error canonicalizing hook cwd: {} ({})",
+                        cwd.to_string_lossy(),
+                        e
+                    )
+                })?;
+
+                ensure!(
+                    cwd == &canonical_cwd,
+                    Config,
+                    "non-canonical hook cwd: {} (expected {})",
+                    cwd.to_string_lossy(),
+                    canonical_cwd.to_string_lossy()
+                );
+
+                ensure!(
+                    canonical_cwd.is_dir(),
+                    Config,
+                    "hook cwd is not a directory: {}",
+                    canonical_cwd.to_string_lossy()
+                );
+
+                Some(canonical_cwd)
+            }
+            None => None,
+        };
 
-        let group = users_cache
-            .get_group_by_name(&config.group)
-            .ok_or_else(|| err!(Config, "invalid group: {}", &config.group))?;
+        ensure!(
+            config.clear_env || config.pass_env.is_empty(),
+            Config,
+            "hook {} sets pass_env without clear_env",
+            canonical_path.to_string_lossy()
+        );
 
         let mut args = vec![];
 
@@ -77,30 +135,18 @@ impl Hook {
 
         Ok(Self {
             path: canonical_path,
-            uid: user.uid(),
-            gid: group.gid(),
+            uid,
+            gid,
             args,
+            cwd,
+            clear_env: config.clear_env,
+            pass_env: config.pass_env.clone(),
         })
     }
 
     /// Run the hook, returning an error if the subcommand returns an error
     pub fn run(&self, file_path: &Path) -> Result<(), Error> {
-        let mut subprocess = Command::new(&self.path)
-            .uid(self.uid)
-            .gid(self.gid)
-            .args(self.args.iter().map(|a| {
-                if a == FILENAME_PLACEHOLDER {
-                    file_path.as_os_str()
-                } else {
-                    a.as_os_str()
-                }
-            }))
-            .spawn()
-            .map_err(|e| err!(Hook, "[hook:{}] {}", &self.path.to_string_lossy(), e))?;
-
-        let exit_status = subprocess
-            .wait()
-            .map_err(|e| err!(Hook, "[hook:{}] {}", &self.path.to_string_lossy(), e))?;
+        let exit_status = self.exec(file_path)?;
 
         match exit_status.code() {
             Some(0) => Ok(()),
@@ -118,4 +164,95 @@ impl Hook {
             ),
         }
     }
+
+    /// Run the hook as a precondition, returning whether it exited
+    /// successfully (or an error if it couldn't be run or was killed)
+    pub fn check(&self, file_path: &Path) -> Result<bool, Error> {
+        let exit_status = self.exec(file_path)?;
+
+        match exit_status.code() {
+            Some(code) => Ok(code == 0),
+            None => fail!(
+                Hook,
+                "[hook:{}] killed by signal {}",
+                &self.path.to_string_lossy(),
+                exit_status.signal().unwrap()
+            ),
+        }
+    }
+
+    /// Spawn the hook and wait for it to exit
+    fn exec(&self, file_path: &Path) -> Result<ExitStatus, Error> {
+        let mut command = Command::new(&self.path);
+
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+
+        if self.clear_env {
+            command.env_clear();
+
+            for name in &self.pass_env {
+                if let Some(value) = env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+
+        let mut subprocess = command
+            .uid(self.uid)
+            .gid(self.gid)
+            .args(self.args.iter().map(|a| {
+                if a == FILENAME_PLACEHOLDER {
+                    file_path.as_os_str()
+                } else {
+                    a.as_os_str()
+                }
+            }))
+            .spawn()
+            .map_err(|e| err!(Hook, "[hook:{}] {}", &self.path.to_string_lossy(), e))?;
+
+        subprocess
+            .wait()
+            .map_err(|e| err!(Hook, "[hook:{}] {}", &self.path.to_string_lossy(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hook;
+    use std::path::{Path, PathBuf};
+
+    /// Hook which runs a shell script as the current user
+    fn shell_hook(script: &str) -> Hook {
+        Hook {
+            path: PathBuf::from("/bin/sh"),
+            uid: users::get_current_uid(),
+            gid: users::get_current_gid(),
+            args: vec!["-c".into(), script.into()],
+            cwd: Some(PathBuf::from("/")),
+            clear_env: true,
+            pass_env: vec!["PATH".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_clear_env() {
+        let file_path = Path::new("/dev/null");
+
+        assert!(shell_hook("test -n \"$PATH\" && test -z \"$HOME\"")
+            .run(file_path)
+            .is_ok());
+        assert!(shell_hook("test \"$(pwd)\" = /").run(file_path).is_ok());
+        assert!(shell_hook("exit 1").run(file_path).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let file_path = Path::new("/dev/null");
+
+        assert_eq!(shell_hook("exit 0").check(file_path), Ok(true));
+        assert_eq!(shell_hook("exit 3").check(file_path), Ok(false));
+        assert!(shell_hook("kill -9 $$").check(file_path).is_err());
+    }
 }
@@ -1,19 +1,35 @@
 //! Signing and encryption keyrings
 
-use placer_pack::crypto::{Encryptor, PublicKey};
+use placer_pack::crypto::{Encryptor, PublicKey, SignaturePolicy};
+use placer_pack::keyuri::bech32k;
 use placer_pack::Fingerprints;
+use std::collections::BTreeMap;
 use std::path::Path;
 use uuid::Uuid;
 
 mod config;
 mod encryption;
 mod signing;
+mod trust_store;
 
-use self::config::KeyringConfig;
+use self::config::{KeyringConfig, SignaturePolicyConfig};
 use self::encryption::EncryptionKeyring;
 use self::signing::SigningKeyring;
+pub use self::signing::FALLBACK_KEY_LABEL;
+pub use self::trust_store::TrustStore;
+use crate::config::KeyPolicyConfig;
 use crate::error::Error;
 
+/// Labels (in the keyring config) of the keys a pack was verified with
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct KeyLabels {
+    /// Label of the signing key (`None` if it was trusted on first use)
+    pub signing: Option<String>,
+
+    /// Label of the encryption key
+    pub encryption: Option<String>,
+}
+
 /// Cryptographic keyrings
 pub struct Keyrings {
     /// Encryption keys
@@ -21,29 +37,74 @@ pub struct Keyrings {
 
     /// Signing keys
     pub signing: SigningKeyring,
+
+    /// Signing keys trusted on first use (if enabled)
+    pub trust_store: Option<TrustStore>,
+
+    /// Policy requiring packs to be signed by several keys (if configured)
+    pub signature_policy: Option<SignaturePolicy>,
 }
 
 impl Keyrings {
-    /// Load the keyring configuration from a file
-    pub fn load(path: &Path) -> Result<Self, Error> {
-        let config = KeyringConfig::load(path)?;
+    /// Load the keyring configuration from a file, rejecting any keys which
+    /// aren't allowed by the given policy
+    pub fn load(path: &Path, policy: &KeyPolicyConfig) -> Result<Self, Error> {
+        Self::from_config(&KeyringConfig::load(path)?, policy)
+    }
+
+    /// Load the keyrings from `PLACER_SIGNING_<label>` and
+    /// `PLACER_ENCRYPTION_<label>` environment variables containing KeyURIs,
+    /// rejecting any keys which aren't allowed by the given policy
+    pub fn from_env(policy: &KeyPolicyConfig) -> Result<Self, Error> {
+        Self::from_config(&KeyringConfig::from_env()?, policy)
+    }
+
+    /// Create keyrings from the given configuration
+    fn from_config(config: &KeyringConfig, policy: &KeyPolicyConfig) -> Result<Self, Error> {
+        enforce_policy(policy, &config.encryption)?;
+        enforce_policy(policy, &config.signing)?;
 
         Ok(Self {
             encryption: EncryptionKeyring::new(&config.encryption)?,
             signing: SigningKeyring::new(&config.signing)?,
+            trust_store: None,
+            signature_policy: match config.signature_policy {
+                Some(ref policy) => Some(signature_policy(policy, &config.signing)?),
+                None => None,
+            },
         })
     }
 
-    /// Get the keys for a set of fingerprints
+    /// Accept packs signed by keys which aren't in the signing keyring,
+    /// trusting each pack's signing key the first time it's seen
+    pub fn with_trust_store(mut self, trust_store: TrustStore) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+
+    /// Get the keys for a set of fingerprints.
+    ///
+    /// If the signing key isn't in the keyring, `first_use_key` (which the
+    /// caller must have checked against the trust store) is used instead,
+    /// provided trust-on-first-use is enabled and it matches the fingerprint.
+    /// Otherwise the catch-all key is used (if configured): the pack is only
+    /// accepted if its signature actually verifies with it.
     pub fn get_for_fingerprints(
         &self,
         fingerprints: &Fingerprints,
         uuid: &Uuid,
+        first_use_key: Option<&PublicKey>,
     ) -> Result<(PublicKey, Encryptor), Error> {
-        let verify_key = self
-            .signing
-            .get(&fingerprints.signing_key)
-            .map_err(|e| err!(InvalidKey, "{}", e))?;
+        let verify_key = match self.signing.get(&fingerprints.signing_key) {
+            Ok(key) => key,
+            Err(e) => match self.first_use_key(fingerprints, first_use_key) {
+                Some(key) => key,
+                None => self
+                    .signing
+                    .fallback()
+                    .ok_or_else(|| err!(InvalidKey, "{}", e))?,
+            },
+        };
 
         let encryption_key = self
             .encryption
@@ -52,4 +113,360 @@ impl Keyrings {
 
         Ok((*verify_key, encryption_key))
     }
+
+    /// Get the labels of the keys with the given fingerprints, i.e. those a
+    /// pack was verified and decrypted with. Signing keys which aren't in the
+    /// keyring are resolved the same way as by `get_for_fingerprints`.
+    pub fn key_labels(
+        &self,
+        fingerprints: &Fingerprints,
+        first_use_key: Option<&PublicKey>,
+    ) -> KeyLabels {
+        let signing = match self.signing.label(&fingerprints.signing_key) {
+            Some(label) => Some(label),
+            None if self.first_use_key(fingerprints, first_use_key).is_some() => None,
+            None => self.signing.fallback().map(|_| FALLBACK_KEY_LABEL),
+        };
+
+        KeyLabels {
+            signing: signing.map(str::to_owned),
+            encryption: self
+                .encryption
+                .label(&fingerprints.encryption_key)
+                .map(str::to_owned),
+        }
+    }
+
+    /// Get the key being trusted on first use, if trust-on-first-use is
+    /// enabled and it matches the signing key fingerprint
+    fn first_use_key<'a>(
+        &self,
+        fingerprints: &Fingerprints,
+        first_use_key: Option<&'a PublicKey>,
+    ) -> Option<&'a PublicKey> {
+        first_use_key.filter(|key| {
+            self.trust_store.is_some() && key.matches_fingerprint(&fingerprints.signing_key)
+        })
+    }
+}
+
+/// Build a signature policy from the given signing key labels
+fn signature_policy(
+    config: &SignaturePolicyConfig,
+    signing_keys: &BTreeMap<String, String>,
+) -> Result<SignaturePolicy, Error> {
+    let mut authorized_keys = vec![];
+
+    for label in &config.keys {
+        let keyuri = signing_keys.get(label).ok_or_else(|| {
+            err!(
+                Config,
+                "signature policy references unknown signing key: \"{}\"",
+                label
+            )
+        })?;
+
+        let key = PublicKey::from_keyuri(keyuri).map_err(|e| err!(InvalidKey, "{}", e))?;
+
+        if authorized_keys.contains(&key) {
+            fail!(Config, "duplicate key in signature policy: \"{}\"", label);
+        }
+
+        authorized_keys.push(key);
+    }
+
+    SignaturePolicy::new(config.threshold, authorized_keys)
+        .map_err(|e| err!(Config, "invalid signature policy: {}", e))
+}
+
+/// Ensure all of the given keys have a KeyURI prefix allowed by the policy
+fn enforce_policy(policy: &KeyPolicyConfig, keys: &BTreeMap<String, String>) -> Result<(), Error> {
+    if policy.allowed_prefixes.is_empty() {
+        return Ok(());
+    }
+
+    for (label, encoded_key) in keys {
+        let prefix = encoded_key
+            .splitn(2, bech32k::SEPARATOR)
+            .next()
+            .unwrap_or_default();
+
+        if !policy
+            .allowed_prefixes
+            .iter()
+            .any(|allowed| allowed == prefix)
+        {
+            fail!(
+                InvalidKey,
+                "key \"{}\" has a disallowed KeyURI prefix: {}",
+                label,
+                prefix
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        enforce_policy, signature_policy, KeyLabels, KeyringConfig, Keyrings,
+        SignaturePolicyConfig, FALLBACK_KEY_LABEL,
+    };
+    use crate::config::KeyPolicyConfig;
+    use crate::pack::{Pack, RejectionReason};
+    use chrono::Utc;
+    use placer_pack::crypto::{EncryptionKey, Encryptor, PublicKey, Signer};
+    use placer_pack::keyuri::{bech32k, ENCRYPTION_KEY_PREFIX, VERIFY_KEY_PREFIX};
+    use placer_pack::{Fingerprints, PackFile};
+    use slog::{Discard, Logger};
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
+
+    /// Keyrings with a signing key labeled "alice" (and optionally a
+    /// catch-all key) and an encryption key labeled "default"
+    fn example_keyrings(fallback: bool) -> Keyrings {
+        let mut signing_keys = vec![("alice", bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]))];
+
+        if fallback {
+            signing_keys.push((
+                FALLBACK_KEY_LABEL,
+                bech32k::encode(VERIFY_KEY_PREFIX, &[2u8; 32]),
+            ));
+        }
+
+        keyrings_with(&signing_keys)
+    }
+
+    /// Keyrings with the given (labeled) signing keys and an encryption key
+    /// labeled "default"
+    fn keyrings_with(signing_keys: &[(&str, String)]) -> Keyrings {
+        let signing = signing_keys
+            .iter()
+            .map(|(label, keyuri)| ((*label).to_owned(), keyuri.clone()))
+            .collect();
+
+        let mut encryption = BTreeMap::new();
+        encryption.insert(
+            "default".to_owned(),
+            bech32k::encode(ENCRYPTION_KEY_PREFIX, &[3u8; 32]),
+        );
+
+        let config = KeyringConfig {
+            signing,
+            encryption,
+            signature_policy: None,
+        };
+
+        Keyrings::from_config(&config, &KeyPolicyConfig::default()).unwrap()
+    }
+
+    fn example_fingerprints(signing_key: &str) -> Fingerprints {
+        let encryption_keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[3u8; 32]);
+
+        Fingerprints {
+            signing_key: signing_key.to_owned(),
+            encryption_key: EncryptionKey::from_keyuri(&encryption_keyuri)
+                .unwrap()
+                .fingerprint()
+                .to_owned(),
+        }
+    }
+
+    /// Encrypt an example pack to the encryption key in `keyrings_with`, and
+    /// sign it with the given signer
+    fn signed_pack(signer: &Signer) -> Vec<u8> {
+        let uuid = Uuid::from_u128(1);
+        let pack = placer_pack::Pack {
+            uuid,
+            date: Utc::now(),
+            fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: None,
+            channel: None,
+            files: vec![PackFile {
+                filename: "/etc/motd".to_owned(),
+                content_type: String::new(),
+                modified_at: None,
+                body: b"hello".to_vec(),
+            }],
+            chunked_files: vec![],
+            limits: Default::default(),
+        };
+
+        let encryption_keyuri = bech32k::encode(ENCRYPTION_KEY_PREFIX, &[3u8; 32]);
+        let mut encryptor = Encryptor::from_keyuri(&encryption_keyuri, uuid.as_bytes()).unwrap();
+        pack.encrypt_and_sign(&mut encryptor, signer).unwrap()
+    }
+
+    fn public_key(seed: u8) -> PublicKey {
+        Signer::from_bytes(&[seed; 32])
+            .unwrap()
+            .public_key()
+            .unwrap()
+    }
+
+    fn verify(data: &[u8], keyrings: &Keyrings) -> Result<(), RejectionReason> {
+        let log = Logger::root(Discard, o!());
+        Pack::verify_and_decrypt("example", data, keyrings, &log).map(|_| ())
+    }
+
+    #[test]
+    fn test_get_for_fingerprints() {
+        let alice = public_key(1);
+        let mallory = public_key(4);
+        let fallback = public_key(5);
+
+        let get = |keyrings: &Keyrings, key: &PublicKey| {
+            keyrings
+                .get_for_fingerprints(
+                    &example_fingerprints(&key.to_fingerprint()),
+                    &Uuid::nil(),
+                    None,
+                )
+                .map(|(public_key, _)| public_key)
+        };
+
+        let keyrings = keyrings_with(&[("alice", alice.to_keyuri())]);
+        assert_eq!(get(&keyrings, &alice).unwrap(), alice);
+        assert!(get(&keyrings, &mallory).is_err());
+
+        // Unknown signing keys are resolved to the catch-all key, but an
+        // exact match takes precedence over it
+        let keyrings = keyrings_with(&[
+            ("alice", alice.to_keyuri()),
+            (FALLBACK_KEY_LABEL, fallback.to_keyuri()),
+        ]);
+        assert_eq!(get(&keyrings, &alice).unwrap(), alice);
+        assert_eq!(get(&keyrings, &mallory).unwrap(), fallback);
+    }
+
+    #[test]
+    fn test_verify_with_fallback_key() {
+        let alice = Signer::from_bytes(&[1u8; 32]).unwrap();
+        let mallory = Signer::from_bytes(&[4u8; 32]).unwrap();
+        let alice_keyuri = alice.public_key().unwrap().to_keyuri();
+        let mallory_keyuri = mallory.public_key().unwrap().to_keyuri();
+        let fallback_keyuri = public_key(5).to_keyuri();
+
+        let alice_pack = signed_pack(&alice);
+        let mallory_pack = signed_pack(&mallory);
+
+        // Unknown signers are rejected without a catch-all key...
+        let keyrings = keyrings_with(&[("alice", alice_keyuri.clone())]);
+        assert!(verify(&alice_pack, &keyrings).is_ok());
+        assert_eq!(
+            verify(&mallory_pack, &keyrings),
+            Err(RejectionReason::UnknownKey)
+        );
+
+        // ...and when the catch-all key didn't sign the pack
+        let keyrings = keyrings_with(&[
+            ("alice", alice_keyuri.clone()),
+            (FALLBACK_KEY_LABEL, fallback_keyuri),
+        ]);
+        assert_eq!(
+            verify(&mallory_pack, &keyrings),
+            Err(RejectionReason::BadSignature)
+        );
+
+        // The exact match is used rather than the catch-all key
+        assert!(verify(&alice_pack, &keyrings).is_ok());
+
+        // Accepted once the catch-all key verifies it
+        let keyrings = keyrings_with(&[
+            ("alice", alice_keyuri),
+            (FALLBACK_KEY_LABEL, mallory_keyuri),
+        ]);
+        assert!(verify(&mallory_pack, &keyrings).is_ok());
+    }
+
+    #[test]
+    fn test_key_labels() {
+        let alice = PublicKey::from_keyuri(&bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]))
+            .unwrap()
+            .to_fingerprint();
+        let unknown = "unknown-signing-key";
+
+        let labels = |keyrings: &Keyrings, signing_key: &str| {
+            keyrings.key_labels(&example_fingerprints(signing_key), None)
+        };
+
+        let expected = |signing: Option<&str>| KeyLabels {
+            signing: signing.map(str::to_owned),
+            encryption: Some("default".to_owned()),
+        };
+
+        // An exact match is labeled as itself even if there's a catch-all key
+        let keyrings = example_keyrings(true);
+        assert_eq!(labels(&keyrings, &alice), expected(Some("alice")));
+        assert_eq!(
+            labels(&keyrings, unknown),
+            expected(Some(FALLBACK_KEY_LABEL))
+        );
+
+        let keyrings = example_keyrings(false);
+        assert_eq!(labels(&keyrings, &alice), expected(Some("alice")));
+        assert_eq!(labels(&keyrings, unknown), expected(None));
+    }
+
+    #[test]
+    fn test_signature_policy() {
+        let mut keys = BTreeMap::new();
+        keys.insert(
+            "alice".to_owned(),
+            bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]),
+        );
+        keys.insert(
+            "bob".to_owned(),
+            bech32k::encode(VERIFY_KEY_PREFIX, &[2u8; 32]),
+        );
+
+        let config = SignaturePolicyConfig {
+            threshold: 2,
+            keys: vec!["alice".to_owned(), "bob".to_owned()],
+        };
+        assert_eq!(signature_policy(&config, &keys).unwrap().threshold(), 2);
+
+        let unknown = SignaturePolicyConfig {
+            threshold: 1,
+            keys: vec!["carol".to_owned()],
+        };
+        assert!(signature_policy(&unknown, &keys).is_err());
+
+        let too_high = SignaturePolicyConfig {
+            threshold: 3,
+            keys: vec!["alice".to_owned(), "bob".to_owned()],
+        };
+        assert!(signature_policy(&too_high, &keys).is_err());
+    }
+
+    #[test]
+    fn test_enforce_policy() {
+        let policy = KeyPolicyConfig {
+            allowed_prefixes: vec![VERIFY_KEY_PREFIX.to_owned()],
+        };
+
+        let mut keys = BTreeMap::new();
+        keys.insert(
+            "default".to_owned(),
+            bech32k::encode(VERIFY_KEY_PREFIX, &[0u8; 32]),
+        );
+        assert!(enforce_policy(&policy, &keys).is_ok());
+
+        keys.insert(
+            "other".to_owned(),
+            bech32k::encode(ENCRYPTION_KEY_PREFIX, &[0u8; 32]),
+        );
+        assert_eq!(
+            enforce_policy(&policy, &keys).unwrap_err(),
+            err!(
+                InvalidKey,
+                "key \"other\" has a disallowed KeyURI prefix: {}",
+                ENCRYPTION_KEY_PREFIX
+            )
+        );
+    }
 }
@@ -2,9 +2,11 @@
 
 use crate::error::Error;
 use clear_on_drop::clear::Clear;
+use placer_pack::error::Error as PackError;
+use placer_pack::keyring_file;
 use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
-use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
@@ -12,6 +14,18 @@ use std::path::Path;
 /// Restricted since they (may) contain encryption keys
 pub const REQUIRED_FILE_PERMISSIONS: u32 = 0o600;
 
+/// Prefix of environment variables containing signing (verify) keys
+pub const SIGNING_ENV_PREFIX: &str = "PLACER_SIGNING_";
+
+/// Prefix of environment variables containing encryption keys
+pub const ENCRYPTION_ENV_PREFIX: &str = "PLACER_ENCRYPTION_";
+
+/// Example of the sections a keyring configuration must contain
+const EXAMPLE_SECTIONS: &str = "[signing]\n\
+                                default = \"public.key:ed25519...\"\n\n\
+                                [encryption]\n\
+                                default = \"secret.key:aes256siv+hks256...\"";
+
 /// Keyring configuration
 #[derive(Serialize, Deserialize, Debug)]
 pub struct KeyringConfig {
@@ -20,6 +34,21 @@ pub struct KeyringConfig {
 
     /// Encryption keyring
     pub encryption: BTreeMap<String, String>,
+
+    /// Require packs to be signed by several keys in the signing keyring
+    #[serde(default)]
+    pub signature_policy: Option<SignaturePolicyConfig>,
+}
+
+/// k-of-n multi-signature policy configuration
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SignaturePolicyConfig {
+    /// Number of distinct keys which must have signed each pack
+    pub threshold: usize,
+
+    /// Labels of the signing keys authorized to sign packs
+    pub keys: Vec<String>,
 }
 
 impl KeyringConfig {
@@ -38,18 +67,67 @@ impl KeyringConfig {
             );
         }
 
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
+        let data = keyring_file::read_to_string(&mut file, filename).map_err(|e| match e {
+            PackError::Config { description } => err!(Config, "{}", description),
+            e => err!(Io, "{}", e),
+        })?;
 
-        toml::from_str(&data).map_err(|e| {
-            err!(
-                Config,
-                "couldn't parse {}: {}",
-                filename.to_string_lossy(),
-                e
-            )
+        let result = Self::parse(filename, &data);
+        data.into_bytes().as_mut_slice().clear();
+        result
+    }
+
+    /// Parse a keyring configuration (read from the given file)
+    fn parse(filename: &Path, data: &str) -> Result<Self, Error> {
+        toml::from_str(data).map_err(|e| {
+            match keyring_file::missing_section(filename, &e, EXAMPLE_SECTIONS) {
+                Some(missing) => err!(Config, "{}", missing),
+                None => Error::from_toml(filename, &e),
+            }
         })
     }
+
+    /// Load the keyring configuration from environment variables named
+    /// `PLACER_SIGNING_<label>` and `PLACER_ENCRYPTION_<label>`, removing
+    /// them from the environment so they aren't inherited by subprocesses
+    pub fn from_env() -> Result<Self, Error> {
+        let mut config = Self {
+            signing: BTreeMap::new(),
+            encryption: BTreeMap::new(),
+            signature_policy: None,
+        };
+
+        for (name, value) in env::vars_os() {
+            let name = match name.into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            let keyring = if name.starts_with(SIGNING_ENV_PREFIX) {
+                &mut config.signing
+            } else if name.starts_with(ENCRYPTION_ENV_PREFIX) {
+                &mut config.encryption
+            } else {
+                continue;
+            };
+
+            env::remove_var(&name);
+
+            let value = value
+                .into_string()
+                .map_err(|_| err!(Config, "invalid {}: not valid UTF-8", name))?;
+
+            let label = name.splitn(3, '_').nth(2).unwrap();
+
+            if label.is_empty() {
+                fail!(Config, "missing key label in {}", name);
+            }
+
+            keyring.insert(label.to_owned(), value);
+        }
+
+        Ok(config)
+    }
 }
 
 impl Drop for KeyringConfig {
@@ -59,3 +137,53 @@ impl Drop for KeyringConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyringConfig, REQUIRED_FILE_PERMISSIONS};
+    use crate::error::Error;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn test_missing_sections() {
+        let path = Path::new("keyring.toml");
+        assert!(KeyringConfig::parse(path, "[signing]\n[encryption]\n").is_ok());
+
+        let err = KeyringConfig::parse(path, "[signing]\nfoo = \"bar\"\n").unwrap_err();
+        assert!(err.to_string().contains("missing its [encryption] section"));
+
+        let err = KeyringConfig::parse(path, "[encryption]\n").unwrap_err();
+        assert!(err.to_string().contains("missing its [signing] section"));
+
+        // Other errors are still reported with their location
+        match KeyringConfig::parse(path, "[signing]\n[encryption]\nfoo = 1\n") {
+            Err(Error::ConfigParse { line, .. }) => assert_eq!(line, Some(3)),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_invalid_utf8() {
+        let path = env::temp_dir().join(format!("placer-keyring-{}.toml", std::process::id()));
+
+        OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(REQUIRED_FILE_PERMISSIONS)
+            .open(&path)
+            .and_then(|mut file| file.write_all(b"[signing]\nfoo = \"\xff\"\n"))
+            .unwrap();
+
+        let result = KeyringConfig::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        match result {
+            Err(Error::Config { description }) => assert!(description.contains("UTF-8")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+    }
+}
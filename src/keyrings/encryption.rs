@@ -3,42 +3,93 @@
 use std::collections::BTreeMap;
 
 use crate::error::Error;
-use placer_pack::crypto::Encryptor;
-use placer_pack::keyuri::{self, ENCRYPTION_KEY_PREFIX};
+use placer_pack::crypto::{EncryptionKey, Encryptor};
+use placer_pack::keyuri::{
+    fingerprint_with, normalize_fingerprint, FingerprintAlgorithm, ENCRYPTION_KEY_PREFIX,
+};
 
-/// Keyring of `Encryptor` values
+/// Keyring of `EncryptionKey` values, decoded once at load time so looking
+/// up a key only needs to derive a per-pack `Encryptor`
 pub struct EncryptionKeyring {
-    /// Encryption keys
-    pub keys: BTreeMap<String, String>,
+    /// Encryption keys (by normalized fingerprint)
+    keys: BTreeMap<String, EncryptionKey>,
+
+    /// Normalized fingerprints of each key under other fingerprint
+    /// algorithms, mapped to the fingerprint it's stored under in `keys`
+    aliases: BTreeMap<String, String>,
+
+    /// Labels of keys in the keyring config (by normalized fingerprint)
+    labels: BTreeMap<String, String>,
 }
 
 impl EncryptionKeyring {
     /// Create a new encryption keyring
     pub fn new(keys: &BTreeMap<String, String>) -> Result<Self, Error> {
         let mut k = BTreeMap::new();
+        let mut aliases = BTreeMap::new();
+        let mut labels = BTreeMap::new();
 
         for (label, encoded_key) in keys {
             if !encoded_key.starts_with(ENCRYPTION_KEY_PREFIX) {
-                fail!(InvalidKey, "invalid encryption KeyURI: {}", encoded_key,)
+                fail!(InvalidKey, "invalid encryption KeyURI: \"{}\"", label)
             }
 
-            if k.insert(keyuri::fingerprint(encoded_key), encoded_key.clone())
-                .is_some()
-            {
+            let key = EncryptionKey::from_keyuri(encoded_key).map_err(|e| {
+                err!(
+                    InvalidKey,
+                    "invalid encryption KeyURI: \"{}\" ({})",
+                    label,
+                    e
+                )
+            })?;
+
+            let fingerprint = normalize_fingerprint(key.fingerprint());
+
+            for &algorithm in FingerprintAlgorithm::ALL {
+                if algorithm != FingerprintAlgorithm::default() {
+                    let alias = normalize_fingerprint(&fingerprint_with(algorithm, encoded_key));
+                    aliases.insert(alias, fingerprint.clone());
+                }
+            }
+
+            labels.insert(fingerprint.clone(), label.clone());
+
+            if k.insert(fingerprint, key).is_some() {
                 fail!(InvalidKey, "duplicate encryption key: \"{}\"", label);
             }
         }
 
-        Ok(EncryptionKeyring { keys: k })
+        Ok(EncryptionKeyring {
+            keys: k,
+            aliases,
+            labels,
+        })
     }
 
     /// Get an `Encryptor` from the keyring
     #[inline]
     pub fn get(&self, key: &str, salt: &[u8]) -> Result<Encryptor, Error> {
-        match self.keys.get(key) {
-            Some(k) => Ok(Encryptor::from_keyuri(k, salt)
-                .map_err(|e| err!(InvalidKey, "invalid encryption KeyURI: \"{}\" ({})", k, e))?),
+        match self.keys.get(&self.canonical_fingerprint(key)) {
+            Some(k) => Ok(k.encryptor(salt)),
             None => fail!(InvalidKey, "unknown encryption key: \"{}\"", key),
         }
     }
+
+    /// Get the label of the key with the given fingerprint in the keyring config
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .get(&self.canonical_fingerprint(key))
+            .map(String::as_str)
+    }
+
+    /// Normalize a fingerprint, resolving fingerprints computed with other
+    /// algorithms to the one keys are stored under
+    fn canonical_fingerprint(&self, key: &str) -> String {
+        let fingerprint = normalize_fingerprint(key);
+
+        match self.aliases.get(&fingerprint) {
+            Some(alias) => alias.clone(),
+            None => fingerprint,
+        }
+    }
 }
@@ -4,18 +4,43 @@ use std::collections::BTreeMap;
 
 use crate::error::Error;
 use placer_pack::crypto::PublicKey;
+use placer_pack::keyuri::{normalize_fingerprint, FingerprintAlgorithm, SIGNING_KEY_PREFIX};
+
+/// Label of the catch-all signing key, which packs signed by keys which
+/// aren't in the keyring are verified with
+pub const FALLBACK_KEY_LABEL: &str = "*";
 
 /// Keyring of `PublicKey` (Ed25519 verifier key) values
 pub struct SigningKeyring {
-    /// Signing public keys
+    /// Signing public keys (by normalized fingerprint, under every
+    /// supported fingerprint algorithm)
     keys: BTreeMap<String, PublicKey>,
+
+    /// Labels of keys in the keyring config (by the same fingerprints)
+    labels: BTreeMap<String, String>,
+
+    /// Catch-all key (labeled `*` in the keyring config), if configured
+    fallback: Option<PublicKey>,
 }
 
 impl SigningKeyring {
+    /// Create a new signing keyring, which may only contain public keys
     pub fn new(keys: &BTreeMap<String, String>) -> Result<Self, Error> {
         let mut k = BTreeMap::new();
+        let mut labels = BTreeMap::new();
+        let mut fallback = None;
 
         for (label, encoded_key) in keys {
+            // Refuse to load a secret keyring which was deployed by mistake
+            if encoded_key.starts_with(SIGNING_KEY_PREFIX) {
+                fail!(
+                    InvalidKey,
+                    "SECRET signing key \"{}\" found in verify keyring! \
+                     Only public keys may be deployed to placer clients",
+                    label
+                );
+            }
+
             let public_key = PublicKey::from_keyuri(encoded_key).map_err(|e| {
                 err!(
                     InvalidKey,
@@ -25,19 +50,119 @@ impl SigningKeyring {
                 )
             })?;
 
-            if k.insert(public_key.to_fingerprint(), public_key).is_some() {
-                fail!(InvalidKey, "duplicate signing key: \"{}\"", label);
+            if label == FALLBACK_KEY_LABEL {
+                fallback = Some(public_key);
+            }
+
+            for &algorithm in FingerprintAlgorithm::ALL {
+                let fingerprint = normalize_fingerprint(&public_key.to_fingerprint_with(algorithm));
+
+                labels.insert(fingerprint.clone(), label.clone());
+
+                if k.insert(fingerprint, public_key).is_some() {
+                    fail!(InvalidKey, "duplicate signing key: \"{}\"", label);
+                }
             }
         }
 
-        Ok(SigningKeyring { keys: k })
+        Ok(SigningKeyring {
+            keys: k,
+            labels,
+            fallback,
+        })
     }
 
     /// Get an Ed25519 `PublicKey` from the keyring
     #[inline]
     pub fn get(&self, key: &str) -> Result<&PublicKey, Error> {
         self.keys
-            .get(key)
+            .get(&normalize_fingerprint(key))
             .ok_or_else(|| err!(InvalidKey, "unknown signing key: \"{}\"", key))
     }
+
+    /// Get the catch-all key (if configured)
+    pub fn fallback(&self) -> Option<&PublicKey> {
+        self.fallback.as_ref()
+    }
+
+    /// Get the label of the key with the given fingerprint in the keyring config
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .get(&normalize_fingerprint(key))
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SigningKeyring, FALLBACK_KEY_LABEL};
+    use placer_pack::crypto::PublicKey;
+    use placer_pack::keyuri::{
+        bech32k, FingerprintAlgorithm, SIGNING_KEY_PREFIX, VERIFY_KEY_PREFIX,
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_rejects_secret_keys() {
+        let mut keys = BTreeMap::new();
+        keys.insert(
+            "default".to_owned(),
+            bech32k::encode(SIGNING_KEY_PREFIX, &[0u8; 32]),
+        );
+
+        assert!(SigningKeyring::new(&keys).is_err());
+    }
+
+    #[test]
+    fn test_mixed_case_fingerprint() {
+        let keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]);
+        let public_key = PublicKey::from_keyuri(&keyuri).unwrap();
+
+        let mut keys = BTreeMap::new();
+        keys.insert("default".to_owned(), keyuri);
+        let keyring = SigningKeyring::new(&keys).unwrap();
+
+        // Uppercase everything after the KeyURI prefix
+        let fingerprint = public_key.to_fingerprint();
+        let (prefix, data) = fingerprint.split_at(fingerprint.find(';').unwrap());
+        let mangled = format!(" {}{} ", prefix, data.to_ascii_uppercase());
+
+        assert_eq!(keyring.get(&mangled).unwrap(), &public_key);
+    }
+
+    #[test]
+    fn test_fingerprint_algorithms() {
+        let keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]);
+        let public_key = PublicKey::from_keyuri(&keyuri).unwrap();
+
+        let mut keys = BTreeMap::new();
+        keys.insert("default".to_owned(), keyuri);
+        let keyring = SigningKeyring::new(&keys).unwrap();
+
+        for &algorithm in FingerprintAlgorithm::ALL {
+            let fingerprint = public_key.to_fingerprint_with(algorithm);
+            assert_eq!(keyring.get(&fingerprint).unwrap(), &public_key);
+            assert_eq!(keyring.label(&fingerprint), Some("default"));
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]);
+        let public_key = PublicKey::from_keyuri(&keyuri).unwrap();
+
+        let mut keys = BTreeMap::new();
+        keys.insert("default".to_owned(), keyuri.clone());
+        assert!(SigningKeyring::new(&keys).unwrap().fallback().is_none());
+
+        keys.clear();
+        keys.insert(FALLBACK_KEY_LABEL.to_owned(), keyuri);
+        let keyring = SigningKeyring::new(&keys).unwrap();
+
+        assert_eq!(keyring.fallback(), Some(&public_key));
+        assert_eq!(
+            keyring.get(&public_key.to_fingerprint()).unwrap(),
+            &public_key
+        );
+    }
 }
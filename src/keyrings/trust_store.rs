@@ -0,0 +1,158 @@
+//! Trust-on-first-use store of signing keys (like SSH's `known_hosts`)
+//!
+//! Each line of the store file contains a pack name followed by the verify
+//! key KeyURI first seen signing that pack, e.g.:
+//!
+//! ```text
+//! my-pack public.key:ed25519;...
+//! ```
+
+use placer_pack::crypto::PublicKey;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::config::REQUIRED_FILE_PERMISSIONS;
+use crate::error::Error;
+
+/// Trust store of signing keys learned on first use, by pack name
+pub struct TrustStore {
+    /// Path to the trust store file
+    path: PathBuf,
+
+    /// Trusted signing keys by pack name
+    keys: Mutex<BTreeMap<String, PublicKey>>,
+}
+
+impl TrustStore {
+    /// Load the trust store from the given file, creating it if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .mode(REQUIRED_FILE_PERMISSIONS)
+                .open(path)
+                .map_err(|e| err!(Io, "couldn't create {}: {}", path.display(), e))?,
+            Err(e) => return Err(err!(Io, "couldn't open {}: {}", path.display(), e)),
+        };
+
+        let permissions = file.metadata()?.permissions();
+
+        if permissions.mode() != (0o100_000 | REQUIRED_FILE_PERMISSIONS) {
+            fail!(
+                Config,
+                "bad file permissions for {:?} (must be chmod 0600)",
+                path.display()
+            );
+        }
+
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            keys: Mutex::new(parse(&data)?),
+        })
+    }
+
+    /// Check whether the given key is trusted for the given pack. Returns
+    /// `true` if it was previously trusted, `false` if no key has been seen
+    /// for this pack yet, and an error if a *different* key was trusted.
+    pub fn check(&self, pack_name: &str, key: &PublicKey) -> Result<bool, Error> {
+        match self.keys.lock().unwrap().get(pack_name) {
+            Some(trusted_key) if trusted_key == key => Ok(true),
+            Some(trusted_key) => Err(err!(
+                InvalidKey,
+                "signing key for \"{}\" pack CHANGED! trusted {}, got {} \
+                 (remove the entry from {} if this change is expected)",
+                pack_name,
+                trusted_key.to_fingerprint(),
+                key.to_fingerprint(),
+                self.path.display()
+            )),
+            None => Ok(false),
+        }
+    }
+
+    /// Trust the given key for the given pack from now on, persisting it
+    pub fn record(&self, pack_name: &str, key: &PublicKey) -> Result<(), Error> {
+        let mut keys = self.keys.lock().unwrap();
+
+        if pack_name.is_empty() || pack_name.contains(char::is_whitespace) {
+            fail!(InvalidKey, "can't trust key for pack name {:?}", pack_name);
+        }
+
+        if keys.contains_key(pack_name) {
+            fail!(
+                InvalidKey,
+                "already trusted a key for \"{}\" pack",
+                pack_name
+            );
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| err!(Io, "couldn't open {}: {}", self.path.display(), e))?;
+
+        writeln!(file, "{} {}", pack_name, key.to_keyuri())?;
+        file.sync_all()?;
+
+        keys.insert(pack_name.to_owned(), *key);
+        Ok(())
+    }
+}
+
+/// Parse the contents of a trust store file
+fn parse(data: &str) -> Result<BTreeMap<String, PublicKey>, Error> {
+    let mut keys = BTreeMap::new();
+
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+
+        let (pack_name, keyuri) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(pack_name), Some(keyuri), None) => (pack_name, keyuri),
+            _ => fail!(Config, "malformed trust store entry on line {}", i + 1),
+        };
+
+        let key = PublicKey::from_keyuri(keyuri)
+            .map_err(|e| err!(Config, "bad key on trust store line {}: {}", i + 1, e))?;
+
+        if keys.insert(pack_name.to_owned(), key).is_some() {
+            fail!(Config, "duplicate trust store entry for \"{}\"", pack_name);
+        }
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use placer_pack::keyuri::{bech32k, VERIFY_KEY_PREFIX};
+
+    #[test]
+    fn test_parse() {
+        let keyuri = bech32k::encode(VERIFY_KEY_PREFIX, &[1u8; 32]);
+        let data = format!("# comment\n\nmy-pack {}\n", keyuri);
+
+        let keys = parse(&data).unwrap();
+        assert_eq!(keys["my-pack"].to_keyuri(), keyuri);
+
+        let duplicate = format!("my-pack {}\nmy-pack {}\n", keyuri, keyuri);
+        assert!(parse(&duplicate).is_err());
+        assert!(parse("my-pack").is_err());
+    }
+}
@@ -3,7 +3,7 @@
 /// Create a new error (of a given enum variant) with a formatted message
 macro_rules! err {
     ($variant:ident, $msg:expr) => {
-        ::error::Error::$variant { description: $msg.to_owned() }
+        crate::error::Error::$variant { description: $msg.to_owned() }
     };
     ($variant:ident, $fmt:expr, $($arg:tt)+) => {
         crate::error::Error::$variant { description: format!($fmt, $($arg)+) }
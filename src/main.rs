@@ -3,6 +3,9 @@
 #![crate_name = "placer"]
 #![deny(missing_docs, unsafe_code, unused_import_braces, unused_qualifications)]
 
+#[cfg(not(unix))]
+compile_error!("placer only supports Unix-like platforms");
+
 #[macro_use]
 extern crate failure_derive;
 #[macro_use]
@@ -20,27 +23,46 @@ mod macros;
 mod config;
 mod digest;
 mod error;
+mod file_stamps;
 mod hook;
 mod keyrings;
 mod pack;
+mod platform;
+mod readiness;
 mod source;
+mod staging;
+mod status;
+mod systemd;
 mod target_file;
+mod throttle;
 
-use slog::{Drain, Logger};
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use clear_on_drop::clear::Clear;
+use slog::{Discard, Drain, Logger};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
-use users::UsersCache;
+use users::{Groups, Users, UsersCache};
 
-use crate::config::{Config, FileConfig};
+use crate::config::{Config, FileConfig, SourceConfig, StrictConfig};
 use crate::digest::Digest;
-use crate::keyrings::Keyrings;
-use crate::pack::Pack;
-use crate::source::Source;
-use crate::target_file::TargetFile;
+use crate::error::Error;
+use crate::file_stamps::FileStamps;
+use crate::keyrings::{Keyrings, TrustStore, FALLBACK_KEY_LABEL};
+use crate::pack::{DeadLetters, FailureTracker, Pack, RecentPacks};
+use crate::platform;
+use crate::source::{PackSource, Source};
+use crate::status::Status;
+use crate::systemd::Watchdog;
+use crate::target_file::{parse_mode, TargetFile};
+use crate::throttle::{self, QueueReceiver, RateLimit};
 
 lazy_static! {
     /// Path to the placer executable
@@ -73,6 +95,30 @@ struct Opts {
     /// Print debugging information
     #[structopt(short = "v", long = "verbose")]
     verbose: bool,
+
+    /// Subcommand to run (runs the daemon if none is given)
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// placer subcommands
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(
+        name = "promote",
+        about = "move staged files into place (running their hooks)"
+    )]
+    Promote {
+        /// Real paths of the staged files to promote (all if none are given)
+        #[structopt(name = "FILE", parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+
+    #[structopt(
+        name = "status",
+        about = "show the pack each file was most recently placed from"
+    )]
+    Status,
 }
 
 fn main() {
@@ -86,13 +132,101 @@ fn main() {
         process::exit(1);
     });
 
-    let keyrings = Keyrings::load(&config.keyrings).unwrap_or_else(|e| {
+    // Promoting staged files doesn't conflict with a running daemon: the
+    // files themselves are locked while they're placed
+    match opts.command {
+        Some(Command::Promote { files }) => staging::promote(&config, &files, &log),
+        Some(Command::Status) => status::print(&config, &log),
+        None => (),
+    }
+
+    // Refuse to run two daemons against the same config. The lock on the
+    // config file (opened read-only) is held until placer exits.
+    let _config_lock = match platform::try_lock_existing(&opts.config) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            crit!(
+                &log,
+                "another placer instance is already running with {}",
+                opts.config.display()
+            );
+            process::exit(1);
+        }
+        Err(e) => {
+            crit!(&log, "error locking {}: {}", opts.config.display(), e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = parse_mode(
+        &config.quarantine.mode,
+        config.quarantine.allow_dangerous_mode,
+    ) {
+        crit!(&log, "bad quarantine config: {}", e);
+        process::exit(1);
+    }
+
+    let keyrings_result = match (&config.keyrings, config.keyrings_from_env) {
+        (Some(path), false) => Keyrings::load(path, &config.key_policy),
+        (None, true) => Keyrings::from_env(&config.key_policy),
+        _ => {
+            crit!(
+                &log,
+                "exactly one of `keyrings` or `keyrings_from_env` must be configured"
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut keyrings = keyrings_result.unwrap_or_else(|e| {
         crit!(&log, "error loading keyrings: {}", e);
         process::exit(1);
     });
 
+    if let Some(ref path) = config.trust_on_first_use {
+        let trust_store = TrustStore::load(path).unwrap_or_else(|e| {
+            crit!(&log, "error loading trust store: {}", e);
+            process::exit(1);
+        });
+
+        warn!(
+            &log,
+            "trust-on-first-use ENABLED: packs signed by unknown keys will be \
+             accepted the first time they're seen (trust store: {})",
+            path.display()
+        );
+
+        keyrings = keyrings.with_trust_store(trust_store);
+    }
+
+    if keyrings.signing.fallback().is_some() {
+        warn!(
+            &log,
+            "catch-all signing key \"{}\" configured: packs signed by keys which \
+             aren't in the keyring will be verified with it",
+            FALLBACK_KEY_LABEL
+        );
+    }
+
     let mut users_cache = UsersCache::new();
-    let file_properties = process_file_config(&config.files, &mut users_cache, &log);
+    let mut file_properties = process_file_config(&config.files, &mut users_cache, &log);
+
+    if let Some(ref staging_root) = config.staging_root {
+        if let Err(e) = staging::check_staging_root(staging_root) {
+            crit!(&log, "bad staging_root: {}", e);
+            process::exit(1);
+        }
+
+        warn!(
+            &log,
+            "Staging updated files under {} for review (use `placer promote` to place them)",
+            staging_root.display()
+        );
+
+        for target in file_properties.values_mut() {
+            target.staging_root = Some(staging_root.clone());
+        }
+    }
 
     // TODO: support for multiple sources running in their own threads
     //let mut _sources: Vec<Source> = config
@@ -117,8 +251,8 @@ fn main() {
     let source_name = "http".to_owned();
     let source_config = &config.sources[&source_name];
 
-    let mut source =
-        Source::new(&source_name, source_config, &mut users_cache).unwrap_or_else(|e| {
+    let source =
+        Source::new(&source_name, source_config, &mut users_cache, &log).unwrap_or_else(|e| {
             crit!(&log, "error configuring \"{}\" source: {}", source_name, e);
             process::exit(1);
         });
@@ -132,13 +266,415 @@ fn main() {
         );
     }
 
+    for resource in source.unacknowledged_resources() {
+        warn!(
+            &log,
+            "[source:{}] Source will not fetch \"{}\" pack: {}",
+            source_name,
+            source.resources()[resource],
+            resource
+        );
+    }
+
+    let dead_letters = if config.quarantine.dead_letters {
+        Some(dead_letters(&config, &mut users_cache).unwrap_or_else(|e| {
+            crit!(&log, "bad quarantine config: {}", e);
+            process::exit(1);
+        }))
+    } else {
+        None
+    };
+
+    let failures = FailureTracker::new(config.failure_threshold, dead_letters);
+
+    let satisfied_targets = Arc::new(Mutex::new(BTreeSet::new()));
+
+    if let Some(ref strict) = config.strict {
+        spawn_strict_watchdog(
+            strict,
+            file_properties.keys().cloned().collect(),
+            Arc::clone(&satisfied_targets),
+            log.clone(),
+        );
+    }
+
+    if let Some(ref readiness) = config.readiness {
+        readiness::spawn_readiness_watcher(
+            readiness,
+            file_properties.keys().cloned().collect(),
+            Arc::clone(&satisfied_targets),
+            log.clone(),
+        )
+        .unwrap_or_else(|e| {
+            crit!(&log, "bad readiness config: {}", e);
+            process::exit(1);
+        });
+    } else {
+        systemd::notify_ready(&log);
+    }
+
+    let status = config.status_file.as_ref().map(|path| {
+        Status::new(path).unwrap_or_else(|e| {
+            crit!(&log, "bad status_file: {}", e);
+            process::exit(1);
+        })
+    });
+
+    run_source(
+        &source_name,
+        source,
+        source_config,
+        &keyrings,
+        &file_properties,
+        config.placement_threads,
+        config.compare_by_metadata,
+        status.as_ref(),
+        config.debounce_window.map(Duration::from_secs),
+        config.strict.is_some(),
+        failures,
+        &satisfied_targets,
+        &log,
+    )
+}
+
+/// Configure writing dead letters to the quarantine directory
+fn dead_letters(config: &Config, users_cache: &mut UsersCache) -> Result<DeadLetters, Error> {
+    let quarantine = &config.quarantine;
+
+    ensure!(
+        quarantine.path.is_dir(),
+        Config,
+        "quarantine directory doesn't exist: {}",
+        quarantine.path.display()
+    );
+
+    let user = users_cache
+        .get_user_by_name(&quarantine.user)
+        .ok_or_else(|| err!(Config, "invalid user: {}", &quarantine.user))?;
+
+    let group = users_cache
+        .get_group_by_name(&quarantine.group)
+        .ok_or_else(|| err!(Config, "invalid group: {}", &quarantine.group))?;
+
+    Ok(DeadLetters {
+        dir: quarantine.path.clone(),
+        permissions: parse_mode(&quarantine.mode, quarantine.allow_dangerous_mode)?,
+        uid: user.uid(),
+        gid: group.gid(),
+    })
+}
+
+/// Verify and place packs from the given source as they arrive.
+///
+/// If a debounce window is given, packs which arrive within it of the first
+/// are collected into a batch, and only the newest of each pack is placed
+/// (so each target is placed, and its hooks run, at most once per batch).
+#[allow(clippy::too_many_arguments)]
+fn run_source<S: PackSource + Send + 'static>(
+    source_name: &str,
+    source: S,
+    source_config: &SourceConfig,
+    keyrings: &Keyrings,
+    file_properties: &BTreeMap<PathBuf, TargetFile>,
+    placement_threads: usize,
+    compare_by_metadata: bool,
+    status: Option<&Status>,
+    debounce: Option<Duration>,
+    reject_empty_packs: bool,
+    mut failures: FailureTracker,
+    satisfied_targets: &Mutex<BTreeSet<PathBuf>>,
+    log: &Logger,
+) -> ! {
+    let packs = spawn_reader(source_name, source, source_config, log);
+
+    // systemd watchdog (if enabled), pinged while waiting for packs
+    let watchdog = systemd::watchdog();
+
+    // Most recently placed pack for each pack name (base for delta packs)
+    let mut placed_packs = BTreeMap::new();
+
+    // Digests of recently placed packs (across all sources)
+    let mut recent_packs = RecentPacks::default();
+
+    // Sizes and mtimes of placed files, if they're used to skip comparisons
+    let stamps = if compare_by_metadata {
+        Some(FileStamps::default())
+    } else {
+        None
+    };
+
+    loop {
+        // Newest pack for each pack name in this batch (with deltas applied)
+        let mut batch = BTreeMap::new();
+
+        let file = next_pack(&packs, watchdog, source_name, log);
+
+        let pack = verify_pack(
+            source_name,
+            file,
+            keyrings,
+            &recent_packs,
+            &mut failures,
+            reject_empty_packs,
+            &source_config.channels,
+            log,
+        );
+        add_to_batch(pack, &mut batch, &placed_packs, log);
+
+        if let Some(window) = debounce {
+            let deadline = Instant::now() + window;
+
+            loop {
+                systemd::ping_watchdog(log);
+
+                let mut timeout = deadline.saturating_duration_since(Instant::now());
+
+                if let Some(watchdog) = watchdog {
+                    timeout = timeout.min(watchdog.time_until_due());
+                }
+
+                let file = match packs.recv_timeout(timeout) {
+                    Ok(file) => file,
+                    Err(RecvTimeoutError::Timeout) if Instant::now() < deadline => continue,
+                    Err(_) => break,
+                };
+
+                let pack = verify_pack(
+                    source_name,
+                    file,
+                    keyrings,
+                    &recent_packs,
+                    &mut failures,
+                    reject_empty_packs,
+                    &source_config.channels,
+                    log,
+                );
+                add_to_batch(pack, &mut batch, &placed_packs, log);
+            }
+        }
+
+        let updated_packs: Vec<String> = batch.keys().cloned().collect();
+
+        for (name, pack) in batch {
+            process_pack(
+                &pack,
+                file_properties,
+                source_config.allow_extra_files,
+                placement_threads,
+                stamps.as_ref(),
+                status,
+                log,
+            );
+
+            satisfied_targets.lock().unwrap().extend(
+                file_properties
+                    .iter()
+                    .filter(|(_, target)| satisfies(target, &pack))
+                    .map(|(path, _)| path.clone()),
+            );
+
+            // Only packs which have been placed are skipped if they're
+            // received again, so packs which failed are always retried
+            recent_packs.insert(pack.digest.clone());
+            placed_packs.insert(name, pack);
+        }
+
+        let composed = place_composed_files(
+            &updated_packs,
+            &placed_packs,
+            file_properties,
+            stamps.as_ref(),
+            status,
+            log,
+        );
+        satisfied_targets.lock().unwrap().extend(composed);
+
+        if let Some(status) = status {
+            if let Err(e) = status.save() {
+                error!(log, "couldn't update status file: {}", e);
+            }
+        }
+    }
+}
+
+/// Wait for the next pack from the source, pinging the systemd watchdog (if
+/// enabled) while waiting
+fn next_pack(
+    packs: &QueueReceiver<(String, Vec<u8>)>,
+    watchdog: Option<&Watchdog>,
+    source_name: &str,
+    log: &Logger,
+) -> (String, Vec<u8>) {
     loop {
-        if let Some(pack) = get_next_pack(&source_name, &mut source, &keyrings, &log) {
-            process_pack(&pack, &file_properties, &log);
+        let result = match watchdog {
+            Some(watchdog) => {
+                watchdog.ping_if_due(log);
+                packs.recv_timeout(watchdog.time_until_due())
+            }
+            None => packs.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match result {
+            Ok(file) => return file,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                crit!(log, "[source:{}] reader thread exited", source_name);
+                systemd::notify_stopping(log);
+                process::exit(1);
+            }
         }
     }
 }
 
+/// Read packs from the source on their own thread, so they can be waited on
+/// with a timeout (for debouncing). Packs are held back if the source delivers
+/// them faster than its rate limit, and the oldest are dropped if too many are
+/// queued.
+fn spawn_reader<S: PackSource + Send + 'static>(
+    source_name: &str,
+    mut source: S,
+    config: &SourceConfig,
+    log: &Logger,
+) -> QueueReceiver<(String, Vec<u8>)> {
+    let (sender, receiver) = throttle::queue(config.max_queued_packs);
+    let mut rate_limit = config.max_packs_per_minute.map(RateLimit::per_minute);
+    let source_name = source_name.to_owned();
+    let log = log.clone();
+
+    thread::spawn(move || loop {
+        let file = source.next_file().unwrap_or_else(|e| {
+            crit!(log, "[source:{}] read error: {}", source_name, e);
+            process::exit(1);
+        });
+
+        if let Some(ref mut rate_limit) = rate_limit {
+            let delay = rate_limit.wait();
+
+            if delay > Duration::from_secs(0) {
+                debug!(
+                    log,
+                    "[source:{}] held back \"{}\" pack for {}ms (rate limited)",
+                    source_name,
+                    file.0,
+                    delay.as_millis()
+                );
+            }
+        }
+
+        match sender.send(file) {
+            Ok(None) => (),
+            Ok(Some((pack_name, _))) => warn!(
+                log,
+                "[source:{}] dropping older \"{}\" pack: too many packs waiting to be verified",
+                source_name,
+                pack_name
+            ),
+            Err(_) => break,
+        }
+    });
+
+    receiver
+}
+
+/// Add a verified pack to a batch, applying it on top of the batch's (or
+/// the most recently placed) pack if it's a delta pack, and replacing any
+/// older pack with the same name
+fn add_to_batch(
+    pack: Option<Pack>,
+    batch: &mut BTreeMap<String, Pack>,
+    placed_packs: &BTreeMap<String, Pack>,
+    log: &Logger,
+) {
+    let pack = match pack {
+        Some(pack) if batch.contains_key(&pack.name) => apply_delta(pack, batch, log),
+        Some(pack) => apply_delta(pack, placed_packs, log),
+        None => return,
+    };
+
+    let pack = match pack {
+        Some(pack) => pack,
+        None => return,
+    };
+
+    if let Some(pending) = batch.get(&pack.name) {
+        if pending.date() > pack.date() {
+            debug!(
+                log,
+                "Skipping \"{}\" pack {}: newer pack {} already received",
+                pack.name,
+                pack.uuid(),
+                pending.uuid()
+            );
+            return;
+        }
+
+        debug!(
+            log,
+            "Superseding \"{}\" pack {} with {}",
+            pack.name,
+            pending.uuid(),
+            pack.uuid()
+        );
+    }
+
+    batch.insert(pack.name.clone(), pack);
+}
+
+/// Does the given pack contain a file for the given target?
+fn satisfies(target: &TargetFile, pack: &Pack) -> bool {
+    target.pack == pack.name
+        && pack.files().any(|file| {
+            let path = Path::new(&file.filename);
+            path == target.path || target.matches(path)
+        })
+}
+
+/// Strict mode: once the grace period has elapsed, report every target which
+/// hasn't been satisfied by a pack yet, exiting if strict mode is fatal
+fn spawn_strict_watchdog(
+    strict: &StrictConfig,
+    targets: Vec<PathBuf>,
+    satisfied_targets: Arc<Mutex<BTreeSet<PathBuf>>>,
+    log: Logger,
+) {
+    let grace_period = Duration::from_secs(strict.grace_period);
+    let fatal = strict.fatal;
+
+    thread::spawn(move || {
+        thread::sleep(grace_period);
+
+        let satisfied_targets = satisfied_targets.lock().unwrap();
+        let unsatisfied: Vec<_> = targets
+            .iter()
+            .filter(|path| !satisfied_targets.contains(*path))
+            .collect();
+
+        if unsatisfied.is_empty() {
+            info!(log, "[strict] All configured targets satisfied");
+            return;
+        }
+
+        for path in &unsatisfied {
+            error!(
+                log,
+                "[strict] {} not received from its pack within {} seconds",
+                path.to_string_lossy(),
+                grace_period.as_secs()
+            );
+        }
+
+        if fatal {
+            crit!(
+                log,
+                "[strict] {} target(s) unsatisfied; exiting",
+                unsatisfied.len()
+            );
+            systemd::notify_stopping(&log);
+            process::exit(1);
+        }
+    });
+}
+
 /// Initialize the logging subsystem
 fn init_logger() -> Logger {
     // slog configuration
@@ -172,18 +708,19 @@ fn process_file_config(
     result
 }
 
-/// Get the next pack to process
-fn get_next_pack(
+/// Verify a pack fetched from a source, skipping it if we've already
+/// placed an identical pack
+#[allow(clippy::too_many_arguments)]
+fn verify_pack(
     source_name: &str,
-    source: &mut Source,
+    (pack_name, pack_data): (String, Vec<u8>),
     keyrings: &Keyrings,
+    recent_packs: &RecentPacks,
+    failures: &mut FailureTracker,
+    reject_empty: bool,
+    channels: &[String],
     log: &Logger,
 ) -> Option<Pack> {
-    let (pack_name, pack_data) = source.next_file().unwrap_or_else(|e| {
-        crit!(log, "[source:{}] read error: {}", source_name, e);
-        process::exit(1);
-    });
-
     info!(
         log,
         "[source:{}] Fetched \"{}\" pack ({} bytes)",
@@ -192,17 +729,120 @@ fn get_next_pack(
         pack_data.len()
     );
 
-    Pack::verify_and_decrypt(&pack_name, &pack_data, keyrings, log)
+    if recent_packs.contains(&Digest::for_bytes(&pack_data)) {
+        debug!(
+            log,
+            "[source:{}] Skipping \"{}\" pack: already placed identical pack",
+            source_name,
+            pack_name
+        );
+        return None;
+    }
+
+    // Don't log every failure of packs which are failing persistently
+    let quiet = failures.is_quiet(&pack_name);
+    let discard = Logger::root(Discard, o!());
+    let verify_log = if quiet { &discard } else { log };
+
+    let result = Pack::verify_and_decrypt(&pack_name, &pack_data, keyrings, verify_log)
+        .and_then(|pack| pack.check_not_empty(reject_empty, verify_log).map(|_| pack))
+        .and_then(|pack| pack.check_channel(channels, verify_log).map(|_| pack));
+
+    match result {
+        Ok(pack) => {
+            let previous_failures = failures.record_success(&pack_name);
+
+            if previous_failures > 0 {
+                info!(
+                    log,
+                    "[source:{}] \"{}\" pack verified after {} consecutive failure(s)",
+                    source_name,
+                    pack_name,
+                    previous_failures
+                );
+            }
+
+            Some(pack)
+        }
+        Err(reason) => {
+            let consecutive = failures.record_failure(&pack_name, &pack_data, log);
+
+            // Rejections are logged (with their reason) as they're found,
+            // except for packs which are failing persistently
+            if quiet {
+                debug!(
+                    log,
+                    "[source:{}] \"{}\" pack rejected again ({} consecutive failures): {}",
+                    source_name,
+                    pack_name,
+                    consecutive,
+                    reason.as_str();
+                    "reason" => reason.as_str()
+                );
+            }
+
+            None
+        }
+    }
 }
 
-/// Process pack
-fn process_pack(pack: &Pack, targets: &BTreeMap<PathBuf, TargetFile>, log: &Logger) {
+/// If the given pack is a delta pack, apply it on top of the most recently
+/// placed pack with the same name
+fn apply_delta(pack: Pack, placed_packs: &BTreeMap<String, Pack>, log: &Logger) -> Option<Pack> {
+    if !pack.contents.is_delta() {
+        return Some(pack);
+    }
+
+    let base = match placed_packs.get(&pack.name) {
+        Some(base) => base,
+        None => {
+            error!(
+                log,
+                "can't apply delta pack {}:{}: no base pack has been placed",
+                pack.name,
+                pack.uuid()
+            );
+            return None;
+        }
+    };
+
+    let Pack {
+        name,
+        contents,
+        digest,
+    } = pack;
+
+    match base.contents.apply_delta(contents) {
+        Ok(contents) => Some(Pack {
+            name,
+            contents,
+            digest,
+        }),
+        Err(e) => {
+            error!(log, "can't apply delta \"{}\" pack: {}", name, e);
+            None
+        }
+    }
+}
+
+/// Process pack, placing files (and running their hooks) on a pool of
+/// `threads` worker threads. Each target's before hooks, placement, and
+/// after hooks run in order, but different targets proceed concurrently.
+fn process_pack(
+    pack: &Pack,
+    targets: &BTreeMap<PathBuf, TargetFile>,
+    allow_extra_files: bool,
+    threads: usize,
+    stamps: Option<&FileStamps>,
+    status: Option<&Status>,
+    log: &Logger,
+) {
+    let mut placements = vec![];
+
     for file in pack.files() {
-        match targets.get(&PathBuf::from(&file.filename)) {
+        match find_target(targets, &pack.name, Path::new(&file.filename)) {
             Some(target) => {
-                if target.pack == pack.name {
-                    place_file_if_updated(target, pack, &file.body, log);
-                } else {
+                if target.pack != pack.name {
                     debug!(
                         log,
                         "Ignoring {} from \"{}\" pack (configured pack is \"{}\")",
@@ -210,8 +850,34 @@ fn process_pack(pack: &Pack, targets: &BTreeMap<PathBuf, TargetFile>, log: &Logg
                         pack.name,
                         target.pack
                     );
+                } else if !target.accepts_content_type(&file.content_type) {
+                    error!(
+                        log,
+                        "refusing to place {}: Content-Type mismatch (expected \"{}\", \
+                         got \"{}\" from {}:{})",
+                        target.path.to_string_lossy(),
+                        target.content_type.as_ref().unwrap(),
+                        file.content_type,
+                        pack.name,
+                        pack.uuid()
+                    );
+                } else {
+                    placements.push((target, &file.body));
                 }
             }
+            // Fragments are placed once they're composed (after the whole batch)
+            None if targets
+                .values()
+                .any(|target| target.has_fragment(&pack.name, Path::new(&file.filename))) => {}
+            None if allow_extra_files => {
+                debug!(
+                    log,
+                    "Ignoring unconfigured file \"{}\" (from {}:{})",
+                    file.filename,
+                    pack.name,
+                    pack.uuid()
+                );
+            }
             None => {
                 warn!(
                     log,
@@ -223,22 +889,208 @@ fn process_pack(pack: &Pack, targets: &BTreeMap<PathBuf, TargetFile>, log: &Logg
             }
         }
     }
+
+    let queue = Mutex::new(placements.iter());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(placements.len()) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+
+                match next {
+                    Some((target, body)) => {
+                        systemd::ping_watchdog(log);
+                        let digest = place_file_if_updated(target, pack, body, stamps, log);
+
+                        if let (Some(status), Some(digest)) = (status, digest) {
+                            status.record(target, pack, &digest);
+                        }
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+}
+
+/// Compose the files made of fragments from any of the given (just placed)
+/// packs, placing them if they've changed. Files are only composed once every
+/// one of their fragments has been received, and are placed as if they came
+/// from the newest pack they contain a fragment of. Returns the paths of the
+/// files which were composed.
+fn place_composed_files(
+    updated_packs: &[String],
+    placed_packs: &BTreeMap<String, Pack>,
+    targets: &BTreeMap<PathBuf, TargetFile>,
+    stamps: Option<&FileStamps>,
+    status: Option<&Status>,
+    log: &Logger,
+) -> Vec<PathBuf> {
+    let mut composed = vec![];
+
+    'targets: for target in targets.values() {
+        systemd::ping_watchdog(log);
+
+        if !target
+            .fragments
+            .iter()
+            .any(|fragment| updated_packs.contains(&fragment.pack))
+        {
+            continue;
+        }
+
+        let mut body = vec![];
+        let mut newest_pack: Option<&Pack> = None;
+
+        for fragment in &target.fragments {
+            let pack = match placed_packs.get(&fragment.pack) {
+                Some(pack) => pack,
+                None => {
+                    debug!(
+                        log,
+                        "Not composing {} yet: no \"{}\" pack received",
+                        target.path.to_string_lossy(),
+                        fragment.pack
+                    );
+                    continue 'targets;
+                }
+            };
+
+            let file = match pack
+                .files()
+                .find(|file| Path::new(&file.filename) == fragment.file)
+            {
+                Some(file) => file,
+                None => {
+                    error!(
+                        log,
+                        "couldn't compose {}: no {} in {}:{}",
+                        target.path.to_string_lossy(),
+                        fragment.file.to_string_lossy(),
+                        pack.name,
+                        pack.uuid()
+                    );
+                    continue 'targets;
+                }
+            };
+
+            if !target.accepts_content_type(&file.content_type) {
+                error!(
+                    log,
+                    "refusing to compose {}: Content-Type mismatch (expected \"{}\", \
+                     got \"{}\" for {} from {}:{})",
+                    target.path.to_string_lossy(),
+                    target.content_type.as_ref().unwrap(),
+                    file.content_type,
+                    fragment.file.to_string_lossy(),
+                    pack.name,
+                    pack.uuid()
+                );
+                continue 'targets;
+            }
+
+            body.extend_from_slice(&file.body);
+
+            if newest_pack.map_or(true, |newest| pack.date() > newest.date()) {
+                newest_pack = Some(pack);
+            }
+        }
+
+        let pack = newest_pack.unwrap();
+        let digest = place_file_if_updated(target, pack, &body, stamps, log);
+
+        if let (Some(status), Some(digest)) = (status, digest) {
+            status.record(target, pack, &digest);
+        }
+
+        // Don't leave a copy of the composed secret in memory
+        if target.secret {
+            body.as_mut_slice().clear();
+        }
+
+        composed.push(target.path.clone());
+    }
+
+    composed
+}
+
+/// Find the target for a file, either by its exact path or a glob pattern
+/// (preferring patterns configured for the given pack)
+fn find_target<'a>(
+    targets: &'a BTreeMap<PathBuf, TargetFile>,
+    pack_name: &str,
+    path: &Path,
+) -> Option<Cow<'a, TargetFile>> {
+    if let Some(target) = targets.get(path).filter(|target| target.pattern.is_none()) {
+        return Some(Cow::Borrowed(target));
+    }
+
+    targets
+        .values()
+        .find(|target| target.pack == pack_name && target.matches(path))
+        .or_else(|| targets.values().find(|target| target.matches(path)))
+        .map(|target| Cow::Owned(target.with_path(path)))
 }
 
-/// Place the file on disk, but only if it's changed
-fn place_file_if_updated(target: &TargetFile, pack: &Pack, body: &[u8], log: &Logger) {
+/// Place the file on disk, but only if it's changed, returning the digest
+/// of its contents if it's now in place (i.e. placed, and not staged, or
+/// already up-to-date)
+fn place_file_if_updated(
+    target: &TargetFile,
+    pack: &Pack,
+    body: &[u8],
+    stamps: Option<&FileStamps>,
+    log: &Logger,
+) -> Option<Digest> {
+    let updated_file_digest = Digest::for_bytes(body);
+
+    // If enabled, skip reading the current file if its size and mtime haven't
+    // changed since we last placed (or compared) it with the same contents
+    if let Some(stamps) = stamps {
+        if stamps.probably_unchanged(&target.path, &updated_file_digest) {
+            debug!(
+                log,
+                "Not updating {}: size and mtime unchanged since matching {}:{}",
+                target.path.to_string_lossy(),
+                pack.name,
+                pack.uuid()
+            );
+
+            return Some(updated_file_digest);
+        }
+    }
+
     // Compare SHA-256 of current file versus the updated version
     // TODO: active file integrity monitoring
-    if let Ok(mut file) = File::open(&target.path) {
-        let mut data = vec![];
+    let current_file = match target.open_current() {
+        Ok(file) => file,
+        Err(e) => {
+            error!(
+                log,
+                "couldn't place {}: {}",
+                target.path.to_string_lossy(),
+                e
+            );
+            return None;
+        }
+    };
+
+    if let Some(mut file) = current_file {
+        // Read at most one byte more than the updated file into a buffer
+        // which is never reallocated (which would leave unwiped copies of
+        // secrets behind): anything longer can't be identical anyway
+        let mut data = vec![0u8; body.len() + 1];
 
-        match file.read_to_end(&mut data) {
-            Ok(_) => {
-                let current_file_digest = Digest::for_bytes(&data);
-                let updated_file_digest = Digest::for_bytes(body);
+        match read_up_to(&mut file, &mut data) {
+            Ok(len) => {
+                let current_file_digest = Digest::for_bytes(&data[..len]);
 
                 // Do nothing if the file is already up-to-date
                 if current_file_digest == updated_file_digest {
+                    if let (Some(stamps), Ok(metadata)) = (stamps, file.metadata()) {
+                        stamps.record(&target.path, &metadata, updated_file_digest.clone());
+                    }
+
                     debug!(
                         log,
                         "Not updating {}: already identical to {}:{}",
@@ -246,7 +1098,11 @@ fn place_file_if_updated(target: &TargetFile, pack: &Pack, body: &[u8], log: &Lo
                         pack.name,
                         pack.uuid()
                     );
-                    return;
+                    if target.secret {
+                        data.as_mut_slice().clear();
+                    }
+
+                    return Some(updated_file_digest);
                 }
             }
             Err(e) => error!(
@@ -256,6 +1112,36 @@ fn place_file_if_updated(target: &TargetFile, pack: &Pack, body: &[u8], log: &Lo
                 e
             ),
         }
+
+        // Don't leave a copy of the secret's previous contents in memory
+        if target.secret {
+            data.as_mut_slice().clear();
+        }
+    }
+
+    // Files whose preconditions don't hold on this host are skipped (which
+    // isn't an error)
+    match target.check_preconditions(log) {
+        Ok(true) => (),
+        Ok(false) => {
+            info!(
+                log,
+                "Not placing {}: not applicable to this host (from {}:{})",
+                target.path.to_string_lossy(),
+                pack.name,
+                pack.uuid()
+            );
+            return None;
+        }
+        Err(e) => {
+            error!(
+                log,
+                "couldn't place {}: {}",
+                target.path.to_string_lossy(),
+                e
+            );
+            return None;
+        }
     }
 
     if let Err(e) = target.place(body, log) {
@@ -265,7 +1151,20 @@ fn place_file_if_updated(target: &TargetFile, pack: &Pack, body: &[u8], log: &Lo
             target.path.to_string_lossy(),
             e
         );
-        return;
+
+        if let Some(stamps) = stamps {
+            stamps.forget(&target.path);
+        }
+
+        return None;
+    }
+
+    // Staged files aren't placed at their real paths, so there's nothing to record
+    if let Some(stamps) = stamps.filter(|_| target.staging_root.is_none()) {
+        match fs::symlink_metadata(&target.path) {
+            Ok(metadata) => stamps.record(&target.path, &metadata, updated_file_digest.clone()),
+            Err(_) => stamps.forget(&target.path),
+        }
     }
 
     info!(
@@ -275,4 +1174,262 @@ fn place_file_if_updated(target: &TargetFile, pack: &Pack, body: &[u8], log: &Lo
         pack.name,
         pack.uuid()
     );
+
+    if target.staging_root.is_none() {
+        Some(updated_file_digest)
+    } else {
+        None
+    }
+}
+
+/// Read from a file until the buffer is full or the end of the file is
+/// reached, returning the number of bytes read
+fn read_up_to(file: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, place_composed_files, read_up_to};
+    use crate::digest::Digest;
+    use crate::pack::Pack;
+    use crate::target_file::{Fragment, TargetFile};
+    use chrono::{TimeZone, Utc};
+    use placer_pack::{Pack as PackContents, PackFile};
+    use slog::{Discard, Logger};
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use uuid::Uuid;
+
+    /// Create an empty directory for a test's target files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("placer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Placed pack containing the given files, published at the given time
+    fn example_pack(name: &str, timestamp: i64, files: &[(&str, &[u8])]) -> (String, Pack) {
+        let contents = PackContents {
+            uuid: Uuid::from_u128(timestamp as u128),
+            date: Utc.timestamp(timestamp, 0),
+            fingerprints: None,
+            base_uuid: None,
+            deletions: vec![],
+            description: None,
+            channel: None,
+            files: files
+                .iter()
+                .map(|(filename, body)| PackFile {
+                    filename: (*filename).to_owned(),
+                    content_type: String::new(),
+                    modified_at: None,
+                    body: body.to_vec(),
+                })
+                .collect(),
+            chunked_files: vec![],
+            limits: Default::default(),
+        };
+
+        let pack = Pack {
+            name: name.to_owned(),
+            contents,
+            digest: Digest::for_bytes(name.as_bytes()),
+        };
+
+        (name.to_owned(), pack)
+    }
+
+    /// Target composed of the given fragments (pack name and file)
+    fn composed_target(path: &Path, fragments: &[(&str, &str)]) -> TargetFile {
+        TargetFile {
+            path: path.to_owned(),
+            pattern: None,
+            pack: String::new(),
+            fragments: fragments
+                .iter()
+                .map(|(pack, file)| Fragment {
+                    pack: (*pack).to_owned(),
+                    file: PathBuf::from(file),
+                })
+                .collect(),
+            uid: users::get_current_uid(),
+            gid: users::get_current_gid(),
+            permissions: Permissions::from_mode(0o600),
+            precondition_hooks: vec![],
+            before_hooks: vec![],
+            after_hooks: vec![],
+            parent_dirs: None,
+            content_type: None,
+            fsync: false,
+            allow_special_file: false,
+            secret: false,
+            staging_root: None,
+        }
+    }
+
+    /// Compose the given target from the given packs, returning the paths of
+    /// the files which were composed
+    fn compose(target: TargetFile, packs: Vec<(String, Pack)>) -> Vec<PathBuf> {
+        let log = Logger::root(Discard, o!());
+        let updated_packs: Vec<String> = packs.iter().map(|(name, _)| name.clone()).collect();
+        let placed_packs: BTreeMap<String, Pack> = packs.into_iter().collect();
+
+        let mut targets = BTreeMap::new();
+        targets.insert(target.path.clone(), target);
+
+        place_composed_files(&updated_packs, &placed_packs, &targets, None, None, &log)
+    }
+
+    #[test]
+    fn test_read_up_to() {
+        let mut buf = [0u8; 4];
+        assert_eq!(read_up_to(&mut &b"ab"[..], &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+
+        assert_eq!(read_up_to(&mut &b"abcdef"[..], &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let log = Logger::root(Discard, o!());
+        let (_, base) = example_pack(
+            "base",
+            1_500_000_000,
+            &[("/etc/a", b"a\n"), ("/etc/b", b"b\n"), ("/etc/c", b"c\n")],
+        );
+        let (_, update) = example_pack(
+            "base",
+            1_500_000_001,
+            &[
+                ("/etc/a", b"a\n"),
+                ("/etc/b", b"new b\n"),
+                ("/etc/d", b"d\n"),
+            ],
+        );
+
+        let delta = Pack {
+            contents: update.contents.into_delta(&base.contents),
+            ..update
+        };
+        let delta_uuid = *delta.contents.uuid();
+        let delta_digest = delta.digest.clone();
+
+        let mut placed_packs = BTreeMap::new();
+        placed_packs.insert("base".to_owned(), base);
+
+        let applied = apply_delta(delta, &placed_packs, &log).unwrap();
+        assert!(!applied.contents.is_delta());
+        assert_eq!(applied.contents.uuid(), &delta_uuid);
+        assert_eq!(applied.digest, delta_digest);
+
+        let mut files: Vec<_> = applied
+            .contents
+            .files()
+            .map(|file| (file.filename.as_str(), file.body.as_slice()))
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("/etc/a", &b"a\n"[..]),
+                ("/etc/b", &b"new b\n"[..]),
+                ("/etc/d", &b"d\n"[..])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_delta_missing_base() {
+        let log = Logger::root(Discard, o!());
+        let (_, base) = example_pack("base", 1_500_000_000, &[("/etc/a", b"a\n")]);
+        let delta = || {
+            let (_, update) = example_pack("base", 1_500_000_001, &[("/etc/a", b"new a\n")]);
+            Pack {
+                contents: update.contents.into_delta(&base.contents),
+                ..update
+            }
+        };
+
+        // No pack with this name has been placed yet
+        assert!(apply_delta(delta(), &BTreeMap::new(), &log).is_none());
+
+        // A different pack with this name was placed since the delta's base
+        let mut placed_packs = BTreeMap::new();
+        let (_, other) = example_pack("base", 1_400_000_000, &[("/etc/a", b"other\n")]);
+        placed_packs.insert("base".to_owned(), other);
+        assert!(apply_delta(delta(), &placed_packs, &log).is_none());
+    }
+
+    #[test]
+    fn test_fragment_order() {
+        let dir = test_dir("compose-order");
+        let path = dir.join("hosts");
+
+        // Fragments are concatenated in the configured order (not by pack
+        // name or date)
+        let target = composed_target(&path, &[("site", "/hosts.site"), ("base", "/hosts.base")]);
+        let packs = vec![
+            example_pack("base", 1_500_000_000, &[("/hosts.base", b"base\n")]),
+            example_pack("site", 1_400_000_000, &[("/hosts.site", b"site\n")]),
+        ];
+
+        assert_eq!(compose(target, packs), vec![path.clone()]);
+        assert_eq!(fs::read(&path).unwrap(), b"site\nbase\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_fragment_pack() {
+        let dir = test_dir("compose-missing-pack");
+        let path = dir.join("hosts");
+        fs::write(&path, b"original\n").unwrap();
+
+        let target = composed_target(&path, &[("base", "/hosts.base"), ("site", "/hosts.site")]);
+        let packs = vec![example_pack(
+            "base",
+            1_500_000_000,
+            &[("/hosts.base", b"base\n")],
+        )];
+
+        assert!(compose(target, packs).is_empty());
+        assert_eq!(fs::read(&path).unwrap(), b"original\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_fragment_file() {
+        let dir = test_dir("compose-missing-file");
+        let path = dir.join("hosts");
+        fs::write(&path, b"original\n").unwrap();
+
+        let target = composed_target(&path, &[("base", "/hosts.base"), ("site", "/hosts.site")]);
+        let packs = vec![
+            example_pack("base", 1_500_000_000, &[("/hosts.base", b"base\n")]),
+            example_pack("site", 1_500_000_001, &[("/other", b"other\n")]),
+        ];
+
+        assert!(compose(target, packs).is_empty());
+        assert_eq!(fs::read(&path).unwrap(), b"original\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
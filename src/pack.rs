@@ -1,14 +1,30 @@
 //! Encrypted/signed packs of files
 
 use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::Permissions;
+use std::io::Write;
+use std::path::PathBuf;
 use std::slice::Iter;
+use users::{gid_t, uid_t};
 
-use crate::keyrings::Keyrings;
+use crate::digest::Digest;
+use crate::error::Error;
+use crate::keyrings::{Keyrings, TrustStore};
+use crate::platform;
+use placer_pack::crypto::{Encryptor, PublicKey};
+use placer_pack::error::Error as PackError;
 use placer_pack::Pack as PackContents;
-use placer_pack::PackFile;
+use placer_pack::{Fingerprints, KeyLookup, PackFile};
 use slog::Logger;
 use uuid::Uuid;
 
+/// Number of recently processed packs to remember for deduplication
+pub const RECENT_PACKS_CAPACITY: usize = 64;
+
+/// Once a pack is failing persistently, only every Nth failure is logged
+pub const PERSISTENT_FAILURE_LOG_INTERVAL: u32 = 100;
+
 /// Decrypted/verified pack including metadata about it
 pub struct Pack {
     /// Name of this pack
@@ -16,6 +32,10 @@ pub struct Pack {
 
     /// Contents of this pack
     pub contents: PackContents,
+
+    /// Digest of the pack as received from its source (i.e. of the delta
+    /// itself for delta packs)
+    pub digest: Digest,
 }
 
 impl Pack {
@@ -25,51 +45,142 @@ impl Pack {
         data: &[u8],
         keyrings: &Keyrings,
         log: &Logger,
-    ) -> Option<Self> {
-        let contents_result = PackContents::verify_and_decrypt(data, |fingerprints, uuid| {
-            match keyrings.get_for_fingerprints(fingerprints, uuid) {
-                Ok(result) => Some(result),
-                Err(e) => {
-                    warn!(log, "missing keys for \"{}\" pack: {}", name, e);
-                    None
-                }
+    ) -> Result<Self, RejectionReason> {
+        let first_use_key = match keyrings.trust_store {
+            Some(ref trust_store) => Self::first_use_key(name, data, keyrings, trust_store, log)?,
+            None => None,
+        };
+
+        let key_lookup = KeyringLookup {
+            keyrings,
+            first_use_key: first_use_key.as_ref().map(|(key, _)| key),
+        };
+
+        let contents_result = match keyrings.signature_policy {
+            Some(ref policy) => {
+                PackContents::verify_and_decrypt_with_policy(data, &key_lookup, policy)
             }
-        });
+            None => PackContents::verify_and_decrypt_with(data, &key_lookup),
+        };
 
         match contents_result {
             Ok(contents) => {
+                if let (Some((key, false)), Some(trust_store)) =
+                    (&first_use_key, &keyrings.trust_store)
+                {
+                    if let Err(e) = trust_store.record(name, key) {
+                        error!(
+                            log,
+                            "couldn't record signing key for \"{}\" pack: {}", name, e
+                        );
+                        return Err(RejectionReason::Other);
+                    }
+
+                    warn!(
+                        log,
+                        "TRUSTING NEW SIGNING KEY for \"{}\" pack ON FIRST USE: {} \
+                         (it will be required for all future packs)",
+                        name,
+                        key.to_fingerprint()
+                    );
+                }
+
                 let pack = Self {
                     name: name.to_owned(),
                     contents,
+                    digest: Digest::for_bytes(data),
                 };
 
+                // Labels of the keys the pack was actually verified and
+                // decrypted with (which may not be the last ones looked up)
+                let labels = pack
+                    .contents
+                    .fingerprints
+                    .as_ref()
+                    .map(|fingerprints| keyrings.key_labels(fingerprints, key_lookup.first_use_key))
+                    .unwrap_or_default();
+                let signing_key = labels
+                    .signing
+                    .as_ref()
+                    .map_or("(trusted on first use)", String::as_str);
+                let encryption_key = labels
+                    .encryption
+                    .as_ref()
+                    .map_or("(unknown)", String::as_str);
+
                 info!(
                     log,
-                    "Verified pack {}:{} ({})",
+                    "Verified pack {}:{} ({}) signed by {}, encrypted to {}",
                     pack.name,
                     pack.uuid(),
-                    pack.date().format("%a %b %e %T %Y")
+                    pack.date().format("%a %b %e %T %Y"),
+                    signing_key,
+                    encryption_key;
+                    "signing_key" => signing_key,
+                    "encryption_key" => encryption_key
                 );
 
-                Some(pack)
+                if let Some(ref description) = pack.contents.description {
+                    info!(
+                        log,
+                        "Pack {}:{} description: {:?}",
+                        pack.name,
+                        pack.uuid(),
+                        description
+                    );
+                }
+
+                Ok(pack)
             }
             Err(e) => {
-                error!(log, "bad \"{}\" pack: {}", name, e);
-                None
+                let reason = RejectionReason::from(&e);
+                error!(log, "bad \"{}\" pack: {}", name, e; "reason" => reason.as_str());
+                Err(reason)
             }
         }
     }
 
-    /// Get the date when this crate was published
+    /// If the pack is signed by a key which isn't in the signing keyring, get
+    /// the signing key embedded in it, along with whether the trust store
+    /// already trusts it (`false` means this is the first time we've seen it).
+    /// Rejects the pack if the trust store has a different key for it.
+    fn first_use_key(
+        name: &str,
+        data: &[u8],
+        keyrings: &Keyrings,
+        trust_store: &TrustStore,
+        log: &Logger,
+    ) -> Result<Option<(PublicKey, bool)>, RejectionReason> {
+        let key = match PackContents::unverified_signing_key(data) {
+            Ok(Some(key)) => key,
+            // Malformed packs are reported by `verify_and_decrypt`
+            Ok(None) | Err(_) => return Ok(None),
+        };
+
+        if keyrings.signing.get(&key.to_fingerprint()).is_ok() {
+            return Ok(None);
+        }
+
+        match trust_store.check(name, &key) {
+            Ok(trusted) => Ok(Some((key, trusted))),
+            Err(e) => {
+                let reason = RejectionReason::SigningKeyChanged;
+                error!(log, "bad \"{}\" pack: {}", name, e; "reason" => reason.as_str());
+                Err(reason)
+            }
+        }
+    }
+
+    /// Get the date when this pack was published
     #[inline]
     pub fn date(&self) -> &DateTime<Utc> {
-        &self.contents.date
+        self.contents.date()
     }
 
     /// Get the UUID for this pack
     #[inline]
     pub fn uuid(&self) -> &Uuid {
-        &self.contents.uuid
+        self.contents.uuid()
     }
 
     /// Iterate over the files in this pack
@@ -77,4 +188,415 @@ impl Pack {
     pub fn files(&self) -> Iter<'_, PackFile> {
         self.contents.files()
     }
+
+    /// Check that this pack isn't empty, since an empty pack usually means it
+    /// was built by mistake. Empty packs are logged, and rejected if `reject`
+    /// is set (i.e. in strict mode).
+    pub fn check_not_empty(&self, reject: bool, log: &Logger) -> Result<(), RejectionReason> {
+        if !self.contents.is_empty() {
+            return Ok(());
+        }
+
+        if reject {
+            let reason = RejectionReason::EmptyPack;
+            error!(
+                log,
+                "bad \"{}\" pack: {} is empty",
+                self.name,
+                self.uuid();
+                "reason" => reason.as_str()
+            );
+            return Err(reason);
+        }
+
+        warn!(
+            log,
+            "\"{}\" pack {} is empty (no files to place)",
+            self.name,
+            self.uuid()
+        );
+        Ok(())
+    }
+
+    /// Check that this pack is for one of the given channels (i.e.
+    /// environments). Any pack is accepted if no channels are given, but
+    /// otherwise packs without a channel are rejected too.
+    pub fn check_channel(&self, channels: &[String], log: &Logger) -> Result<(), RejectionReason> {
+        if channels.is_empty() {
+            return Ok(());
+        }
+
+        let channel = self.contents.channel.as_ref();
+
+        if channel.map_or(false, |channel| channels.contains(channel)) {
+            return Ok(());
+        }
+
+        let reason = RejectionReason::WrongChannel;
+        error!(
+            log,
+            "bad \"{}\" pack: {} is for {} (accepted channels: {})",
+            self.name,
+            self.uuid(),
+            channel.map_or_else(|| "no channel".to_owned(), |channel| format!("{:?} channel", channel)),
+            channels.join(", ");
+            "reason" => reason.as_str()
+        );
+        Err(reason)
+    }
+}
+
+/// Looks up pack keys in the keyrings, falling back to a signing key being
+/// trusted on first use
+struct KeyringLookup<'a> {
+    /// Keyrings to look up keys in
+    keyrings: &'a Keyrings,
+
+    /// Signing key embedded in the pack, if it's being trusted on first use
+    first_use_key: Option<&'a PublicKey>,
+}
+
+impl<'a> KeyLookup for KeyringLookup<'a> {
+    fn lookup(
+        &self,
+        fingerprints: &Fingerprints,
+        uuid: &Uuid,
+    ) -> Result<(PublicKey, Encryptor), PackError> {
+        self.keyrings
+            .get_for_fingerprints(fingerprints, uuid, self.first_use_key)
+            .map_err(|e| PackError::InvalidKey {
+                description: format!("missing keys: {}", e),
+            })
+    }
+}
+
+/// Machine-readable reasons why a pack was rejected, for monitoring
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum RejectionReason {
+    /// Signature verification failed (possible attack)
+    BadSignature,
+
+    /// Pack was encrypted or signed with a key we don't have (config drift)
+    UnknownKey,
+
+    /// Pack was signed by a different key than the one trusted on first use
+    SigningKeyChanged,
+
+    /// Pack has a timestamp too far in the future
+    FutureTimestamp,
+
+    /// Pack couldn't be decrypted
+    DecryptionFailed,
+
+    /// Pack is malformed or too large
+    Malformed,
+
+    /// Pack contains no files (rejected in strict mode)
+    EmptyPack,
+
+    /// Pack is for a channel (i.e. environment) we don't accept
+    WrongChannel,
+
+    /// Any other error
+    Other,
+}
+
+impl RejectionReason {
+    /// Get a stable string identifier for this reason
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectionReason::BadSignature => "bad_signature",
+            RejectionReason::UnknownKey => "unknown_key",
+            RejectionReason::SigningKeyChanged => "signing_key_changed",
+            RejectionReason::FutureTimestamp => "future_timestamp",
+            RejectionReason::DecryptionFailed => "decryption_failed",
+            RejectionReason::Malformed => "malformed",
+            RejectionReason::EmptyPack => "empty_pack",
+            RejectionReason::WrongChannel => "wrong_channel",
+            RejectionReason::Other => "other",
+        }
+    }
+}
+
+impl<'a> From<&'a PackError> for RejectionReason {
+    fn from(error: &'a PackError) -> Self {
+        match error {
+            PackError::Signature { .. } => RejectionReason::BadSignature,
+            PackError::InvalidKey { .. } => RejectionReason::UnknownKey,
+            PackError::Timestamp { .. } => RejectionReason::FutureTimestamp,
+            PackError::Crypto { .. } => RejectionReason::DecryptionFailed,
+            PackError::Parse { .. }
+            | PackError::Serialization { .. }
+            | PackError::TooManyFiles { .. }
+            | PackError::FileTooLarge { .. } => RejectionReason::Malformed,
+            _ => RejectionReason::Other,
+        }
+    }
+}
+
+/// Digests of recently processed packs, used to skip packs we've already
+/// seen (e.g. the same pack served by both a primary source and a mirror)
+#[derive(Default)]
+pub struct RecentPacks(VecDeque<Digest>);
+
+impl RecentPacks {
+    /// Has a pack with the given digest been processed recently?
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.0.contains(digest)
+    }
+
+    /// Record the digest of a pack, returning `false` if it was already seen
+    pub fn insert(&mut self, digest: Digest) -> bool {
+        if self.0.contains(&digest) {
+            return false;
+        }
+
+        if self.0.len() == RECENT_PACKS_CAPACITY {
+            self.0.pop_front();
+        }
+
+        self.0.push_back(digest);
+        true
+    }
+}
+
+/// Tracks consecutive verification failures of each pack, so persistently
+/// failing packs can be logged less noisily (and dead-lettered)
+pub struct FailureTracker {
+    /// Consecutive failures after which a pack is failing persistently
+    threshold: Option<u32>,
+
+    /// Where to write persistently failing packs (if enabled)
+    dead_letters: Option<DeadLetters>,
+
+    /// Consecutive failures by pack name
+    failures: BTreeMap<String, u32>,
+}
+
+impl FailureTracker {
+    /// Create a new failure tracker
+    pub fn new(threshold: Option<u32>, dead_letters: Option<DeadLetters>) -> Self {
+        Self {
+            threshold,
+            dead_letters,
+            failures: BTreeMap::new(),
+        }
+    }
+
+    /// Should the next failure of the given pack be logged quietly? True
+    /// once it's failed `threshold` times in a row, except for every
+    /// `PERSISTENT_FAILURE_LOG_INTERVAL`th failure after that.
+    pub fn is_quiet(&self, pack_name: &str) -> bool {
+        match (self.threshold, self.failures.get(pack_name)) {
+            (Some(threshold), Some(&count)) if count >= threshold => {
+                (count + 1 - threshold) % PERSISTENT_FAILURE_LOG_INTERVAL != 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Record a failure of the given pack, returning how many times in a row
+    /// it's failed. Writes a dead letter when it reaches the threshold.
+    pub fn record_failure(&mut self, pack_name: &str, data: &[u8], log: &Logger) -> u32 {
+        let count = self.failures.entry(pack_name.to_owned()).or_insert(0);
+        *count += 1;
+        let count = *count;
+
+        if Some(count) != self.threshold {
+            return count;
+        }
+
+        warn!(
+            log,
+            "\"{}\" pack has failed {} times in a row: only logging every {} failures",
+            pack_name,
+            count,
+            PERSISTENT_FAILURE_LOG_INTERVAL
+        );
+
+        if let Some(ref dead_letters) = self.dead_letters {
+            match dead_letters.write(pack_name, data) {
+                Ok(path) => warn!(
+                    log,
+                    "wrote \"{}\" pack to dead letter: {}",
+                    pack_name,
+                    path.display()
+                ),
+                Err(e) => error!(
+                    log,
+                    "couldn't write dead letter for \"{}\" pack: {}", pack_name, e
+                ),
+            }
+        }
+
+        count
+    }
+
+    /// Record that the given pack was verified, returning how many times in
+    /// a row it had failed beforehand
+    pub fn record_success(&mut self, pack_name: &str) -> u32 {
+        self.failures.remove(pack_name).unwrap_or(0)
+    }
+}
+
+/// Directory where packs which fail persistently are written for analysis
+#[derive(Clone, Debug)]
+pub struct DeadLetters {
+    /// Directory to write dead letters to (i.e. the quarantine directory)
+    pub dir: PathBuf,
+
+    /// Permissions of dead letter files
+    pub permissions: Permissions,
+
+    /// POSIX user ID which owns dead letter files
+    pub uid: uid_t,
+
+    /// POSIX group ID which owns dead letter files
+    pub gid: gid_t,
+}
+
+impl DeadLetters {
+    /// Write the raw bytes of a pack to a new dead letter file
+    pub fn write(&self, pack_name: &str, data: &[u8]) -> Result<PathBuf, Error> {
+        let path = self.dir.join(format!(
+            "{}.{}.dead-letter",
+            pack_name.replace('/', "_"),
+            Utc::now().timestamp()
+        ));
+
+        platform::create_new(&path, &self.permissions)?.write_all(data)?;
+        platform::set_owner(&path, self.uid, self.gid)?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FailureTracker, Pack, PackContents, RecentPacks, RejectionReason,
+        PERSISTENT_FAILURE_LOG_INTERVAL, RECENT_PACKS_CAPACITY,
+    };
+    use crate::digest::Digest;
+    use chrono::Utc;
+    use placer_pack::error::Error as PackError;
+    use placer_pack::Limits;
+    use slog::{Discard, Logger};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_recent_packs() {
+        let mut recent_packs = RecentPacks::default();
+        assert!(!recent_packs.contains(&Digest::for_bytes(b"pack 0")));
+        assert!(recent_packs.insert(Digest::for_bytes(b"pack 0")));
+        assert!(recent_packs.contains(&Digest::for_bytes(b"pack 0")));
+        assert!(!recent_packs.insert(Digest::for_bytes(b"pack 0")));
+
+        // Evicts the oldest digest once full
+        for i in 1..=RECENT_PACKS_CAPACITY {
+            assert!(recent_packs.insert(Digest::for_bytes(format!("pack {}", i).as_bytes())));
+        }
+
+        assert!(recent_packs.insert(Digest::for_bytes(b"pack 0")));
+    }
+
+    #[test]
+    fn test_failure_tracker() {
+        let log = Logger::root(Discard, o!());
+        let mut tracker = FailureTracker::new(Some(2), None);
+
+        assert!(!tracker.is_quiet("base"));
+        assert_eq!(tracker.record_failure("base", b"", &log), 1);
+        assert!(!tracker.is_quiet("base"));
+        assert_eq!(tracker.record_failure("base", b"", &log), 2);
+        assert!(tracker.is_quiet("base"));
+
+        // Persistent failures are still logged periodically
+        for _ in 0..PERSISTENT_FAILURE_LOG_INTERVAL - 1 {
+            tracker.record_failure("base", b"", &log);
+        }
+        assert!(!tracker.is_quiet("base"));
+
+        assert_eq!(
+            tracker.record_success("base"),
+            PERSISTENT_FAILURE_LOG_INTERVAL + 1
+        );
+        assert!(!tracker.is_quiet("base"));
+    }
+
+    #[test]
+    fn test_check_not_empty() {
+        let pack = Pack {
+            name: "example".to_owned(),
+            contents: PackContents {
+                uuid: Uuid::nil(),
+                date: Utc::now(),
+                fingerprints: None,
+                base_uuid: None,
+                deletions: vec![],
+                description: None,
+                channel: None,
+                files: vec![],
+                chunked_files: vec![],
+                limits: Limits::default(),
+            },
+            digest: Digest::for_bytes(b"example"),
+        };
+
+        let log = Logger::root(Discard, o!());
+        assert!(pack.check_not_empty(false, &log).is_ok());
+        assert_eq!(
+            pack.check_not_empty(true, &log),
+            Err(RejectionReason::EmptyPack)
+        );
+    }
+
+    #[test]
+    fn test_check_channel() {
+        let mut pack = Pack {
+            name: "example".to_owned(),
+            contents: PackContents {
+                uuid: Uuid::nil(),
+                date: Utc::now(),
+                fingerprints: None,
+                base_uuid: None,
+                deletions: vec![],
+                description: None,
+                channel: None,
+                files: vec![],
+                chunked_files: vec![],
+                limits: Limits::default(),
+            },
+            digest: Digest::for_bytes(b"example"),
+        };
+
+        let log = Logger::root(Discard, o!());
+        let production = vec!["production".to_owned()];
+        assert!(pack.check_channel(&[], &log).is_ok());
+        assert_eq!(
+            pack.check_channel(&production, &log),
+            Err(RejectionReason::WrongChannel)
+        );
+
+        pack.contents.channel = Some("staging".to_owned());
+        assert!(pack.check_channel(&[], &log).is_ok());
+        assert_eq!(
+            pack.check_channel(&production, &log),
+            Err(RejectionReason::WrongChannel)
+        );
+
+        pack.contents.channel = Some("production".to_owned());
+        assert!(pack.check_channel(&production, &log).is_ok());
+    }
+
+    #[test]
+    fn test_rejection_reason() {
+        let error = PackError::Signature {
+            description: "signature verification failed!".to_owned(),
+        };
+
+        let reason = RejectionReason::from(&error);
+        assert_eq!(reason, RejectionReason::BadSignature);
+        assert_eq!(reason.as_str(), "bad_signature");
+    }
 }
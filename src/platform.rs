@@ -0,0 +1,209 @@
+//! Platform-specific support for placing files: ownership, permissions,
+//! and refusing to follow symlinks
+//!
+//! placer only supports Unix-like platforms: the rest of the daemon also
+//! relies on Unix users and groups (e.g. to drop privileges for sources and
+//! hooks).
+
+use std::ffi::{CString, OsStr};
+use std::fs::{self, DirBuilder, File, OpenOptions, Permissions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Convert POSIX mode bits (e.g. from the config) into file permissions
+pub fn permissions_from_mode(mode: u32) -> Result<Permissions, Error> {
+    Ok(Permissions::from_mode(mode))
+}
+
+/// Create a new file for writing with the given permissions, failing if
+/// anything (including a symlink) already exists at the given path
+pub fn create_new(path: &Path, permissions: &Permissions) -> io::Result<File> {
+    // O_EXCL and O_NOFOLLOW ensure we never write through a symlink
+    OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(permissions.mode())
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Open an existing file for reading without following symlinks
+pub fn open_nofollow(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Did opening a file fail because it's a symlink?
+pub fn is_symlink_error(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ELOOP)
+}
+
+/// Create a directory with the given permissions
+pub fn create_dir(path: &Path, permissions: &Permissions) -> io::Result<()> {
+    DirBuilder::new().mode(permissions.mode()).create(path)?;
+
+    // Set permissions explicitly so they aren't affected by the umask
+    fs::set_permissions(path, permissions.clone())
+}
+
+/// Ensure a directory is owned by root or the given user, and isn't
+/// writable by anyone else
+pub fn ensure_trusted_dir(path: &Path, uid: u32) -> Result<(), Error> {
+    let metadata = fs::metadata(path)?;
+
+    ensure!(
+        metadata.uid() == 0 || metadata.uid() == uid,
+        Config,
+        "untrusted parent directory: {} (owned by uid {})",
+        path.to_string_lossy(),
+        metadata.uid()
+    );
+
+    ensure!(
+        metadata.mode() & 0o022 == 0,
+        Config,
+        "untrusted parent directory: {} (writable by group/others: {:o})",
+        path.to_string_lossy(),
+        metadata.mode() & 0o7777
+    );
+
+    Ok(())
+}
+
+/// Set the owner and group of a file or directory
+pub fn set_owner(path: &Path, uid: u32, gid: u32) -> Result<(), Error> {
+    chown(path, uid, gid)
+}
+
+/// Take an exclusive advisory lock on the given file (creating it if it
+/// doesn't exist), held until the returned file is closed. Returns `None`
+/// if another process (or another open handle) already holds the lock.
+/// The lock file can be safely removed while the lock is held: the lock
+/// is only taken on the file which is at the given path once locked.
+pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+    loop {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)?;
+
+        match flock_exclusive_nonblocking(&file) {
+            Ok(()) => (),
+            Err(ref e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        // If the previous holder removed the lock file before we locked
+        // it, we've locked a file nobody else will ever open: try again
+        let locked = file.metadata()?;
+
+        match fs::symlink_metadata(path) {
+            Ok(ref current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(Some(file))
+            }
+            Ok(_) => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Take an exclusive advisory lock on an existing file, which is only
+/// opened for reading (without following symlinks), so it's never
+/// created or modified. Returns `None` if the lock is already held.
+pub fn try_lock_existing(path: &Path) -> io::Result<Option<File>> {
+    let file = open_nofollow(path)?;
+
+    match flock_exclusive_nonblocking(&file) {
+        Ok(()) => Ok(Some(file)),
+        Err(ref e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Flush a directory's entries (e.g. a rename into it) to disk
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+/// "Safe" wrapper for flock(LOCK_EX | LOCK_NB)
+#[allow(unsafe_code)]
+fn flock_exclusive_nonblocking(file: &File) -> io::Result<()> {
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// "Safe" wrapper for chown
+// TODO: find (or create) a crate to supply this, or get stable Rust to
+#[allow(unsafe_code)]
+fn chown(path: &Path, uid: u32, gid: u32) -> Result<(), Error> {
+    let path_osstr: &OsStr = path.as_ref();
+    let path_cstring = CString::new(path_osstr.as_bytes()).unwrap();
+    let ret = unsafe { libc::chown(path_cstring.as_ptr(), uid, gid) };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(err!(Io, "chown failed: {}", errno::errno()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{try_lock, try_lock_existing};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_try_lock() {
+        let path = env::temp_dir().join(format!(".placer-lock-test-{}", std::process::id()));
+
+        let lock = try_lock(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(try_lock(&path).unwrap().is_none());
+
+        drop(lock);
+        let lock = try_lock(&path).unwrap();
+        assert!(lock.is_some());
+
+        // Removing the lock file while it's held doesn't break locking
+        fs::remove_file(&path).unwrap();
+        drop(lock);
+        assert!(try_lock(&path).unwrap().is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_try_lock_existing() {
+        let path = env::temp_dir().join(format!(".placer-config-lock-test-{}", std::process::id()));
+
+        // The file must already exist: it's never created
+        assert!(try_lock_existing(&path).is_err());
+
+        fs::write(&path, b"# config\n").unwrap();
+        let lock = try_lock_existing(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(try_lock_existing(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(try_lock_existing(&path).unwrap().is_some());
+        assert_eq!(fs::read(&path).unwrap(), b"# config\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! Signaling readiness once every configured target has been satisfied
+
+use slog::Logger;
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::ReadinessConfig;
+use crate::error::Error;
+use crate::systemd;
+
+/// How often to check whether every target has been satisfied
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Remove any readiness file left over from a previous run, then wait (in
+/// the background) for every target to be satisfied or the timeout to
+/// elapse, and signal readiness (creating the readiness file, if configured,
+/// and notifying systemd)
+pub fn spawn_readiness_watcher(
+    config: &ReadinessConfig,
+    targets: Vec<PathBuf>,
+    satisfied_targets: Arc<Mutex<BTreeSet<PathBuf>>>,
+    log: Logger,
+) -> Result<(), Error> {
+    if let Some(ref file) = config.file {
+        match fs::remove_file(file) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => fail!(
+                Config,
+                "couldn't remove stale readiness file {}: {}",
+                file.display(),
+                e
+            ),
+        }
+    }
+
+    let timeout = Duration::from_secs(config.timeout);
+    let file = config.file.clone();
+
+    thread::spawn(move || {
+        let started_at = Instant::now();
+
+        loop {
+            let unsatisfied = {
+                let satisfied_targets = satisfied_targets.lock().unwrap();
+                targets
+                    .iter()
+                    .filter(|path| !satisfied_targets.contains(*path))
+                    .count()
+            };
+
+            if unsatisfied == 0 {
+                info!(log, "[readiness] All configured targets satisfied");
+                break;
+            }
+
+            if started_at.elapsed() >= timeout {
+                warn!(
+                    log,
+                    "[readiness] {} target(s) unsatisfied after {} seconds; ready anyway",
+                    unsatisfied,
+                    timeout.as_secs()
+                );
+                break;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        if let Some(ref file) = file {
+            match touch(file) {
+                Ok(()) => info!(log, "[readiness] Created {}", file.display()),
+                Err(e) => error!(log, "[readiness] couldn't create {}: {}", file.display(), e),
+            }
+        }
+
+        systemd::notify_ready(&log);
+    });
+
+    Ok(())
+}
+
+/// Create an empty file (if it doesn't already exist)
+fn touch(path: &Path) -> io::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(path)
+        .map(|_| ())
+}
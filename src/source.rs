@@ -4,10 +4,14 @@
 //! and digitally signed to ensure authenticity.
 
 use placer_pack::MAX_PACK_SIZE;
-use std::collections::BTreeMap;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::os::unix::process::CommandExt;
+use slog::Logger;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
 use users::{Groups, Users, UsersCache};
 
 use super::PLACER_PATH;
@@ -20,6 +24,49 @@ pub const PLACER_SOURCE_PREFIX: &str = "placer-source-";
 /// Greetings from sources start with this string
 pub const GREETING_PREFIX: &str = "OK ";
 
+/// Acknowledgements of requested resources start with this string
+pub const ACK_PREFIX: &str = "ACK ";
+
+/// Word at the end of a greeting which indicates the source acknowledges the
+/// resources requested from it. Sources which don't send it aren't expected
+/// to send acknowledgements, and are assumed to fetch every resource.
+pub const ACK_CAPABILITY: &str = "+ack";
+
+/// Line sent to sources after the last requested resource
+pub const END_OF_RESOURCES: &str = ".";
+
+/// Maximum length of a resource (e.g. URL) requested from a source
+pub const MAX_RESOURCE_LENGTH: usize = 2048;
+
+/// Maximum length of a pack header line (i.e. `<length> <resource>\n`)
+pub const MAX_HEADER_LENGTH: usize = MAX_RESOURCE_LENGTH + 32;
+
+/// Maximum length of a greeting or acknowledgement line
+pub const MAX_HANDSHAKE_LINE_LENGTH: usize = MAX_RESOURCE_LENGTH + 32;
+
+/// Maximum length of a line the source writes to stderr (the rest of longer
+/// lines is discarded)
+const MAX_STDERR_LINE_LENGTH: usize = 1024;
+
+/// How long to wait for a source which closed stdout before greeting to exit
+/// (so its exit status can be reported)
+const EXIT_WAIT: Duration = Duration::from_secs(1);
+
+/// Sources of (not yet verified) packs for the daemon to place
+pub trait PackSource {
+    /// Read the next pack, blocking until it's available. Returns the name of
+    /// the pack and its raw (encrypted and signed) contents.
+    fn next_file(&mut self) -> Result<(String, Vec<u8>), Error>;
+}
+
+/// In-process sources which receive packs over a channel
+impl PackSource for Receiver<(String, Vec<u8>)> {
+    fn next_file(&mut self) -> Result<(String, Vec<u8>), Error> {
+        self.recv()
+            .map_err(|_| err!(Source, "channel source disconnected"))
+    }
+}
+
 /// Source wraps an untrusted, low-privilege subprocess which fetches data
 /// from the network.
 pub struct Source {
@@ -40,14 +87,20 @@ pub struct Source {
 
     /// Mapping of resource URIs to their pack names
     resources: BTreeMap<String, String>,
+
+    /// Resources the source acknowledged it will fetch (all of them if the
+    /// source doesn't send acknowledgements)
+    acknowledged: BTreeSet<String>,
 }
 
 impl Source {
-    /// Spawn the source subcommand and request the packs from the source config
+    /// Spawn the source subcommand and request the packs from the source config.
+    /// Anything the source writes to stderr is forwarded to the given logger.
     pub fn new(
         source_name: &str,
         config: &SourceConfig,
         users_cache: &mut UsersCache,
+        log: &Logger,
     ) -> Result<Self, Error> {
         let user = users_cache
             .get_user_by_name(&config.user)
@@ -57,20 +110,7 @@ impl Source {
             .get_group_by_name(&config.group)
             .ok_or_else(|| err!(Config, "invalid group: {}", &config.group))?;
 
-        // Create a reverse mapping of URLs back to their pack names
-        let mut resources = BTreeMap::new();
-
-        for (label, resource) in &config.packs {
-            if let Some(other) = resources.insert(resource.clone(), label.clone()) {
-                fail!(
-                    Config,
-                    "packs \"{}\" and \"{}\" have duplicate URL: {}",
-                    label,
-                    other,
-                    resource
-                );
-            }
-        }
+        let resources = resource_map(source_name, &config.packs, config.max_resources)?;
 
         // Source command MUST be in the same directory as the placer executable
         let source_cmd_path = PLACER_PATH
@@ -88,9 +128,10 @@ impl Source {
         }
 
         // TODO: drop privileges, support for arguments
-        let source_child = Command::new(source_cmd_path.clone())
+        let mut source_child = Command::new(source_cmd_path.clone())
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .uid(user.uid())
             .gid(group.gid())
             .spawn()
@@ -106,10 +147,24 @@ impl Source {
 
         let pid = source_child.id();
 
-        let Child { stdin, stdout, .. } = source_child;
-        let (stdin, mut stdout) = (stdin.unwrap(), BufReader::new(stdout.unwrap()));
-
-        let greeting = read_greeting(&mut stdout)?;
+        let stdin = source_child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(source_child.stdout.take().unwrap());
+        spawn_stderr_pump(
+            source_name,
+            source_child.stderr.take().unwrap(),
+            log.clone(),
+        );
+
+        let greeting = match read_greeting(&mut stdout)? {
+            Some(greeting) => greeting,
+            None => fail!(
+                Source,
+                "[{}] {} {} before sending a greeting",
+                source_name,
+                source_cmd_path.to_string_lossy(),
+                describe_exit(&mut source_child)
+            ),
+        };
 
         let mut result = Self {
             name: source_name.to_owned(),
@@ -118,86 +173,263 @@ impl Source {
             stdin,
             stdout,
             resources,
+            acknowledged: BTreeSet::new(),
         };
 
         // Issue the request to fetch the configured pack resources
         result.request_resources(config.packs.values())?;
 
+        result.acknowledged = if result.greeting.split_whitespace().last() == Some(ACK_CAPABILITY) {
+            read_acks(&result.name, &mut result.stdout, &result.resources)?
+        } else {
+            result.resources.keys().cloned().collect()
+        };
+
         Ok(result)
     }
 
-    /// Read the next file the source has fetched, blocking until it's available
-    pub fn next_file(&mut self) -> Result<(String, Vec<u8>), Error> {
-        let mut line = String::new();
-        self.stdout.read_line(&mut line)?;
+    /// Get the resources requested from this source, mapped to their pack names
+    pub fn resources(&self) -> &BTreeMap<String, String> {
+        &self.resources
+    }
 
-        let line_parts: Vec<&str> = line.split_whitespace().collect();
+    /// Iterate over the resources the source did not acknowledge (i.e. dropped)
+    pub fn unacknowledged_resources(&self) -> impl Iterator<Item = &str> {
+        self.resources
+            .keys()
+            .filter(move |resource| !self.acknowledged.contains(*resource))
+            .map(String::as_str)
+    }
 
-        if line_parts.len() != 2 {
-            fail!(Source, "[{}] bad pack header: {:?}", self.name, line);
+    /// Send the source command (via STDIN) the list of resource URLs to fetch
+    fn request_resources<'a, I>(&'a mut self, resources: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        // Write the resource URLs/URIs to fetch out to the subprocess
+        for resource in resources {
+            writeln!(&mut self.stdin, "{}", resource)?;
         }
 
-        let length: u32 = line_parts[0]
-            .parse()
-            .map_err(|e| err!(Source, "[{}] bad length in pack header: {}", &self.name, e))?;
+        // Write a terminator line as an indicator we're done writing resources
+        // TODO: should we close pipe or leave it "open" to send additional commands?
+        writeln!(&mut self.stdin, "{}", END_OF_RESOURCES)?;
+        self.stdin.flush()?;
+
+        Ok(())
+    }
+}
+
+impl PackSource for Source {
+    /// Read the next file the source has fetched, blocking until it's available
+    fn next_file(&mut self) -> Result<(String, Vec<u8>), Error> {
+        read_frame(&self.name, &mut self.stdout, &self.resources)
+    }
+}
 
-        let resource = line_parts[1];
+/// Validate the packs requested from a source, creating a reverse mapping of
+/// their resources (e.g. URLs) back to their pack names
+fn resource_map(
+    source_name: &str,
+    packs: &BTreeMap<String, String>,
+    max_resources: usize,
+) -> Result<BTreeMap<String, String>, Error> {
+    if packs.len() > max_resources {
+        fail!(
+            Config,
+            "[{}] too many packs: {} (max {})",
+            source_name,
+            packs.len(),
+            max_resources
+        );
+    }
 
-        if length > MAX_PACK_SIZE as u32 {
+    let mut resources = BTreeMap::new();
+
+    for (label, resource) in packs {
+        // Resources are sent one per line and echoed back in frame headers,
+        // so they can't contain whitespace or control characters
+        if resource.is_empty()
+            || resource == END_OF_RESOURCES
+            || resource.len() > MAX_RESOURCE_LENGTH
+            || resource
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
             fail!(
-                Source,
-                "[{}] Resource too large ({} bytes): {}",
-                self.name,
-                length,
+                Config,
+                "[{}] invalid resource for pack \"{}\": {:?} (must be 1-{} bytes \
+                 without whitespace)",
+                source_name,
+                label,
+                resource,
+                MAX_RESOURCE_LENGTH
+            );
+        }
+
+        if let Some(other) = resources.insert(resource.clone(), label.clone()) {
+            fail!(
+                Config,
+                "packs \"{}\" and \"{}\" have duplicate URL: {}",
+                label,
+                other,
                 resource
             );
         }
+    }
 
-        let mut pack_data = vec![0u8; length as usize];
-        self.stdout.read_exact(&mut pack_data)?;
+    Ok(resources)
+}
 
-        let mut blank = String::new();
-        self.stdout.read_line(&mut blank)?;
+/// Read a pack from the source's output, framed as `<length> <resource>\n<body>\n`
+fn read_frame<R: BufRead>(
+    source_name: &str,
+    reader: &mut R,
+    resources: &BTreeMap<String, String>,
+) -> Result<(String, Vec<u8>), Error> {
+    let line = read_header_line(source_name, reader)?;
+    let line_parts: Vec<&str> = line.split_whitespace().collect();
+
+    if line_parts.len() != 2 {
+        fail!(Source, "[{}] bad pack header: {:?}", source_name, line);
+    }
 
-        if &blank != "\n" {
-            fail!(Source, "[{}] bad EOF marker: {:?}", self.name, blank);
-        }
+    let length: u32 = line_parts[0]
+        .parse()
+        .map_err(|e| err!(Source, "[{}] bad length in pack header: {}", source_name, e))?;
 
-        let pack_label = self.resources.get(resource).ok_or_else(|| {
-            err!(
+    let resource = line_parts[1];
+
+    if length > MAX_PACK_SIZE as u32 {
+        fail!(
+            Source,
+            "[{}] Resource too large ({} bytes): {}",
+            source_name,
+            length,
+            resource
+        );
+    }
+
+    let mut pack_data = vec![0u8; length as usize];
+    reader.read_exact(&mut pack_data)?;
+
+    let mut blank = String::new();
+    reader.read_line(&mut blank)?;
+
+    if &blank != "\n" {
+        fail!(Source, "[{}] bad EOF marker: {:?}", source_name, blank);
+    }
+
+    let pack_label = resources.get(resource).ok_or_else(|| {
+        err!(
+            Source,
+            "[{}] I never asked for this: {}",
+            source_name,
+            resource
+        )
+    })?;
+
+    Ok((pack_label.clone(), pack_data))
+}
+
+/// Read a pack header line, however many writes the source sends it in.
+/// Fails if the source closes its output before the end of the line, or
+/// sends more than `MAX_HEADER_LENGTH` bytes without one.
+fn read_header_line<R: BufRead>(source_name: &str, reader: &mut R) -> Result<String, Error> {
+    let mut line = vec![];
+
+    loop {
+        let (used, done) = {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if available.is_empty() {
+                fail!(
+                    Source,
+                    "[{}] source closed its output mid-header: {:?}",
+                    source_name,
+                    String::from_utf8_lossy(&line)
+                );
+            }
+
+            match available.iter().position(|&byte| byte == b'\n') {
+                Some(pos) => {
+                    line.extend_from_slice(&available[..=pos]);
+                    (pos + 1, true)
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    (available.len(), false)
+                }
+            }
+        };
+
+        reader.consume(used);
+
+        if line.len() > MAX_HEADER_LENGTH {
+            fail!(
                 Source,
-                "[{}] I never asked for this: {}",
-                self.name,
-                resource
-            )
-        })?;
+                "[{}] pack header too long (max {} bytes)",
+                source_name,
+                MAX_HEADER_LENGTH
+            );
+        }
 
-        Ok((pack_label.clone(), pack_data))
+        if done {
+            break;
+        }
     }
 
-    /// Send the source command (via STDIN) the list of resource URLs to fetch
-    fn request_resources<'a, I>(&'a mut self, resources: I) -> Result<(), Error>
-    where
-        I: IntoIterator<Item = &'a String>,
-    {
-        // Write the resource URLs/URIs to fetch out to the subprocess
-        for resource in resources {
-            writeln!(&mut self.stdin, "{}", resource)?;
+    String::from_utf8(line).map_err(|e| err!(Source, "[{}] bad pack header: {}", source_name, e))
+}
+
+/// Read the acknowledgements of requested resources, framed as one
+/// `ACK <resource>\n` line per resource, terminated by a blank line (only
+/// sent by sources whose greeting ends with `ACK_CAPABILITY`)
+fn read_acks<R: BufRead>(
+    source_name: &str,
+    reader: &mut R,
+    resources: &BTreeMap<String, String>,
+) -> Result<BTreeSet<String>, Error> {
+    let mut acknowledged = BTreeSet::new();
+
+    loop {
+        let line = read_handshake_line(reader)?
+            .ok_or_else(|| err!(Source, "[{}] closed its output mid-handshake", source_name))?;
+
+        if line == "\n" {
+            return Ok(acknowledged);
         }
 
-        // Write a single newline as an indicator we're done writing resources
-        // TODO: should we close pipe or leave it "open" to send additional commands?
-        writeln!(&mut self.stdin)?;
-        self.stdin.flush()?;
+        if !line.starts_with(ACK_PREFIX) {
+            fail!(Source, "[{}] bad acknowledgement: {:?}", source_name, line);
+        }
 
-        Ok(())
+        let resource = line[ACK_PREFIX.len()..].trim_end();
+
+        if !resources.contains_key(resource) {
+            fail!(
+                Source,
+                "[{}] acknowledged a resource I never asked for: {}",
+                source_name,
+                resource
+            );
+        }
+
+        acknowledged.insert(resource.to_owned());
     }
 }
 
-/// Read the greeting from the source
-fn read_greeting(stdout: &mut BufReader<ChildStdout>) -> Result<String, Error> {
-    let mut greeting = String::new();
-    stdout.read_line(&mut greeting)?;
+/// Read the greeting from the source, or `None` if it closed stdout (i.e.
+/// exited) without sending one
+fn read_greeting<R: BufRead>(stdout: &mut R) -> Result<Option<String>, Error> {
+    let mut greeting = match read_handshake_line(stdout)? {
+        Some(greeting) => greeting,
+        None => return Ok(None),
+    };
 
     // All greetings need to start with OK
     if !greeting.starts_with(GREETING_PREFIX) {
@@ -209,5 +441,355 @@ fn read_greeting(stdout: &mut BufReader<ChildStdout>) -> Result<String, Error> {
     let greeting_len = greeting.trim_end().len();
     greeting.truncate(greeting_len);
 
-    Ok(greeting)
+    Ok(Some(greeting))
+}
+
+/// Read a line of the greeting/acknowledgement handshake, or `None` at EOF
+fn read_handshake_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, Error> {
+    let mut line = vec![];
+
+    match read_line_bounded(reader, &mut line, MAX_HANDSHAKE_LINE_LENGTH)? {
+        0 => Ok(None),
+        length if length > MAX_HANDSHAKE_LINE_LENGTH => fail!(
+            Source,
+            "handshake line too long ({} bytes, max {})",
+            length,
+            MAX_HANDSHAKE_LINE_LENGTH
+        ),
+        _ => String::from_utf8(line)
+            .map(Some)
+            .map_err(|e| err!(Source, "bad handshake line: {}", e)),
+    }
+}
+
+/// Describe how a source which closed stdout exited, waiting briefly for it
+/// to do so
+fn describe_exit(child: &mut Child) -> String {
+    let started_at = Instant::now();
+
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                return match (status.code(), status.signal()) {
+                    (Some(code), _) => format!("exited with code {}", code),
+                    (None, Some(signal)) => format!("was killed by signal {}", signal),
+                    (None, None) => "exited".to_owned(),
+                }
+            }
+            Ok(None) if started_at.elapsed() < EXIT_WAIT => {
+                thread::sleep(Duration::from_millis(50))
+            }
+            _ => return "closed stdout".to_owned(),
+        }
+    }
+}
+
+/// Forward each line the source writes to stderr to the log (tagged with
+/// the source's name) from a background thread, until the source exits
+fn spawn_stderr_pump<R>(source_name: &str, stderr: R, log: Logger)
+where
+    R: Read + Send + 'static,
+{
+    let source_name = source_name.to_owned();
+
+    thread::spawn(move || {
+        let mut stderr = BufReader::new(stderr);
+        let mut line = vec![];
+
+        loop {
+            match read_line_bounded(&mut stderr, &mut line, MAX_STDERR_LINE_LENGTH) {
+                Ok(0) => break,
+                Ok(length) => {
+                    let truncated = if length > line.len() {
+                        " (truncated)"
+                    } else {
+                        ""
+                    };
+
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end();
+
+                    if !line.is_empty() {
+                        warn!(log, "[source:{}] {}{}", source_name, line, truncated);
+                    }
+                }
+                Err(e) => {
+                    warn!(log, "[source:{}] error reading stderr: {}", source_name, e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Read a line (including its trailing newline) into `line`, replacing its
+/// contents and keeping at most `max_length` bytes: the rest of a longer line
+/// is read and discarded. Returns the full length of the line (0 at EOF).
+fn read_line_bounded<R: BufRead>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    max_length: usize,
+) -> io::Result<usize> {
+    let mut length = 0;
+    line.clear();
+
+    loop {
+        let (used, done) = {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if available.is_empty() {
+                return Ok(length);
+            }
+
+            let (used, done) = match available.iter().position(|&byte| byte == b'\n') {
+                Some(pos) => (pos + 1, true),
+                None => (available.len(), false),
+            };
+
+            let kept = used.min(max_length - line.len());
+            line.extend_from_slice(&available[..kept]);
+            (used, done)
+        };
+
+        reader.consume(used);
+        length += used;
+
+        if done {
+            return Ok(length);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        describe_exit, read_acks, read_frame, read_greeting, read_line_bounded, resource_map,
+        PackSource, MAX_HANDSHAKE_LINE_LENGTH, MAX_HEADER_LENGTH,
+    };
+    use placer_pack::MAX_PACK_SIZE;
+    use std::collections::BTreeMap;
+    use std::io::{BufReader, Read};
+    use std::process::Command;
+    use std::sync::mpsc;
+
+    const SOURCE_NAME: &str = "test";
+    const EXAMPLE_RESOURCE: &str = "https://example.com/base.pack";
+
+    fn resources() -> BTreeMap<String, String> {
+        let mut resources = BTreeMap::new();
+        resources.insert(EXAMPLE_RESOURCE.to_owned(), "base".to_owned());
+        resources
+    }
+
+    fn read(input: &[u8]) -> Result<(String, Vec<u8>), crate::error::Error> {
+        let mut reader = input;
+        read_frame(SOURCE_NAME, &mut reader, &resources())
+    }
+
+    #[test]
+    fn test_resource_map() {
+        let mut packs = BTreeMap::new();
+        packs.insert("base".to_owned(), EXAMPLE_RESOURCE.to_owned());
+        assert_eq!(resource_map(SOURCE_NAME, &packs, 1).unwrap(), resources());
+        assert!(resource_map(SOURCE_NAME, &packs, 0).is_err());
+
+        packs.insert("other".to_owned(), "https://example.com/a b".to_owned());
+        assert!(resource_map(SOURCE_NAME, &packs, 2).is_err());
+
+        packs.insert("other".to_owned(), String::new());
+        assert!(resource_map(SOURCE_NAME, &packs, 2).is_err());
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let frame = format!("5 {}\nhello\n", EXAMPLE_RESOURCE);
+        let (label, data) = read(frame.as_bytes()).unwrap();
+        assert_eq!(label, "base");
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn test_read_frame_chunked_header() {
+        // The first read returns only part of the header
+        let rest = format!("{}\nhello\n", &EXAMPLE_RESOURCE[5..]);
+        let input = (&b"5 https"[..]).chain(rest.as_bytes());
+        let mut reader = BufReader::new(input);
+
+        let (label, data) = read_frame(SOURCE_NAME, &mut reader, &resources()).unwrap();
+        assert_eq!(label, "base");
+        assert_eq!(data, b"hello");
+
+        assert_eq!(
+            read(b"5 https").unwrap_err(),
+            err!(
+                Source,
+                "[test] source closed its output mid-header: {:?}",
+                "5 https"
+            )
+        );
+
+        let long_header = format!("5 {}\n", "a".repeat(MAX_HEADER_LENGTH));
+        assert!(read(long_header.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_frame_oversize() {
+        let frame = format!("{} {}\n", MAX_PACK_SIZE + 1, EXAMPLE_RESOURCE);
+        assert_eq!(
+            read(frame.as_bytes()).unwrap_err(),
+            err!(
+                Source,
+                "[test] Resource too large ({} bytes): {}",
+                MAX_PACK_SIZE + 1,
+                EXAMPLE_RESOURCE
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_frame_bad_header() {
+        assert_eq!(
+            read(b"hello\n").unwrap_err(),
+            err!(Source, "[test] bad pack header: {:?}", "hello\n")
+        );
+    }
+
+    #[test]
+    fn test_read_frame_bad_length() {
+        let frame = format!("five {}\nhello\n", EXAMPLE_RESOURCE);
+        assert_eq!(
+            read(frame.as_bytes()).unwrap_err(),
+            err!(
+                Source,
+                "[test] bad length in pack header: {}",
+                "five".parse::<u32>().unwrap_err()
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_frame_missing_eof_marker() {
+        let frame = format!("5 {}\nhello world\n", EXAMPLE_RESOURCE);
+        assert_eq!(
+            read(frame.as_bytes()).unwrap_err(),
+            err!(Source, "[test] bad EOF marker: {:?}", " world\n")
+        );
+    }
+
+    #[test]
+    fn test_read_frame_unrequested_resource() {
+        let frame = b"5 https://example.com/other.pack\nhello\n";
+        assert_eq!(
+            read(frame).unwrap_err(),
+            err!(
+                Source,
+                "[test] I never asked for this: {}",
+                "https://example.com/other.pack"
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_line_bounded() {
+        let input = (&b"hello world\nsh"[..]).chain(&b"ort\n"[..]);
+        let mut reader = BufReader::new(input);
+        let mut line = vec![];
+
+        assert_eq!(read_line_bounded(&mut reader, &mut line, 5).unwrap(), 12);
+        assert_eq!(line, b"hello");
+        assert_eq!(read_line_bounded(&mut reader, &mut line, 10).unwrap(), 6);
+        assert_eq!(line, b"short\n");
+        assert_eq!(read_line_bounded(&mut reader, &mut line, 10).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_read_acks() {
+        let acks = format!("ACK {}\n\n", EXAMPLE_RESOURCE);
+        let mut reader = acks.as_bytes();
+        let acknowledged = read_acks(SOURCE_NAME, &mut reader, &resources()).unwrap();
+        assert!(acknowledged.contains(EXAMPLE_RESOURCE));
+
+        let mut reader = &b"\n"[..];
+        assert!(read_acks(SOURCE_NAME, &mut reader, &resources())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_read_acks_too_long() {
+        let acks = format!("ACK {}\n\n", "a".repeat(MAX_HANDSHAKE_LINE_LENGTH));
+        let mut reader = acks.as_bytes();
+        assert!(read_acks(SOURCE_NAME, &mut reader, &resources()).is_err());
+
+        let mut reader = &b"ACK https://example.com/base.pack\n"[..];
+        assert!(read_acks(SOURCE_NAME, &mut reader, &resources()).is_err());
+    }
+
+    #[test]
+    fn test_read_acks_unrequested_resource() {
+        let mut reader = &b"ACK https://example.com/other.pack\n\n"[..];
+        assert_eq!(
+            read_acks(SOURCE_NAME, &mut reader, &resources()).unwrap_err(),
+            err!(
+                Source,
+                "[test] acknowledged a resource I never asked for: {}",
+                "https://example.com/other.pack"
+            )
+        );
+    }
+
+    #[test]
+    fn test_channel_source() {
+        let (sender, mut receiver) = mpsc::channel();
+        sender.send(("base".to_owned(), b"hello".to_vec())).unwrap();
+        drop(sender);
+
+        assert_eq!(
+            receiver.next_file().unwrap(),
+            ("base".to_owned(), b"hello".to_vec())
+        );
+
+        assert_eq!(
+            receiver.next_file().unwrap_err(),
+            err!(Source, "channel source disconnected")
+        );
+    }
+
+    #[test]
+    fn test_read_greeting() {
+        let mut reader = &b"OK placer-source-test 0.0.1 started\n"[..];
+        assert_eq!(
+            read_greeting(&mut reader).unwrap().unwrap(),
+            "placer-source-test 0.0.1 started"
+        );
+    }
+
+    #[test]
+    fn test_read_greeting_eof() {
+        let mut reader = &b""[..];
+        assert_eq!(read_greeting(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_describe_exit() {
+        let mut child = Command::new("/bin/sh")
+            .args(&["-c", "exit 3"])
+            .spawn()
+            .unwrap();
+
+        assert_eq!(describe_exit(&mut child), "exited with code 3");
+    }
+
+    #[test]
+    fn test_read_greeting_bad() {
+        let mut reader = &b"ERR something went wrong\n"[..];
+        assert_eq!(
+            read_greeting(&mut reader).unwrap_err(),
+            err!(Source, "bad greeting: {:?}", "ERR something went wrong\n")
+        );
+    }
 }
@@ -0,0 +1,357 @@
+//! Staging updated files for review (under the `staging_root` directory,
+//! mirroring their real paths), and promoting reviewed files into place
+
+use clear_on_drop::clear::Clear;
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use users::UsersCache;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::platform;
+use crate::process_file_config;
+use crate::target_file::{staging_path, TargetFile};
+
+/// Ensure the staging root is an existing directory, given by its canonical
+/// path, which only root (or placer's user) can write to
+pub fn check_staging_root(staging_root: &Path) -> Result<(), Error> {
+    ensure!(
+        staging_root.is_dir(),
+        Config,
+        "staging root isn't a directory: {}",
+        staging_root.display()
+    );
+
+    ensure!(
+        staging_root.canonicalize()? == staging_root,
+        Config,
+        "non-canonical staging root: {}",
+        staging_root.display()
+    );
+
+    platform::ensure_trusted_dir(staging_root, users::get_current_uid())
+}
+
+/// Find the files staged for the given targets, by their real paths. Returns
+/// concrete targets for each along with the path it's staged at. Only regular
+/// files are staged files: symlinks (or anything else) are ignored.
+pub fn staged_files(
+    staging_root: &Path,
+    targets: &BTreeMap<PathBuf, TargetFile>,
+) -> Result<BTreeMap<PathBuf, (TargetFile, PathBuf)>, Error> {
+    let mut result = BTreeMap::new();
+
+    for target in targets.values() {
+        let staged_path = staging_path(staging_root, &target.path);
+
+        if target.pattern.is_none() {
+            let is_file = fs::symlink_metadata(&staged_path)
+                .map(|metadata| metadata.file_type().is_file())
+                .unwrap_or(false);
+
+            if is_file {
+                result.insert(target.path.clone(), (target.clone(), staged_path));
+            }
+
+            continue;
+        }
+
+        // Look for files matching glob patterns where they'd be staged
+        let entries = match fs::read_dir(staged_path.parent().unwrap()) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let path = target.path.parent().unwrap().join(entry.file_name());
+
+            if target.matches(&path) && !result.contains_key(&path) {
+                result.insert(path.clone(), (target.with_path(&path), entry.path()));
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Promote staged files into place (running their hooks), removing them from
+/// the staging root. If any files are given (by their real paths) only they
+/// are promoted, otherwise every staged file is. Exits when done.
+pub fn promote(config: &Config, files: &[PathBuf], log: &Logger) -> ! {
+    let staging_root = config.staging_root.as_ref().unwrap_or_else(|| {
+        crit!(log, "no staging_root configured");
+        process::exit(1);
+    });
+
+    if let Err(e) = check_staging_root(staging_root) {
+        crit!(log, "bad staging_root: {}", e);
+        process::exit(1);
+    }
+
+    let mut users_cache = UsersCache::new();
+    let targets = process_file_config(&config.files, &mut users_cache, log);
+
+    let mut staged = staged_files(staging_root, &targets).unwrap_or_else(|e| {
+        crit!(log, "error finding staged files: {}", e);
+        process::exit(1);
+    });
+
+    if !files.is_empty() {
+        for file in files {
+            if !staged.contains_key(file) {
+                crit!(log, "no staged file for {}", file.display());
+                process::exit(1);
+            }
+        }
+
+        staged.retain(|path, _| files.contains(path));
+    }
+
+    if staged.is_empty() {
+        info!(log, "no staged files to promote");
+        process::exit(0);
+    }
+
+    let failures = promote_staged(&staged, log);
+    process::exit(if failures > 0 { 1 } else { 0 });
+}
+
+/// Promote the given staged files (see `staged_files`) whose preconditions
+/// are met, returning how many couldn't be promoted
+fn promote_staged(staged: &BTreeMap<PathBuf, (TargetFile, PathBuf)>, log: &Logger) -> usize {
+    let mut failures = 0;
+
+    for (path, (target, staged_path)) in &staged {
+        // Preconditions may have changed since the file was staged
+        match target.check_preconditions(log) {
+            Ok(true) => (),
+            Ok(false) => {
+                info!(
+                    log,
+                    "not promoting {}: not applicable to this host (left at {})",
+                    path.display(),
+                    staged_path.display()
+                );
+                continue;
+            }
+            Err(e) => {
+                error!(log, "couldn't promote {}: {}", path.display(), e);
+                failures += 1;
+                continue;
+            }
+        }
+
+        let result = read_staged_file(staged_path)
+            .map_err(Error::from)
+            .and_then(|mut body| {
+                let result = target.place(&body, log);
+
+                if target.secret {
+                    body.as_mut_slice().clear();
+                }
+
+                result
+            })
+            .and_then(|()| fs::remove_file(staged_path).map_err(Error::from));
+
+        match result {
+            Ok(()) => info!(
+                log,
+                "promoted {} (staged at {})",
+                path.display(),
+                staged_path.display()
+            ),
+            Err(e) => {
+                error!(log, "couldn't promote {}: {}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    failures
+}
+
+/// Read a staged file, refusing to follow a symlink which replaced it since
+/// it was found
+fn read_staged_file(staged_path: &Path) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    platform::open_nofollow(staged_path)?.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_staging_root, promote_staged, staged_files};
+    use crate::hook::Hook;
+    use crate::platform;
+    use crate::target_file::{staging_path, TargetFile};
+    use slog::{Discard, Logger};
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::{Path, PathBuf};
+
+    /// Create an empty directory (only writable by its owner) for a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().canonicalize().unwrap().join(format!(
+            "placer-staging-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o700)).unwrap();
+        dir
+    }
+
+    /// Target for the given path, with the given precondition hooks
+    fn example_target(path: &Path, precondition_hooks: Vec<Hook>) -> TargetFile {
+        TargetFile {
+            path: path.to_owned(),
+            pattern: None,
+            pack: "example".to_owned(),
+            fragments: vec![],
+            uid: users::get_current_uid(),
+            gid: users::get_current_gid(),
+            permissions: Permissions::from_mode(0o600),
+            precondition_hooks,
+            before_hooks: vec![],
+            after_hooks: vec![],
+            parent_dirs: None,
+            content_type: None,
+            fsync: false,
+            allow_special_file: false,
+            secret: false,
+            staging_root: None,
+        }
+    }
+
+    /// Hook which runs a shell script as the current user
+    fn shell_hook(script: &str) -> Hook {
+        Hook {
+            path: PathBuf::from("/bin/sh"),
+            uid: users::get_current_uid(),
+            gid: users::get_current_gid(),
+            args: vec!["-c".into(), script.into()],
+            cwd: None,
+            clear_env: false,
+            pass_env: vec![],
+        }
+    }
+
+    /// Stage a file for the given target, returning the staged files found
+    fn stage(
+        staging_root: &Path,
+        target: TargetFile,
+        body: &[u8],
+    ) -> BTreeMap<PathBuf, (TargetFile, PathBuf)> {
+        let staged_path = staging_path(staging_root, &target.path);
+        fs::create_dir_all(staged_path.parent().unwrap()).unwrap();
+        fs::write(&staged_path, body).unwrap();
+
+        let mut targets = BTreeMap::new();
+        targets.insert(target.path.clone(), target);
+        staged_files(staging_root, &targets).unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_staging_root() {
+        let dir = test_dir("untrusted-root");
+        assert!(check_staging_root(&dir).is_ok());
+        assert!(check_staging_root(&dir.join("..").join(dir.file_name().unwrap())).is_err());
+        assert!(check_staging_root(&dir.join("missing")).is_err());
+
+        // Writable by others
+        fs::set_permissions(&dir, Permissions::from_mode(0o777)).unwrap();
+        assert!(check_staging_root(&dir).is_err());
+        fs::set_permissions(&dir, Permissions::from_mode(0o700)).unwrap();
+
+        // Owned by someone other than root or placer's user (which can only
+        // be arranged when running as root)
+        if users::get_current_uid() == 0 {
+            platform::set_owner(&dir, 65534, 65534).unwrap();
+            assert!(check_staging_root(&dir).is_err());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_precondition() {
+        let dir = test_dir("precondition");
+        let staging_root = dir.join("staging");
+        let path = dir.join("example.conf");
+        let log = Logger::root(Discard, o!());
+
+        // Files are left staged when their preconditions aren't met
+        let staged = stage(
+            &staging_root,
+            example_target(&path, vec![shell_hook("exit 1")]),
+            b"staged",
+        );
+        assert_eq!(staged.len(), 1);
+        assert_eq!(promote_staged(&staged, &log), 0);
+        assert!(!path.exists());
+        assert!(staged[&path].1.exists());
+
+        // ...and fail to promote if their preconditions can't be checked
+        let staged = stage(
+            &staging_root,
+            example_target(&path, vec![shell_hook("kill -9 $$")]),
+            b"staged",
+        );
+        assert_eq!(promote_staged(&staged, &log), 1);
+        assert!(!path.exists());
+
+        let staged = stage(
+            &staging_root,
+            example_target(&path, vec![shell_hook("exit 0")]),
+            b"staged",
+        );
+        assert_eq!(promote_staged(&staged, &log), 0);
+        assert_eq!(fs::read(&path).unwrap(), b"staged");
+        assert!(!staged[&path].1.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_symlinked_staged_file() {
+        let dir = test_dir("symlink");
+        let staging_root = dir.join("staging");
+        let path = dir.join("example.conf");
+        let secret = dir.join("secret");
+        fs::write(&secret, b"secret").unwrap();
+
+        let staged = stage(&staging_root, example_target(&path, vec![]), b"staged");
+        let staged_path = staged[&path].1.clone();
+
+        // A symlink in the staging root isn't a staged file...
+        fs::remove_file(&staged_path).unwrap();
+        symlink(&secret, &staged_path).unwrap();
+
+        let mut targets = BTreeMap::new();
+        targets.insert(path.clone(), example_target(&path, vec![]));
+        assert!(staged_files(&staging_root, &targets).unwrap().is_empty());
+
+        // ...and isn't followed if it replaces one after it's found
+        let log = Logger::root(Discard, o!());
+        assert_eq!(promote_staged(&staged, &log), 1);
+        assert!(!path.exists());
+        assert_eq!(fs::read(&secret).unwrap(), b"secret");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
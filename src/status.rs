@@ -0,0 +1,307 @@
+//! Status file listing the pack each target was most recently placed from,
+//! read by `placer status`
+//!
+//! Each line of the status file contains a target path, the name, UUID and
+//! date of the pack it was placed from, and the SHA-256 digest of its
+//! contents (or `(secret)` for secret files), separated by tabs, e.g.:
+//!
+//! ```text
+//! /etc/motd	my-pack	c5b4e0b1-3f4c-4e0e-9a7b-2d0c5f0e6a41	2019-01-01T00:00:00+00:00	2cf24dba...
+//! ```
+//!
+//! Backslashes, tabs, and line breaks in fields (e.g. target paths) are
+//! escaped as `\\`, `\t`, `\n`, and `\r`.
+
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::digest::Digest;
+use crate::error::Error;
+use crate::pack::Pack;
+use crate::platform;
+use crate::target_file::TargetFile;
+
+/// Permissions of the status file (it reveals which packs are deployed, so
+/// it's only readable by placer's user)
+const STATUS_FILE_PERMISSIONS: u32 = 0o600;
+
+/// Recorded in place of the digest of secret files (which could be used to
+/// guess their contents)
+const REDACTED_DIGEST: &str = "(secret)";
+
+/// Pack a target was placed from, and the digest of its contents
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlacedFile {
+    /// Name of the pack
+    pub pack: String,
+
+    /// UUID of the pack
+    pub uuid: String,
+
+    /// Date the pack was published (RFC 3339)
+    pub date: String,
+
+    /// SHA-256 digest of the file's contents (hex), or `(secret)`
+    pub digest: String,
+}
+
+/// Targets placed (or found to be up-to-date) since placer started, which are
+/// written to the status file after each batch of packs is placed
+#[derive(Debug)]
+pub struct Status {
+    /// Path to the status file
+    path: PathBuf,
+
+    /// Placed files by target path
+    files: Mutex<BTreeMap<PathBuf, PlacedFile>>,
+}
+
+impl Status {
+    /// Create an empty status, replacing any status file left over from a
+    /// previous run
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let status = Self {
+            path: path.to_owned(),
+            files: Mutex::new(BTreeMap::new()),
+        };
+
+        status.save()?;
+        Ok(status)
+    }
+
+    /// Record that the given target now holds the file from the given pack
+    pub fn record(&self, target: &TargetFile, pack: &Pack, digest: &Digest) {
+        let placed_file = PlacedFile {
+            pack: pack.name.clone(),
+            uuid: pack.uuid().to_string(),
+            date: pack.date().to_rfc3339(),
+            digest: if target.secret {
+                REDACTED_DIGEST.to_owned()
+            } else {
+                digest.to_string()
+            },
+        };
+
+        self.files
+            .lock()
+            .unwrap()
+            .insert(target.path.clone(), placed_file);
+    }
+
+    /// Write the status file (via a temp file, so readers never see a
+    /// partially written one)
+    pub fn save(&self) -> Result<(), Error> {
+        let data = serialize(&self.files.lock().unwrap());
+
+        let mut temp_filename = self.path.file_name().unwrap_or_default().to_owned();
+        temp_filename.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_filename);
+
+        // Remove any temp file left behind by a crash (or planted there):
+        // the new one is always created from scratch, never through a symlink
+        match fs::remove_file(&temp_path) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => fail!(Io, "couldn't remove {}: {}", temp_path.display(), e),
+        }
+
+        let permissions = platform::permissions_from_mode(STATUS_FILE_PERMISSIONS)?;
+        let mut file = platform::create_new(&temp_path, &permissions)
+            .map_err(|e| err!(Io, "couldn't create {}: {}", temp_path.display(), e))?;
+
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, &self.path)
+            .map_err(|e| err!(Io, "couldn't write {}: {}", self.path.display(), e))
+    }
+}
+
+/// Print the pack each target was most recently placed from, as recorded in
+/// the status file by the running daemon
+pub fn print(config: &Config, log: &Logger) -> ! {
+    let path = config.status_file.as_ref().unwrap_or_else(|| {
+        crit!(log, "no status_file configured");
+        process::exit(1);
+    });
+
+    let files = match fs::read_to_string(path) {
+        Ok(data) => parse(&data),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => {
+            crit!(log, "{} doesn't exist (is placer running?)", path.display());
+            process::exit(1);
+        }
+        Err(e) => Err(err!(Io, "couldn't read {}: {}", path.display(), e)),
+    }
+    .unwrap_or_else(|e| {
+        crit!(log, "bad status file: {}", e);
+        process::exit(1);
+    });
+
+    if files.is_empty() {
+        info!(log, "no files placed yet");
+        process::exit(0);
+    }
+
+    let width = files
+        .keys()
+        .map(|target| target.to_string_lossy().len())
+        .max()
+        .unwrap_or(0);
+
+    for (target, file) in &files {
+        println!(
+            "{:width$}  {}:{}  {}  {}",
+            target.to_string_lossy(),
+            file.pack,
+            file.uuid,
+            file.date,
+            file.digest,
+            width = width
+        );
+    }
+
+    process::exit(0);
+}
+
+/// Serialize placed files in the status file format
+fn serialize(files: &BTreeMap<PathBuf, PlacedFile>) -> String {
+    let mut data = String::from("# target\tpack\tuuid\tdate\tsha256\n");
+
+    for (target, file) in files {
+        data.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            escape(&target.to_string_lossy()),
+            escape(&file.pack),
+            escape(&file.uuid),
+            escape(&file.date),
+            escape(&file.digest)
+        ));
+    }
+
+    data
+}
+
+/// Parse the contents of a status file
+fn parse(data: &str) -> Result<BTreeMap<PathBuf, PlacedFile>, Error> {
+    let mut files = BTreeMap::new();
+
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<_> = line.split('\t').collect();
+
+        let malformed = || err!(Config, "malformed status entry on line {}", i + 1);
+
+        match fields.as_slice() {
+            [target, pack, uuid, date, digest] => files.insert(
+                PathBuf::from(unescape(target).ok_or_else(malformed)?),
+                PlacedFile {
+                    pack: unescape(pack).ok_or_else(malformed)?,
+                    uuid: unescape(uuid).ok_or_else(malformed)?,
+                    date: unescape(date).ok_or_else(malformed)?,
+                    digest: unescape(digest).ok_or_else(malformed)?,
+                },
+            ),
+            _ => return Err(malformed()),
+        };
+    }
+
+    Ok(files)
+}
+
+/// Escape a field so it can't contain the tabs and line breaks which
+/// separate fields and entries
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Reverse `escape`, returning `None` for invalid escape sequences
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        unescaped.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, serialize, PlacedFile};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut files = BTreeMap::new();
+        files.insert(
+            PathBuf::from("/etc/with space.conf"),
+            PlacedFile {
+                pack: "my-pack".to_owned(),
+                uuid: "c5b4e0b1-3f4c-4e0e-9a7b-2d0c5f0e6a41".to_owned(),
+                date: "2017-07-14T02:40:00+00:00".to_owned(),
+                digest: "2cf24dba".to_owned(),
+            },
+        );
+
+        assert_eq!(parse(&serialize(&files)).unwrap(), files);
+        assert!(parse("/etc/motd\tmy-pack\n").is_err());
+    }
+
+    #[test]
+    fn test_escaped_target() {
+        let placed_file = PlacedFile {
+            pack: "my-pack".to_owned(),
+            uuid: "c5b4e0b1-3f4c-4e0e-9a7b-2d0c5f0e6a41".to_owned(),
+            date: "2017-07-14T02:40:00+00:00".to_owned(),
+            digest: "2cf24dba".to_owned(),
+        };
+
+        let mut files = BTreeMap::new();
+        files.insert(PathBuf::from("/etc/tab\there"), placed_file.clone());
+        files.insert(PathBuf::from("/etc/line\nbreak\r"), placed_file.clone());
+        files.insert(PathBuf::from("/etc/back\\slash\\t"), placed_file);
+
+        // Each target is still a single entry of five fields
+        let data = serialize(&files);
+        assert_eq!(data.lines().count(), 4);
+        assert!(data.lines().all(|line| line.split('\t').count() == 5));
+        assert_eq!(parse(&data).unwrap(), files);
+
+        assert!(parse("/etc/bad\\x\tmy-pack\tuuid\tdate\tdigest\n").is_err());
+    }
+}
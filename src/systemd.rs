@@ -0,0 +1,189 @@
+//! systemd service integration (see `sd_notify(3)`): readiness, watchdog,
+//! and stopping notifications. Only available with the `systemd` feature,
+//! and every notification is a no-op when placer isn't run by systemd.
+
+use slog::Logger;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// Environment variable containing the path to systemd's notification socket
+#[cfg(feature = "systemd")]
+const NOTIFY_SOCKET_ENV_VAR: &str = "NOTIFY_SOCKET";
+
+/// Environment variable containing the watchdog timeout (in microseconds)
+#[cfg(feature = "systemd")]
+const WATCHDOG_USEC_ENV_VAR: &str = "WATCHDOG_USEC";
+
+/// Environment variable containing the PID the watchdog timeout applies to
+#[cfg(feature = "systemd")]
+const WATCHDOG_PID_ENV_VAR: &str = "WATCHDOG_PID";
+
+lazy_static! {
+    /// systemd service watchdog (if enabled for this process)
+    static ref WATCHDOG: Option<Watchdog> = Watchdog::from_env();
+}
+
+/// Get the systemd service watchdog (if enabled for this process)
+pub fn watchdog() -> Option<&'static Watchdog> {
+    WATCHDOG.as_ref()
+}
+
+/// Ping the systemd watchdog (if enabled) if it's due. Called between the
+/// steps of long-running work (e.g. placing files and running their hooks)
+/// so systemd doesn't mistake it for a hang.
+pub fn ping_watchdog(log: &Logger) {
+    if let Some(watchdog) = watchdog() {
+        watchdog.ping_if_due(log);
+    }
+}
+
+/// Tell systemd placer has finished starting up
+pub fn notify_ready(log: &Logger) {
+    notify("READY=1", log);
+}
+
+/// Tell systemd placer is shutting down
+pub fn notify_stopping(log: &Logger) {
+    notify("STOPPING=1", log);
+}
+
+/// Send a state update to systemd, logging (but otherwise ignoring) errors
+fn notify(state: &str, log: &Logger) {
+    match send(state) {
+        Ok(true) => debug!(log, "[systemd] Sent {}", state),
+        Ok(false) => (),
+        Err(e) => error!(log, "[systemd] couldn't send {}: {}", state, e),
+    }
+}
+
+/// Send a state update to systemd's notification socket, returning `false`
+/// if placer isn't running under systemd
+#[cfg(feature = "systemd")]
+fn send(state: &str) -> Result<bool, Error> {
+    send_to(state, std::env::var_os(NOTIFY_SOCKET_ENV_VAR).as_deref())
+}
+
+/// Send a state update to the given notification socket (if any)
+#[cfg(feature = "systemd")]
+fn send_to(state: &str, socket_path: Option<&std::ffi::OsStr>) -> Result<bool, Error> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket_path = match socket_path {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+
+    ensure!(
+        !socket_path.to_string_lossy().starts_with('@'),
+        Config,
+        "abstract namespace {} sockets aren't supported",
+        NOTIFY_SOCKET_ENV_VAR
+    );
+
+    UnixDatagram::unbound()?.send_to(state.as_bytes(), socket_path)?;
+    Ok(true)
+}
+
+/// Without the `systemd` feature, placer never notifies systemd
+#[cfg(not(feature = "systemd"))]
+fn send(_state: &str) -> Result<bool, Error> {
+    Ok(false)
+}
+
+/// systemd service watchdog: pinged from the main loop (and while placing
+/// files) so systemd can restart placer if it hangs
+pub struct Watchdog {
+    /// How often to ping the watchdog (half its timeout, as systemd suggests)
+    interval: Duration,
+
+    /// When the watchdog was last pinged
+    last_ping: Mutex<Instant>,
+}
+
+impl Watchdog {
+    /// Create a watchdog if systemd has enabled one for this process
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            interval: watchdog_timeout()? / 2,
+            last_ping: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// How long until the watchdog should be pinged again
+    pub fn time_until_due(&self) -> Duration {
+        (*self.last_ping.lock().unwrap() + self.interval).saturating_duration_since(Instant::now())
+    }
+
+    /// Ping the watchdog if it's due
+    pub fn ping_if_due(&self, log: &Logger) {
+        let mut last_ping = self.last_ping.lock().unwrap();
+
+        if Instant::now() >= *last_ping + self.interval {
+            notify("WATCHDOG=1", log);
+            *last_ping = Instant::now();
+        }
+    }
+}
+
+/// Watchdog timeout systemd has set for this process (if any)
+#[cfg(feature = "systemd")]
+fn watchdog_timeout() -> Option<Duration> {
+    use std::{env, process};
+
+    // The watchdog may be meant for another process (e.g. our parent)
+    if let Ok(pid) = env::var(WATCHDOG_PID_ENV_VAR) {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+
+    match env::var(WATCHDOG_USEC_ENV_VAR).ok()?.parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Without the `systemd` feature, there's never a watchdog
+#[cfg(not(feature = "systemd"))]
+fn watchdog_timeout() -> Option<Duration> {
+    None
+}
+
+#[cfg(all(test, feature = "systemd"))]
+mod tests {
+    use super::send_to;
+    use std::env;
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_send_to() {
+        let dir = env::temp_dir().join(format!("placer-systemd-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        let socket_path = dir.join("notify");
+        let socket = UnixDatagram::bind(&socket_path).unwrap();
+
+        assert_eq!(send_to("READY=1", Some(socket_path.as_os_str())), Ok(true));
+
+        let mut buf = [0u8; 64];
+        let len = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_send_to_abstract_socket() {
+        assert!(send_to("READY=1", Some(OsStr::new("@placer-notify"))).is_err());
+    }
+
+    #[test]
+    fn test_send_without_socket() {
+        assert_eq!(send_to("READY=1", None), Ok(false));
+    }
+}
@@ -1,33 +1,61 @@
 //! Attributes of files-to-be-placed, derived from a validated configuration
 
+use glob::{MatchOptions, Pattern};
 use slog::Logger;
 use std::collections::BTreeMap;
-use std::ffi::{CString, OsStr, OsString};
-use std::fs::{self, OpenOptions, Permissions};
-use std::io::Write;
-use std::os::unix::ffi::OsStrExt;
-use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
-use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::fs::{self, File, Permissions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
 use std::u32;
 use users::{gid_t, uid_t, Groups, Users, UsersCache};
 
 use crate::config::{FileConfig, HookConfig};
 use crate::error::Error;
 use crate::hook::Hook;
+use crate::platform;
+use crate::staging;
+use crate::systemd;
 
 /// Prefix prepended to temporary files placer is placing
 pub const PLACER_TEMPFILE_PREFIX: &str = ".placer-tmp-";
 
+/// Prefix prepended to lock files held while a file is being placed
+pub const PLACER_LOCKFILE_PREFIX: &str = ".placer-lock-";
+
+/// Characters which indicate a target's file name is a glob pattern
+pub const GLOB_CHARS: &[char] = &['*', '?', '['];
+
+/// Default owner of parent directories created for a target file
+pub const DEFAULT_PARENT_OWNER: &str = "root";
+
+/// Default permissions of parent directories created for a target file
+pub const DEFAULT_PARENT_MODE: &str = "755";
+
+/// Options for matching glob patterns: wildcards never match `/` or a leading `.`
+const GLOB_MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
 /// Target file to-be-placed
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TargetFile {
     /// Canonicalized path to file
     pub path: PathBuf,
 
-    /// Name of the pack this file belongs to
+    /// Glob pattern matched against the file name (if the path contains one)
+    pub pattern: Option<Pattern>,
+
+    /// Name of the pack this file belongs to (empty if it's composed of
+    /// fragments)
     // TODO: find some better way to model this
     pub pack: String,
 
+    /// Fragments this file is composed of, in order (if any)
+    pub fragments: Vec<Fragment>,
+
     /// POSIX user ID
     pub uid: uid_t,
 
@@ -37,11 +65,86 @@ pub struct TargetFile {
     /// POSIX file permissions
     pub permissions: Permissions,
 
+    /// Precondition hooks
+    pub precondition_hooks: Vec<Hook>,
+
     /// Before hooks
     pub before_hooks: Vec<Hook>,
 
     /// After hooks
     pub after_hooks: Vec<Hook>,
+
+    /// Attributes of missing parent directories to create (if enabled)
+    pub parent_dirs: Option<ParentDirs>,
+
+    /// Content-Type the file must be declared with in its pack (if any)
+    pub content_type: Option<String>,
+
+    /// Flush the file and its parent directory to disk when placing it
+    pub fsync: bool,
+
+    /// Allow replacing a special file (e.g. a FIFO, socket, or device node)
+    /// rather than a regular file at this path
+    pub allow_special_file: bool,
+
+    /// File is a secret: its mode grants no group/other permissions, and
+    /// copies of its contents are zeroed once it's been placed
+    pub secret: bool,
+
+    /// Place the file under this directory (mirroring its real path) for
+    /// review instead of at its real path, if set
+    pub staging_root: Option<PathBuf>,
+}
+
+/// File from a pack which is concatenated with others to compose a target file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fragment {
+    /// Name of the pack containing the fragment
+    pub pack: String,
+
+    /// Path of the fragment within the pack
+    pub file: PathBuf,
+}
+
+/// Ownership and permissions of parent directories created for a target file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParentDirs {
+    /// POSIX user ID
+    pub uid: uid_t,
+
+    /// POSIX group ID
+    pub gid: gid_t,
+
+    /// POSIX directory permissions
+    pub permissions: Permissions,
+}
+
+/// Lock held while placing a target file. The lock file is removed when the
+/// lock is released, so none are left next to placed files.
+struct TargetLock {
+    /// Path to the lock file
+    path: PathBuf,
+
+    /// Locked file (the lock is released when it's closed)
+    _file: File,
+}
+
+impl TargetLock {
+    /// Take the lock at the given path, or `None` if it's already held
+    fn acquire(path: &Path) -> io::Result<Option<Self>> {
+        Ok(platform::try_lock(path)?.map(|file| Self {
+            path: path.to_owned(),
+            _file: file,
+        }))
+    }
+}
+
+impl Drop for TargetLock {
+    fn drop(&mut self) {
+        // Remove the lock file while still holding the lock, so nobody else
+        // can lock it in the meantime (see `platform::try_lock`)
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
 impl TargetFile {
@@ -55,14 +158,11 @@ impl TargetFile {
             .parent()
             .ok_or_else(|| err!(Config, "bad path: {}", non_canonical_path.to_string_lossy()))?;
 
-        let canonical_parent = non_canonical_parent.canonicalize().map_err(|e| {
-            err!(
-                Config,
-                "error canonicalizing path: {} ({})",
-                non_canonical_parent.to_string_lossy(),
-                e
-            )
-        })?;
+        let canonical_parent = if config.create_parents {
+            canonicalize_existing_ancestor(non_canonical_parent)?
+        } else {
+            canonicalize(non_canonical_parent)?
+        };
 
         let canonical_path = canonical_parent.join(non_canonical_path.file_name().unwrap());
 
@@ -83,26 +183,224 @@ impl TargetFile {
             .get_group_by_name(&config.group)
             .ok_or_else(|| err!(Config, "invalid group: {}", &config.group))?;
 
-        let permissions = Permissions::from_mode(
-            u32::from_str_radix(&config.mode, 8)
-                .map_err(|e| err!(Config, "bad mode: {} ({:?})", &config.mode, e))?,
+        // Refuse to place files in directories writable by untrusted users
+        if !config.allow_untrusted_parent {
+            let existing_parent = canonical_parent
+                .ancestors()
+                .find(|dir| dir.exists())
+                .unwrap();
+
+            platform::ensure_trusted_dir(existing_parent, user.uid())?;
+        }
+
+        let file_name = canonical_path.file_name().unwrap().to_string_lossy();
+
+        // Glob patterns are only allowed in the file name, not the parent directory
+        let pattern = if file_name.contains(GLOB_CHARS) {
+            Some(
+                Pattern::new(&canonical_path.to_string_lossy())
+                    .map_err(|e| err!(Config, "bad pattern: {} ({})", file_name, e))?,
+            )
+        } else {
+            None
+        };
+
+        if pattern.is_none() {
+            ensure_regular_file(&canonical_path, config.allow_special_file)?;
+        }
+
+        // Files are either placed from a single pack or composed of fragments
+        ensure!(
+            config.pack.is_empty() != config.fragments.is_empty(),
+            Config,
+            "exactly one of `pack` or `fragments` must be configured for {}",
+            canonical_path.to_string_lossy()
         );
 
+        ensure!(
+            config.fragments.is_empty() || pattern.is_none(),
+            Config,
+            "can't compose fragments into a pattern: {}",
+            file_name
+        );
+
+        let fragments = config
+            .fragments
+            .iter()
+            .map(|fragment| -> Result<Fragment, Error> {
+                ensure!(
+                    fragment.file.is_absolute(),
+                    Config,
+                    "fragment path must be absolute: {}",
+                    fragment.file.to_string_lossy()
+                );
+
+                Ok(Fragment {
+                    pack: fragment.pack.clone(),
+                    file: fragment.file.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let permissions = parse_mode(&config.mode, config.allow_dangerous_mode)?;
+
+        if config.secret {
+            ensure_secret_mode(&config.mode)?;
+        }
+
+        let parent_dirs = if config.create_parents {
+            let user_name = config
+                .parent_user
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_PARENT_OWNER);
+
+            let group_name = config
+                .parent_group
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or(DEFAULT_PARENT_OWNER);
+
+            let parent_user = users_cache
+                .get_user_by_name(user_name)
+                .ok_or_else(|| err!(Config, "invalid user: {}", user_name))?;
+
+            let parent_group = users_cache
+                .get_group_by_name(group_name)
+                .ok_or_else(|| err!(Config, "invalid group: {}", group_name))?;
+
+            Some(ParentDirs {
+                uid: parent_user.uid(),
+                gid: parent_group.gid(),
+                permissions: parse_mode(
+                    config
+                        .parent_mode
+                        .as_ref()
+                        .map(String::as_str)
+                        .unwrap_or(DEFAULT_PARENT_MODE),
+                    config.allow_dangerous_mode,
+                )?,
+            })
+        } else {
+            None
+        };
+
+        // Hooks run as the file's owner unless configured otherwise
+        let owner = (user.uid(), group.gid());
+
         Ok(Self {
             path: canonical_path,
+            pattern,
             pack: config.pack.clone(),
+            fragments,
             uid: user.uid(),
             gid: group.gid(),
             permissions,
-            before_hooks: process_hook_configs(&config.before_hooks, users_cache)?,
-            after_hooks: process_hook_configs(&config.after_hooks, users_cache)?,
+            precondition_hooks: process_hook_configs(
+                &config.precondition_hooks,
+                owner,
+                users_cache,
+            )?,
+            before_hooks: process_hook_configs(&config.before_hooks, owner, users_cache)?,
+            after_hooks: process_hook_configs(&config.after_hooks, owner, users_cache)?,
+            parent_dirs,
+            content_type: config.content_type.clone(),
+            fsync: config.fsync,
+            allow_special_file: config.allow_special_file,
+            secret: config.secret,
+            staging_root: None,
         })
     }
 
+    /// Is a file declared with the given Content-Type acceptable for this
+    /// target? Always true if the target doesn't expect a Content-Type.
+    pub fn accepts_content_type(&self, content_type: &str) -> bool {
+        match self.content_type {
+            Some(ref expected) => media_type(expected) == media_type(content_type),
+            None => true,
+        }
+    }
+
+    /// Is this file composed of the given file from the given pack?
+    pub fn has_fragment(&self, pack_name: &str, path: &Path) -> bool {
+        self.fragments
+            .iter()
+            .any(|fragment| fragment.pack == pack_name && fragment.file == path)
+    }
+
+    /// Does this target's glob pattern match the given path?
+    pub fn matches(&self, path: &Path) -> bool {
+        match self.pattern {
+            Some(ref pattern) => pattern.matches_path_with(path, GLOB_MATCH_OPTIONS),
+            None => false,
+        }
+    }
+
+    /// Create a concrete target for a path matched by this target's pattern
+    pub fn with_path(&self, path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            pattern: None,
+            ..self.clone()
+        }
+    }
+
+    /// Run precondition hooks, returning whether they all succeeded (i.e.
+    /// whether this file should be placed on this host)
+    pub fn check_preconditions(&self, log: &Logger) -> Result<bool, Error> {
+        for hook in &self.precondition_hooks {
+            systemd::ping_watchdog(log);
+
+            debug!(
+                log,
+                "[file:{}] Running precondition hook: {}",
+                self.path.to_string_lossy(),
+                hook.path.to_string_lossy(),
+            );
+
+            if !hook.check(&self.path)? {
+                debug!(
+                    log,
+                    "[file:{}] Precondition not met: {}",
+                    self.path.to_string_lossy(),
+                    hook.path.to_string_lossy(),
+                );
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
     /// Create a tempfile containing data to-be-placed, run all before hooks
     /// against it, and if they all succeed overwrite the target file, then
     /// run any after hooks
     pub fn place(&self, body: &[u8], log: &Logger) -> Result<(), Error> {
+        if let Some(ref staging_root) = self.staging_root {
+            staging::check_staging_root(staging_root)?;
+            return self.staged(staging_root)?.place(body, log);
+        }
+
+        if let Some(ref parent_dirs) = self.parent_dirs {
+            self.create_parent_dirs(parent_dirs, log)?;
+        }
+
+        self.ensure_no_symlinks()?;
+
+        // Hold a lock while placing so another placer instance can't race us
+        let mut lock_filename = OsString::from(PLACER_LOCKFILE_PREFIX);
+        lock_filename.push(self.path.file_name().unwrap());
+        let lock_path = self.path.with_file_name(&lock_filename);
+
+        let _lock = TargetLock::acquire(&lock_path)?.ok_or_else(|| {
+            err!(
+                Io,
+                "another placer instance is placing {} (locked: {})",
+                self.path.to_string_lossy(),
+                lock_path.to_string_lossy()
+            )
+        })?;
+
         let mut temp_filename = OsString::from(PLACER_TEMPFILE_PREFIX);
         temp_filename.push(self.path.file_name().unwrap());
 
@@ -115,21 +413,24 @@ impl TargetFile {
         let _ = fs::remove_file(&temp_path);
 
         {
-            let mut file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .mode(self.permissions.mode())
-                .open(&temp_path)?;
-
+            let mut file = platform::create_new(&temp_path, &self.permissions)?;
             file.write_all(body)?;
-        }
 
-        // Set ownership on the path
-        chown(&temp_path, self.uid, self.gid)?;
+            // Set ownership on the path, then reapply the permissions (which
+            // changing ownership may have cleared setuid/setgid bits from)
+            // before flushing, so the file is durable in its final state
+            platform::set_owner(&temp_path, self.uid, self.gid)?;
+            file.set_permissions(self.permissions.clone())?;
+
+            if self.fsync {
+                file.sync_all()?;
+            }
+        }
 
         // Run before hooks
         for hook in &self.before_hooks {
+            systemd::ping_watchdog(log);
+
             debug!(
                 log,
                 "[file:{}] Running before hook: {}",
@@ -152,8 +453,15 @@ impl TargetFile {
         // TODO: use renameat2() on Linux when available?
         fs::rename(&temp_path, &self.path)?;
 
+        // Ensure the rename itself is durable
+        if self.fsync {
+            platform::sync_dir(self.path.parent().unwrap())?;
+        }
+
         // Run after hooks
         for hook in &self.after_hooks {
+            systemd::ping_watchdog(log);
+
             debug!(
                 log,
                 "[file:{}] Running after hook: {}",
@@ -166,35 +474,329 @@ impl TargetFile {
 
         Ok(())
     }
+
+    /// Create a target which places this file under the given staging root
+    /// (at `staging_path`), with the same ownership and permissions. Missing
+    /// directories under the staging root are created, and no hooks are run:
+    /// they're run when the staged file is promoted.
+    pub fn staged(&self, staging_root: &Path) -> Result<Self, Error> {
+        let parent_dirs = match self.parent_dirs {
+            Some(ref parent_dirs) => parent_dirs.clone(),
+            None => ParentDirs {
+                uid: 0,
+                gid: 0,
+                permissions: parse_mode(DEFAULT_PARENT_MODE, false)?,
+            },
+        };
+
+        Ok(Self {
+            path: staging_path(staging_root, &self.path),
+            pattern: None,
+            precondition_hooks: vec![],
+            before_hooks: vec![],
+            after_hooks: vec![],
+            parent_dirs: Some(parent_dirs),
+            staging_root: None,
+            ..self.clone()
+        })
+    }
+
+    /// Open the currently placed file for reading (if it exists), refusing to
+    /// follow a symlink in its place. Special files (e.g. FIFOs, which could
+    /// block forever) are never opened: they're treated as missing if
+    /// `allow_special_file` is set, and refused otherwise. Directories are
+    /// always refused, since placing a file can't replace them.
+    pub fn open_current(&self) -> Result<Option<File>, Error> {
+        if let Ok(metadata) = fs::symlink_metadata(&self.path) {
+            ensure!(
+                !metadata.is_dir(),
+                Io,
+                "refusing to replace directory: {}",
+                self.path.to_string_lossy()
+            );
+
+            if is_special_file(metadata.file_type()) {
+                ensure!(
+                    self.allow_special_file,
+                    Io,
+                    "refusing to replace special file: {}",
+                    self.path.to_string_lossy()
+                );
+
+                return Ok(None);
+            }
+        }
+
+        match platform::open_nofollow(&self.path) {
+            Ok(file) => Ok(Some(file)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(ref e) if platform::is_symlink_error(e) => fail!(
+                Io,
+                "refusing to follow symlink: {}",
+                self.path.to_string_lossy()
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Ensure neither this file nor its parent directory have been replaced
+    /// with a symlink since the configuration was loaded
+    fn ensure_no_symlinks(&self) -> Result<(), Error> {
+        let parent = self.path.parent().unwrap();
+
+        ensure!(
+            parent.canonicalize()? == parent,
+            Io,
+            "parent directory is no longer canonical (symlink?): {}",
+            parent.to_string_lossy()
+        );
+
+        match fs::symlink_metadata(&self.path) {
+            Ok(ref metadata) if metadata.file_type().is_symlink() => fail!(
+                Io,
+                "refusing to replace symlink: {}",
+                self.path.to_string_lossy()
+            ),
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Create any missing parent directories of this file
+    fn create_parent_dirs(&self, parent_dirs: &ParentDirs, log: &Logger) -> Result<(), Error> {
+        let mut missing: Vec<&Path> = self
+            .path
+            .parent()
+            .unwrap()
+            .ancestors()
+            .take_while(|dir| !dir.exists())
+            .collect();
+
+        missing.reverse();
+
+        for dir in missing {
+            debug!(
+                log,
+                "[file:{}] Creating parent directory: {}",
+                self.path.to_string_lossy(),
+                dir.to_string_lossy()
+            );
+
+            platform::create_dir(dir, &parent_dirs.permissions)?;
+            platform::set_owner(dir, parent_dirs.uid, parent_dirs.gid)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Path under the staging root where the file at the given (absolute) path
+/// is staged, e.g. `/etc/myapp.conf` is staged at `<root>/etc/myapp.conf`
+pub fn staging_path(staging_root: &Path, path: &Path) -> PathBuf {
+    let mut result = staging_root.to_owned();
+
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            result.push(name);
+        }
+    }
+
+    result
+}
+
+/// Canonicalize a path, returning a configuration error if it fails
+fn canonicalize(path: &Path) -> Result<PathBuf, Error> {
+    path.canonicalize().map_err(|e| {
+        err!(
+            Config,
+            "error canonicalizing path: {} ({})",
+            path.to_string_lossy(),
+            e
+        )
+    })
+}
+
+/// Canonicalize the nearest existing ancestor of a path which may not exist
+/// yet, appending the missing components (which can't be `.` or `..`)
+fn canonicalize_existing_ancestor(path: &Path) -> Result<PathBuf, Error> {
+    let ancestor = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or_else(|| err!(Config, "bad path: {}", path.to_string_lossy()))?;
+
+    let missing = path.strip_prefix(ancestor).unwrap();
+
+    ensure!(
+        missing
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))),
+        Config,
+        "non-canonical path: {}",
+        path.to_string_lossy()
+    );
+
+    Ok(canonicalize(ancestor)?.join(missing))
+}
+
+/// Ensure the file at a target's path (if any) is a regular file rather than
+/// e.g. a FIFO, socket, or device node, unless `allow_special_file` is set.
+/// Directories are always refused: placing a file renames it over the target
+/// path, which can't replace a directory. Symlinks are refused when the file
+/// is placed.
+fn ensure_regular_file(path: &Path, allow_special_file: bool) -> Result<(), Error> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.is_dir() => fail!(
+            Config,
+            "target is a directory: {} (only files can be placed)",
+            path.to_string_lossy()
+        ),
+        Ok(ref metadata) if is_special_file(metadata.file_type()) && !allow_special_file => fail!(
+            Config,
+            "not a regular file: {} (set allow_special_file to replace it)",
+            path.to_string_lossy()
+        ),
+        Ok(_) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Is this neither a regular file nor a symlink?
+fn is_special_file(file_type: fs::FileType) -> bool {
+    !file_type.is_file() && !file_type.is_symlink()
+}
+
+/// Parse POSIX permissions from an octal string, rejecting setuid/setgid/sticky
+/// bits and world-writable modes unless `allow_dangerous_mode` is set
+pub fn parse_mode(mode_str: &str, allow_dangerous_mode: bool) -> Result<Permissions, Error> {
+    let mode = u32::from_str_radix(mode_str, 8)
+        .map_err(|e| err!(Config, "bad mode: {} ({:?})", mode_str, e))?;
+
+    ensure!(mode <= 0o7777, Config, "bad mode: {} (too large)", mode_str);
+
+    if !allow_dangerous_mode {
+        ensure!(
+            mode & 0o7000 == 0,
+            Config,
+            "mode {} sets setuid/setgid/sticky bits (set allow_dangerous_mode to permit this)",
+            mode_str
+        );
+
+        ensure!(
+            mode & 0o002 == 0,
+            Config,
+            "mode {} is world-writable (set allow_dangerous_mode to permit this)",
+            mode_str
+        );
+    }
+
+    platform::permissions_from_mode(mode)
+}
+
+/// Ensure the (already parsed) mode of a secret file grants no permissions
+/// to anyone but its owner
+fn ensure_secret_mode(mode_str: &str) -> Result<(), Error> {
+    let mode = u32::from_str_radix(mode_str, 8)
+        .map_err(|e| err!(Config, "bad mode: {} ({:?})", mode_str, e))?;
+
+    ensure!(
+        mode & 0o077 == 0,
+        Config,
+        "mode {} is too permissive for a secret (group/other permissions must be 0)",
+        mode_str
+    );
+
+    Ok(())
+}
+
+/// Normalize a Content-Type for comparison: its media type (without any
+/// parameters such as `charset`) in lowercase
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
 }
 
 // Process hook configurations and convert them into `Hook` structs
 fn process_hook_configs(
     hooks: &Option<BTreeMap<PathBuf, HookConfig>>,
+    owner: (uid_t, gid_t),
     cache: &mut UsersCache,
 ) -> Result<Vec<Hook>, Error> {
     let mut result = vec![];
 
     if let Some(ref hooks) = *hooks {
         for (path, hook_config) in hooks {
-            result.push(Hook::new(path, hook_config, cache)?);
+            result.push(Hook::new(path, hook_config, owner, cache)?);
         }
     }
 
     Ok(result)
 }
 
-/// "Safe" wrapper for chown
-// TODO: find (or create) a crate to supply this, or get stable Rust to
-#[allow(unsafe_code)]
-fn chown(path: &Path, uid: uid_t, gid: gid_t) -> Result<(), Error> {
-    let path_osstr: &OsStr = path.as_ref();
-    let path_cstring = CString::new(path_osstr.as_bytes()).unwrap();
-    let ret = unsafe { libc::chown(path_cstring.as_ptr(), uid, gid) };
+#[cfg(test)]
+mod tests {
+    use super::{ensure_regular_file, ensure_secret_mode, media_type, parse_mode, staging_path};
+    use std::env;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+
+    #[test]
+    fn test_ensure_regular_file() {
+        let dir = env::temp_dir();
+
+        // Directories can't be replaced, even with `allow_special_file`
+        assert!(ensure_regular_file(&dir, false).is_err());
+        assert!(ensure_regular_file(&dir, true).is_err());
+        assert!(ensure_regular_file(&dir.join("placer-nonexistent-target"), false).is_ok());
+
+        // Other special files can be
+        let socket_path = dir.join(format!("placer-special-target-{}", std::process::id()));
+        let _ = fs::remove_file(&socket_path);
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        assert!(ensure_regular_file(&socket_path, false).is_err());
+        assert!(ensure_regular_file(&socket_path, true).is_ok());
+        fs::remove_file(&socket_path).unwrap();
+    }
 
-    if ret == 0 {
-        Ok(())
-    } else {
-        Err(err!(Io, "chown failed: {}", errno::errno()))
+    #[test]
+    fn test_staging_path() {
+        assert_eq!(
+            staging_path(
+                Path::new("/var/lib/placer/staging"),
+                Path::new("/etc/myapp/config.toml")
+            ),
+            Path::new("/var/lib/placer/staging/etc/myapp/config.toml")
+        );
+    }
+
+    #[test]
+    fn test_ensure_secret_mode() {
+        assert!(ensure_secret_mode("600").is_ok());
+        assert!(ensure_secret_mode("0400").is_ok());
+        assert!(ensure_secret_mode("640").is_err());
+        assert!(ensure_secret_mode("604").is_err());
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type("text/plain; charset=utf-8"), "text/plain");
+        assert_eq!(media_type(" Text/Plain "), "text/plain");
+        assert_ne!(media_type("application/octet-stream"), "text/plain");
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert!(parse_mode("644", false).is_ok());
+        assert!(parse_mode("4755", false).is_err());
+        assert!(parse_mode("1755", false).is_err());
+        assert!(parse_mode("777", false).is_err());
+        assert!(parse_mode("4755", true).is_ok());
+        assert!(parse_mode("17777", true).is_err());
     }
 }
@@ -0,0 +1,230 @@
+//! Limits on how fast sources may deliver packs, and how many packs may be
+//! queued for verification, so a misbehaving source can't starve the daemon
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvError, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Create a bounded queue of packs waiting to be verified. Unlike
+/// `mpsc::sync_channel`, the oldest pack is dropped when the queue is full, so
+/// the most recently delivered pack always gets through.
+pub fn queue<T>(capacity: usize) -> (QueueSender<T>, QueueReceiver<T>) {
+    let queue = Arc::new(Queue {
+        state: Mutex::new(QueueState {
+            items: VecDeque::new(),
+            sender_dropped: false,
+            receiver_dropped: false,
+        }),
+        pushed: Condvar::new(),
+        capacity: capacity.max(1),
+    });
+
+    (QueueSender(Arc::clone(&queue)), QueueReceiver(queue))
+}
+
+/// Queue shared by a `QueueSender` and `QueueReceiver`
+#[derive(Debug)]
+struct Queue<T> {
+    /// Queued items, and whether either end was dropped
+    state: Mutex<QueueState<T>>,
+
+    /// Signaled whenever an item is pushed (or the sender is dropped)
+    pushed: Condvar,
+
+    /// Maximum number of queued items
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct QueueState<T> {
+    items: VecDeque<T>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+/// Sending half of a queue
+#[derive(Debug)]
+pub struct QueueSender<T>(Arc<Queue<T>>);
+
+impl<T> QueueSender<T> {
+    /// Add an item to the queue, returning the oldest item if it had to be
+    /// dropped to make room, or the item itself if the receiver is gone
+    pub fn send(&self, item: T) -> Result<Option<T>, T> {
+        let mut state = self.0.state.lock().unwrap();
+
+        if state.receiver_dropped {
+            return Err(item);
+        }
+
+        let dropped = if state.items.len() >= self.0.capacity {
+            state.items.pop_front()
+        } else {
+            None
+        };
+
+        state.items.push_back(item);
+        self.0.pushed.notify_one();
+        Ok(dropped)
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().sender_dropped = true;
+        self.0.pushed.notify_all();
+    }
+}
+
+/// Receiving half of a queue
+#[derive(Debug)]
+pub struct QueueReceiver<T>(Arc<Queue<T>>);
+
+impl<T> QueueReceiver<T> {
+    /// Wait for the next item. Items queued before the sender was dropped
+    /// are still received.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.0.state.lock().unwrap();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Ok(item);
+            }
+
+            if state.sender_dropped {
+                return Err(RecvError);
+            }
+
+            state = self.0.pushed.wait(state).unwrap();
+        }
+    }
+
+    /// Wait up to the given timeout for the next item
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.0.state.lock().unwrap();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                return Ok(item);
+            }
+
+            if state.sender_dropped {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining == Duration::from_secs(0) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            state = self.0.pushed.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().receiver_dropped = true;
+    }
+}
+
+/// Limit on how often a source may deliver packs
+#[derive(Debug)]
+pub struct RateLimit {
+    /// Minimum interval between deliveries
+    interval: Duration,
+
+    /// When the next delivery is allowed
+    next_allowed: Option<Instant>,
+}
+
+impl RateLimit {
+    /// Allow the given number of deliveries per minute (at least one)
+    pub fn per_minute(deliveries: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / deliveries.max(1),
+            next_allowed: None,
+        }
+    }
+
+    /// Get how long to wait before the next delivery is allowed, reserving
+    /// the delivery slot after that
+    pub fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let allowed_at = self.next_allowed.map_or(now, |next| next.max(now));
+
+        self.next_allowed = Some(allowed_at + self.interval);
+        allowed_at - now
+    }
+
+    /// Wait until the next delivery is allowed, returning how long we waited
+    pub fn wait(&mut self) -> Duration {
+        let delay = self.reserve();
+
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{queue, RateLimit};
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_queue_drops_oldest() {
+        let (sender, receiver) = queue(2);
+
+        assert_eq!(sender.send(1), Ok(None));
+        assert_eq!(sender.send(2), Ok(None));
+        assert_eq!(sender.send(3), Ok(Some(1)));
+
+        // The newest item always gets through, even after the sender is gone
+        drop(sender);
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(3));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_queue_wait() {
+        let (sender, receiver) = queue(1);
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let waiter = thread::spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(sender.send("pack"), Ok(None));
+        assert_eq!(waiter.join().unwrap(), Ok("pack"));
+
+        // The receiver is gone, so the item is handed back
+        assert_eq!(sender.send("another pack"), Err("another pack"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::per_minute(60);
+
+        assert_eq!(limit.reserve(), Duration::from_secs(0));
+
+        let delay = limit.reserve();
+        assert!(delay > Duration::from_millis(900) && delay <= Duration::from_secs(1));
+
+        let delay = limit.reserve();
+        assert!(delay > Duration::from_millis(1900) && delay <= Duration::from_secs(2));
+    }
+}